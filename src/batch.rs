@@ -0,0 +1,290 @@
+use crate::cli::{Options, InputSelection};
+use crate::image::{Image, FitMode, ResampleFilter};
+use crate::pixel_format;
+use crate::png_io::{load_png, save_png};
+use crate::processor::{Buffers, Pipeline};
+use crate::y4m::{Y4mReader, Y4mWriter, Y4mHeader};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Runs the input mode and the processor chain on a stream of frames, the same way the window does.
+struct FrameProcessor
+{
+    /// A copy of the pipeline of the options, since an automatic palette changes from frame to frame.
+    pipeline: Pipeline,
+    size: Option<(usize, usize)>,
+    fit_mode: FitMode,
+    frame: Option<Image>,
+    /// The output and the scratch buffers of the processor chain.
+    buffers: Option<(Image, Buffers)>,
+}
+
+impl FrameProcessor
+{
+    fn new(options: &Options) -> Self
+    {
+        Self
+        {
+            pipeline: options.pipeline.clone(),
+            size: options.size,
+            fit_mode: options.fit_mode,
+            frame: None,
+            buffers: None,
+        }
+    }
+
+    fn process(&mut self, input: Image) -> &Image
+    {
+        let input = match self.size
+        {
+            Some((width, height)) => input.fit_linear(width, height, self.fit_mode, ResampleFilter::Bilinear),
+            None => input,
+        };
+        // blending needs the previous frame, so a change of size starts over
+        let frame = match self.frame.take()
+        {
+            Some(mut frame) if (frame.width, frame.height) == (input.width, input.height) =>
+            {
+                self.pipeline.input_mode.blend(&mut frame, &input);
+                frame
+            }
+            _ => input,
+        };
+        let (mut target, mut buffers) = match self.buffers.take()
+        {
+            Some((target, buffers)) if (target.width, target.height) == (frame.width, frame.height) => (target, buffers),
+            _ => (frame.clone(), Buffers::new(frame.width, frame.height)),
+        };
+        self.pipeline.update_palette(&frame);
+        self.pipeline.run(&frame, &mut target, &mut buffers);
+        self.frame = Some(frame);
+        &self.buffers.insert((target, buffers)).0
+    }
+}
+
+/// Processes the input given in `options` without opening a window and writes the results to the output directory.
+/// 
+/// Returns the number of frames written.
+pub fn run(options: &Options) -> std::io::Result<usize>
+{
+    let mut frames = FrameProcessor::new(options);
+    let output_dir = &options.output_dir;
+    match &options.input
+    {
+        InputSelection::Image(path) =>
+        {
+            let image = load_png(path)?;
+            let output_path = output_path(path, output_dir, processed_name(path, "png"))?;
+            save_png(frames.process(image), &output_path)?;
+            Ok(1)
+        }
+        InputSelection::Sequence{directory, ..} =>
+        {
+            let paths = crate::source::still::list_numbered_pngs(directory)?;
+            for (i, path) in paths.iter().enumerate()
+            {
+                let output_path = output_path(path, output_dir, processed_name(path, "png"))?;
+                let image = load_png(path)?;
+                save_png(frames.process(image), &output_path)?;
+                report_progress(i + 1, paths.len());
+            }
+            Ok(paths.len())
+        }
+        InputSelection::Y4m(path) =>
+        {
+            let output_path = output_path(path, output_dir, processed_name(path, "y4m"))?;
+            let mut reader = Y4mReader::new(std::io::BufReader::new(std::fs::File::open(path)?))?;
+            let mut writer: Option<Y4mWriter<std::io::BufWriter<std::fs::File>>> = None;
+            let mut count = 0;
+            while let Some(image) = reader.read_frame()?
+            {
+                let processed = frames.process(image);
+                if writer.is_none()
+                {
+                    let header = Y4mHeader{width: processed.width, height: processed.height, ..reader.header().clone()};
+                    writer = Some(Y4mWriter::new(std::io::BufWriter::new(std::fs::File::create(&output_path)?), header)?);
+                }
+                writer.as_mut().unwrap().write_frame(processed)?;
+                count += 1;
+                report_progress(count, 0);
+            }
+            if let Some(mut writer) = writer
+            {
+                writer.flush()?;
+            }
+            Ok(count)
+        }
+        InputSelection::Raw{path, format, conversion, frame_size, ..} =>
+        {
+            let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+            let mut buffer = vec![0; format.frame_size(frame_size.0, frame_size.1)];
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let mut count = 0;
+            loop
+            {
+                match file.read_exact(&mut buffer)
+                {
+                    Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    result => result?,
+                }
+                let image = pixel_format::convert_to_image(&buffer, frame_size.0, frame_size.1, *format, *conversion)?;
+                count += 1;
+                let output_path = output_path(path, output_dir, PathBuf::from(format!("{}_{:06}.png", stem, count)))?;
+                save_png(frames.process(image), &output_path)?;
+                report_progress(count, 0);
+            }
+            Ok(count)
+        }
+        InputSelection::Camera{..} | InputSelection::Pattern{..} =>
+        {
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "batch mode needs an image, video, raw dump or directory as input"))
+        }
+    }
+}
+
+/// What batch mode adds to the names of the files it writes.
+pub const PROCESSED_SUFFIX: &str = "_processed";
+
+/// The file name of `path` with `PROCESSED_SUFFIX` added to the stem and its extension replaced by `extension`.
+fn processed_name(path: &Path, extension: &str) -> PathBuf
+{
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    PathBuf::from(format!("{}{}.{}", stem, PROCESSED_SUFFIX, extension))
+}
+
+/// Where the output `name` goes in `output_dir`, or an error if that is the `input` file itself,
+/// which would be overwritten while it's still being read.
+fn output_path(input: &Path, output_dir: &Path, name: PathBuf) -> std::io::Result<PathBuf>
+{
+    let output = output_dir.canonicalize()?.join(name);
+    if input.canonicalize()? == output
+    {
+        let message = format!("the output {} would overwrite the input", output.display());
+        return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message));
+    }
+    Ok(output)
+}
+
+/// Prints a line every 100 frames. `total` is 0 if the number of frames isn't known in advance.
+fn report_progress(done: usize, total: usize)
+{
+    if done.is_multiple_of(100)
+    {
+        if total > 0
+        {
+            println!("{} / {} frames", done, total);
+        }
+        else
+        {
+            println!("{} frames", done);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::temporary_directory;
+    use crate::cli::{parse_args, Command};
+    use crate::pixel::Rgba8;
+    use crate::y4m::Chroma;
+
+    fn gradient(width: usize, height: usize) -> Image
+    {
+        let mut image = Image::new(width, height, Rgba8::BLACK);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                image[(x, y)] = Rgba8::rgb((x * 255 / width) as u8, (y * 255 / height) as u8, 128);
+            }
+        }
+        image
+    }
+
+    /// Runs batch mode on `input`, writing to the directory the input is in.
+    fn run_in_place(input: &Path) -> std::io::Result<usize>
+    {
+        let directory = if input.is_dir() { input } else { input.parent().unwrap() };
+        let args = ["--batch", "-i", input.to_str().unwrap(), "-o", directory.to_str().unwrap(), "--processors", "Threshold"];
+        match parse_args(args.iter().map(|a| a.to_string()))
+        {
+            Ok(Command::Run(options)) => run(&options),
+            _ => panic!("could not parse the arguments"),
+        }
+    }
+
+    #[test]
+    fn image_in_its_own_directory_is_kept()
+    {
+        let directory = temporary_directory("batch_image");
+        let input = directory.join("still.png");
+        save_png(&gradient(16, 8), &input).unwrap();
+        let before = std::fs::read(&input).unwrap();
+
+        assert_eq!(run_in_place(&input).unwrap(), 1);
+        assert_eq!(std::fs::read(&input).unwrap(), before);
+        assert!(directory.join("still_processed.png").is_file());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn sequence_in_its_own_directory_is_kept()
+    {
+        let directory = temporary_directory("batch_sequence");
+        let inputs = (1..=3).map(|i| directory.join(format!("frame_{:04}.png", i))).collect::<Vec<_>>();
+        for input in &inputs
+        {
+            save_png(&gradient(16, 8), input).unwrap();
+        }
+        let before = inputs.iter().map(|input| std::fs::read(input).unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(run_in_place(&directory).unwrap(), 3);
+        for (input, before) in inputs.iter().zip(&before)
+        {
+            assert_eq!(&std::fs::read(input).unwrap(), before);
+            assert!(input.with_file_name(processed_name(input, "png")).is_file());
+        }
+
+        // the outputs of the first run are neither processed again nor played as frames
+        assert_eq!(run_in_place(&directory).unwrap(), 3);
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 6);
+        assert_eq!(crate::source::still::list_numbered_pngs(&directory).unwrap(), inputs);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn video_in_its_own_directory_is_kept()
+    {
+        let directory = temporary_directory("batch_video");
+        let input = directory.join("clip.y4m");
+        let header = Y4mHeader{width: 16, height: 8, frame_rate: (30, 1), chroma: Chroma::C444, full_range: false};
+        let mut writer = Y4mWriter::new(std::fs::File::create(&input).unwrap(), header).unwrap();
+        for _ in 0..3
+        {
+            writer.write_frame(&gradient(16, 8)).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        let before = std::fs::read(&input).unwrap();
+
+        assert_eq!(run_in_place(&input).unwrap(), 3);
+        assert_eq!(std::fs::read(&input).unwrap(), before);
+        assert!(directory.join("clip_processed.y4m").is_file());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn output_that_is_the_input_is_refused()
+    {
+        let directory = temporary_directory("batch_refused");
+        let input = directory.join("still.png");
+        save_png(&gradient(4, 4), &input).unwrap();
+
+        let error = output_path(&input, &directory, PathBuf::from("still.png")).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(output_path(&input, &directory, processed_name(&input, "png")).is_ok());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
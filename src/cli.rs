@@ -0,0 +1,627 @@
+use crate::image::{BorderMode, FitMode};
+use crate::pixel_format::{PixelFormat, YuvConversion, YuvMatrix, YuvRange};
+use crate::source::TestPattern;
+use crate::filter;
+use crate::palette;
+use crate::preset;
+use crate::processor::{InputMode, Pipeline, Stage};
+use crate::quantise::AutoPalette;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Applies image processing effects to a webcam feed, a video file, an image or a test pattern.
+
+USAGE:
+    webcam_image_processor [OPTIONS]
+    webcam_image_processor --batch --input <SOURCE> [OPTIONS]
+
+OPTIONS:
+    -p, --pixel-size <N>          Size of one image pixel on screen, 1 to 32 [default: 2].
+                                  Sets the processing resolution to 640/N wide at 16:9.
+    -c, --camera <INDEX>          Camera to capture from [default: 0]
+    -f, --fps <FPS>               Camera frame rate, and playback rate of image sequences and raw files [default: 30]
+        --capture-size <WxH>      Resolution requested from the camera [default: 640x480]
+        --size <WxH>              Processing resolution, overrides the one derived from --pixel-size.
+                                  In batch mode frames keep their own size unless this is given.
+        --display-size <WxH>      Window resolution in image pixels [default: the processing resolution]
+        --fit <MODE>              How frames are fitted when aspect ratios differ: crop, letterbox or stretch [default: crop]
+    -i, --input <SOURCE>          A PNG file, a .y4m video, a raw frame dump, a directory of numbered PNGs,
+                                  or pattern:<name> for a test pattern [default: the camera]
+        --raw-format <FORMAT>     Pixel format of a raw frame dump: yuyv, uyvy, nv12, i420, rgb24 or bgr24
+                                  [default: taken from the file extension]
+        --raw-size <WxH>          Frame size of a raw frame dump (required for raw input)
+        --yuv <MATRIX>            YUV matrix of a raw frame dump: bt601 or bt709 [default: bt601]
+        --yuv-range <RANGE>       YUV range of a raw frame dump: limited (16-235) or full (0-255) [default: limited]
+        --seed <N>                Seed of the noise test pattern [default: 0]
+        --processors <LIST>       Comma separated processor chain to start with, e.g. GaussianBlur,Sobel
+                                  [default: FloydSteinbergDitheringCustomPalette]
+        --input-mode <MODE>       normal, timeblend or denoising [default: normal]
+        --border <MODE>           What kernels read past the image edge: clamp, mirror, wrap, crop,
+                                  or constant followed by a colour, e.g. 'constant #ff8000' [default: clamp]
+        --palette <FILE>          Use the colours of a palette file: GIMP .gpl, Paint.NET .txt, .hex or JASC .pal
+        --palette-dir <DIR>       Palette files that [N] goes through in the window [default: palettes]
+        --auto-palette <SPEC>     Extract the palette from the frames: medianCut, kmeans or octree, optionally
+                                  followed by the number of colours and how fast it follows new frames
+                                  from 0 to 1, or locked, e.g. 'kmeans 16 0.05' [default: the fixed palette]
+        --preset <FILE>           Start with the processor chain, parameters, input mode and palette of a preset file.
+                                  --processors, --input-mode, --border, --palette and --auto-palette override the preset.
+    -j, --threads <N>             Number of threads the filters run on. The result is the same with any number
+                                  [default: one per CPU core]
+    -o, --output-dir <DIR>        Where saved images, recordings and batch results go [default: the current directory]
+        --batch                   Process the input without opening a window and write the results to the
+                                  output directory. Images and image sequences produce PNGs with the same
+                                  names plus _processed, .y4m videos produce a _processed.y4m video and raw
+                                  dumps produce numbered PNGs.
+    -h, --help                    Print this help and exit
+
+TEST PATTERNS:
+    bars, hgradient, vgradient, zoneplate, checkerboard, edge, noise
+";
+
+#[derive(Clone)]
+pub enum InputSelection
+{
+    /// `fallback` shows colour bars if the camera can't be opened, which only the camera picked by default does.
+    Camera{index: u32, fallback: bool},
+    Image(PathBuf),
+    Sequence{directory: PathBuf, fps: f64},
+    Y4m(PathBuf),
+    Raw{path: PathBuf, format: PixelFormat, conversion: YuvConversion, frame_size: (usize, usize), fps: f64},
+    Pattern{pattern: TestPattern, seed: u64},
+}
+
+pub struct Options
+{
+    pub pixel_size: usize,
+    pub fps: f64,
+    pub capture_size: (usize, usize),
+    /// Processing resolution given with `--size`.
+    pub size: Option<(usize, usize)>,
+    display_size: Option<(usize, usize)>,
+    pub fit_mode: FitMode,
+    pub input: InputSelection,
+    pub pipeline: Pipeline,
+    /// The file given with `--preset`.
+    pub preset_path: Option<PathBuf>,
+    /// The file given with `--palette`.
+    pub palette_path: Option<PathBuf>,
+    /// What the command line sets in `pipeline`, which also goes over the preset.
+    overrides: PipelineOverrides,
+    /// The directory of palette files the window goes through.
+    pub palette_dir: PathBuf,
+    pub output_dir: PathBuf,
+    pub batch: bool,
+    /// Size of the filter thread pool given with `--threads`.
+    pub threads: Option<usize>,
+}
+
+impl Options
+{
+    /// Reads the preset and the palette file that the command line names into `pipeline`.
+    /// `parse_args` doesn't touch them, so call this before using the options.
+    pub fn load_files(&mut self) -> Result<(), String>
+    {
+        if let Some(path) = &self.preset_path
+        {
+            self.pipeline = preset::load(path).map_err(|e| format!("could not load preset {}: {}", path.display(), e))?;
+            self.overrides.apply(&mut self.pipeline);
+        }
+        if let Some(path) = &self.palette_path
+        {
+            self.pipeline.palette = palette::load(path).map_err(|e| format!("could not load palette {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    /// The resolution the processors run at in the window.
+    pub fn processing_size(&self) -> (usize, usize)
+    {
+        self.size.unwrap_or((640 / self.pixel_size, 640 / self.pixel_size * 9 / 16))
+    }
+
+    /// The window resolution in image pixels.
+    pub fn display_size(&self) -> (usize, usize)
+    {
+        self.display_size.unwrap_or(self.processing_size())
+    }
+}
+
+/// The parts of the pipeline that flags set, whether or not there is a preset.
+#[derive(Default)]
+struct PipelineOverrides
+{
+    stages: Option<Vec<Stage>>,
+    input_mode: Option<InputMode>,
+    border: Option<BorderMode>,
+    auto_palette: Option<AutoPalette>,
+    /// `--palette` was given, which replaces an automatic palette of the preset unless `--auto-palette` was given too.
+    fixed_palette: bool,
+}
+
+impl PipelineOverrides
+{
+    fn apply(&self, pipeline: &mut Pipeline)
+    {
+        if let Some(stages) = &self.stages
+        {
+            pipeline.stages = stages.clone();
+        }
+        if let Some(input_mode) = self.input_mode
+        {
+            pipeline.input_mode = input_mode;
+        }
+        if let Some(border) = self.border
+        {
+            pipeline.border = border;
+        }
+        if self.fixed_palette
+        {
+            pipeline.auto_palette = None;
+        }
+        if self.auto_palette.is_some()
+        {
+            pipeline.auto_palette = self.auto_palette.clone();
+        }
+    }
+}
+
+pub enum Command
+{
+    Run(Box<Options>),
+    Help,
+}
+
+/// Parses the command line arguments, without the program name.
+/// Files named by the arguments aren't read, see `Options::load_files`.
+pub fn parse_args<I>(args: I) -> Result<Command, String> where I: IntoIterator<Item = String>
+{
+    let mut pixel_size = 2;
+    let mut camera_index = None;
+    let mut fps = 30.0;
+    let mut capture_size = (640, 480);
+    let mut size = None;
+    let mut display_size = None;
+    let mut fit_mode = FitMode::Crop;
+    let mut input = None;
+    let mut raw_format = None;
+    let mut raw_size = None;
+    let mut yuv_matrix = None;
+    let mut yuv_range = None;
+    let mut seed = 0;
+    let mut overrides = PipelineOverrides::default();
+    let mut palette_path = None;
+    let mut palette_dir = PathBuf::from("palettes");
+    let mut preset_path = None;
+    let mut output_dir = PathBuf::from(".");
+    let mut batch = false;
+    let mut threads = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next()
+    {
+        // accept both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match arg.split_once('=')
+        {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        if flag == "-h" || flag == "--help"
+        {
+            return Ok(Command::Help);
+        }
+        let mut value = ||
+        {
+            inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", flag))
+        };
+        match flag.as_str()
+        {
+            "-p" | "--pixel-size" =>
+            {
+                pixel_size = parse_number(&flag, &value()?)?;
+                if !(1..=32).contains(&pixel_size)
+                {
+                    return Err(format!("--pixel-size must be between 1 and 32, got {}", pixel_size));
+                }
+            }
+            "-c" | "--camera" => camera_index = Some(parse_number(&flag, &value()?)?),
+            "-f" | "--fps" =>
+            {
+                fps = parse_number(&flag, &value()?)?;
+                if !(fps > 0.0 && fps <= 240.0)
+                {
+                    return Err(format!("--fps must be between 0 and 240, got {}", fps));
+                }
+            }
+            "--capture-size" => capture_size = parse_size(&flag, &value()?)?,
+            "--size" => size = Some(parse_size(&flag, &value()?)?),
+            "--display-size" => display_size = Some(parse_size(&flag, &value()?)?),
+            "--fit" =>
+            {
+                fit_mode = match value()?.to_ascii_lowercase().as_str()
+                {
+                    "crop" => FitMode::Crop,
+                    "letterbox" => FitMode::Letterbox,
+                    "stretch" => FitMode::Stretch,
+                    other => return Err(format!("unknown fit mode {}, expected crop, letterbox or stretch", other)),
+                }
+            }
+            "-i" | "--input" => input = Some(value()?),
+            "--raw-format" =>
+            {
+                let name = value()?;
+                raw_format = Some(PixelFormat::from_name(&name).ok_or_else(|| format!("unknown raw pixel format {}, expected yuyv, uyvy, nv12, i420, rgb24 or bgr24", name))?);
+            }
+            "--raw-size" => raw_size = Some(parse_size(&flag, &value()?)?),
+            "--yuv" =>
+            {
+                let name = value()?;
+                yuv_matrix = Some(YuvMatrix::from_name(&name).ok_or_else(|| format!("unknown YUV matrix {}, expected bt601 or bt709", name))?);
+            }
+            "--yuv-range" =>
+            {
+                let name = value()?;
+                yuv_range = Some(YuvRange::from_name(&name).ok_or_else(|| format!("unknown YUV range {}, expected limited or full", name))?);
+            }
+            "--seed" => seed = parse_number(&flag, &value()?)?,
+            "--processors" => overrides.stages = Some(parse_processors(&value()?)?),
+            "--input-mode" =>
+            {
+                let name = value()?;
+                overrides.input_mode = Some(InputMode::from_name(&name).ok_or_else(|| format!("unknown input mode {}, expected normal, timeblend or denoising", name))?);
+            }
+            "--border" =>
+            {
+                let name = value()?;
+                overrides.border = Some(BorderMode::from_name(&name).ok_or_else(|| format!("unknown border mode {}, expected clamp, mirror, wrap, crop or constant #rrggbb", name))?);
+            }
+            "--palette" =>
+            {
+                palette_path = Some(PathBuf::from(value()?));
+                overrides.fixed_palette = true;
+            }
+            "--palette-dir" => palette_dir = PathBuf::from(value()?),
+            "--auto-palette" =>
+            {
+                let spec = value()?;
+                overrides.auto_palette = Some(AutoPalette::from_name(&spec).ok_or_else(|| format!("expected a method, colours and adaptation like 'kmeans 16 0.05', got {}", spec))?);
+            }
+            "--preset" => preset_path = Some(PathBuf::from(value()?)),
+            "-o" | "--output-dir" => output_dir = PathBuf::from(value()?),
+            "--batch" => batch = true,
+            "-j" | "--threads" =>
+            {
+                let count = parse_number(&flag, &value()?)?;
+                if count == 0
+                {
+                    return Err("--threads must be at least 1".to_string());
+                }
+                threads = Some(count);
+            }
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+
+    let mut input = match input
+    {
+        None => InputSelection::Camera{index: camera_index.unwrap_or(0), fallback: camera_index.is_none()},
+        Some(input) => parse_input(&input, fps, seed, raw_format, raw_size)?,
+    };
+    if let InputSelection::Raw{conversion, ..} = &mut input
+    {
+        conversion.matrix = yuv_matrix.unwrap_or(conversion.matrix);
+        conversion.range = yuv_range.unwrap_or(conversion.range);
+    }
+    else if yuv_matrix.is_some() || yuv_range.is_some()
+    {
+        return Err("--yuv and --yuv-range only apply to raw input".to_string());
+    }
+    let mut pipeline = Pipeline::default();
+    overrides.apply(&mut pipeline);
+    if batch && matches!(input, InputSelection::Camera{..} | InputSelection::Pattern{..})
+    {
+        return Err("--batch needs an image, video, raw dump or directory as --input".to_string());
+    }
+    Ok(Command::Run(Box::new(Options
+    {
+        pixel_size,
+        fps,
+        capture_size,
+        size,
+        display_size,
+        fit_mode,
+        input,
+        pipeline,
+        preset_path,
+        palette_path,
+        overrides,
+        palette_dir,
+        output_dir,
+        batch,
+        threads,
+    })))
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String>
+{
+    value.trim().parse().map_err(|_| format!("{} expects a number, got {}", flag, value))
+}
+
+/// Parses sizes like `1280x720`.
+fn parse_size(flag: &str, value: &str) -> Result<(usize, usize), String>
+{
+    let size = value.trim().split_once(['x', 'X']).and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)));
+    match size
+    {
+        Some((w, h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err(format!("{} expects a size like 640x480, got {}", flag, value)),
+    }
+}
+
+fn parse_processors(list: &str) -> Result<Vec<Stage>, String>
+{
+    let processors = list.split(',')
+        .filter(|name| !name.trim().is_empty())
+        .map(|name| filter::find(name).map(Stage::new).ok_or_else(|| format!("unknown processor {}", name.trim())))
+        .collect::<Result<Vec<_>, _>>()?;
+    if processors.is_empty()
+    {
+        return Err("--processors needs at least one processor".to_string());
+    }
+    Ok(processors)
+}
+
+fn parse_input(input: &str, fps: f64, seed: u64, raw_format: Option<PixelFormat>, raw_size: Option<(usize, usize)>) -> Result<InputSelection, String>
+{
+    if let Some(name) = input.strip_prefix("pattern:")
+    {
+        let pattern = TestPattern::from_name(name).ok_or_else(||
+        {
+            let names = TestPattern::ALL.iter().map(|p| p.name()).collect::<Vec<_>>().join(", ");
+            format!("unknown test pattern {}, expected one of {}", name, names)
+        })?;
+        return Ok(InputSelection::Pattern{pattern, seed});
+    }
+
+    let path = PathBuf::from(input);
+    if path.is_dir()
+    {
+        return Ok(InputSelection::Sequence{directory: path, fps});
+    }
+    if !path.is_file()
+    {
+        return Err(format!("input {} does not exist", path.display()));
+    }
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("y4m"))
+    {
+        return Ok(InputSelection::Y4m(path));
+    }
+    if let Some(format) = raw_format.or_else(|| raw_pixel_format(&path))
+    {
+        let frame_size = raw_size.ok_or_else(|| format!("raw input {} needs --raw-size", path.display()))?;
+        return Ok(InputSelection::Raw{path, format, conversion: YuvConversion::default(), frame_size, fps});
+    }
+    Ok(InputSelection::Image(path))
+}
+
+/// Picks the raw pixel format from a file extension like `.nv12`.
+fn raw_pixel_format(path: &std::path::Path) -> Option<PixelFormat>
+{
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str()
+    {
+        "rgb" => Some(PixelFormat::Rgb24),
+        "bgr" => Some(PixelFormat::Bgr24),
+        "yuv" => Some(PixelFormat::I420),
+        _ => PixelFormat::from_name(&extension),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::pixel::Rgba8;
+    use crate::test_support::temporary_directory;
+
+    fn parse(args: &[&str]) -> Result<Options, String>
+    {
+        match parse_args(args.iter().map(|a| a.to_string()))?
+        {
+            Command::Run(options) => Ok(*options),
+            Command::Help => Err("help".to_string()),
+        }
+    }
+
+    fn error(args: &[&str]) -> String
+    {
+        parse(args).err().expect("the arguments should be refused")
+    }
+
+    fn stage_names(pipeline: &Pipeline) -> Vec<&'static str>
+    {
+        pipeline.stages.iter().map(|stage| stage.filter.name()).collect()
+    }
+
+    /// A directory with an image, a video, a raw dump, a preset and a palette to point flags at.
+    fn input_files(name: &str) -> PathBuf
+    {
+        let directory = temporary_directory(name);
+        for file in ["still.png", "clip.y4m", "dump.nv12", "dump.bin"]
+        {
+            std::fs::write(directory.join(file), "").unwrap();
+        }
+        std::fs::write(directory.join("look.preset"), "input_mode = TimeBlend\nborder = mirror\nauto_palette = octree 8\nstage = Sobel\nstage = Threshold\n").unwrap();
+        std::fs::write(directory.join("colours.hex"), "ff0000\n00ff00\n").unwrap();
+        directory
+    }
+
+    fn path(directory: &std::path::Path, file: &str) -> String
+    {
+        directory.join(file).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn no_arguments_give_the_defaults()
+    {
+        let options = parse(&[]).unwrap();
+        assert!(matches!(options.input, InputSelection::Camera{index: 0, fallback: true}));
+        assert_eq!((options.pixel_size, options.fps, options.capture_size), (2, 30.0, (640, 480)));
+        assert_eq!((options.processing_size(), options.display_size()), ((320, 180), (320, 180)));
+        assert_eq!(options.fit_mode, FitMode::Crop);
+        assert_eq!(stage_names(&options.pipeline), vec!["FloydSteinbergDitheringCustomPalette"]);
+        assert_eq!((options.preset_path, options.palette_path), (None, None));
+        assert_eq!((options.palette_dir, options.output_dir), (PathBuf::from("palettes"), PathBuf::from(".")));
+        assert_eq!((options.batch, options.threads), (false, None));
+    }
+
+    #[test]
+    fn help_wins_wherever_it_is()
+    {
+        for args in [&["-h"][..], &["--help"], &["--fps", "10", "--help"], &["--help", "--no-such-flag"]]
+        {
+            assert!(matches!(parse_args(args.iter().map(|a| a.to_string())), Ok(Command::Help)), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn display_and_processing_flags()
+    {
+        let options = parse(&["-p", "4", "-c", "2", "-f", "24", "--capture-size", "1280x720", "--display-size", "640X360", "--fit", "Letterbox"]).unwrap();
+        assert!(matches!(options.input, InputSelection::Camera{index: 2, fallback: false}));
+        assert_eq!((options.pixel_size, options.fps, options.capture_size), (4, 24.0, (1280, 720)));
+        assert_eq!((options.processing_size(), options.display_size()), ((160, 90), (640, 360)));
+        assert_eq!(options.fit_mode, FitMode::Letterbox);
+
+        let options = parse(&["--pixel-size=8", "--size=200x100", "--fit=stretch", "--threads=3", "-j", "5"]).unwrap();
+        assert_eq!((options.processing_size(), options.display_size()), ((200, 100), (200, 100)));
+        assert_eq!((options.fit_mode, options.threads), (FitMode::Stretch, Some(5)));
+    }
+
+    #[test]
+    fn pipeline_flags()
+    {
+        let options = parse(&["--processors", "GaussianBlur, sobel,,Threshold", "--input-mode", "denoising", "--border", "constant #ff8000",
+            "--auto-palette", "kmeans 4 locked", "--palette-dir", "mine", "--preset", "missing.preset", "--palette", "missing.gpl", "-o", "out"]).unwrap();
+        assert_eq!(stage_names(&options.pipeline), vec!["GaussianBlur", "Sobel", "Threshold"]);
+        assert_eq!(options.pipeline.input_mode, InputMode::Denoising);
+        assert_eq!(options.pipeline.border, BorderMode::Constant(Rgba8::rgb(255, 128, 0)));
+        assert_eq!(options.pipeline.auto_palette, AutoPalette::from_name("kmeans 4 locked"));
+        // the files are only named, not read
+        assert_eq!(options.preset_path, Some(PathBuf::from("missing.preset")));
+        assert_eq!(options.palette_path, Some(PathBuf::from("missing.gpl")));
+        assert_eq!((options.palette_dir, options.output_dir), (PathBuf::from("mine"), PathBuf::from("out")));
+    }
+
+    #[test]
+    fn inputs_are_told_apart()
+    {
+        let directory = input_files("cli_inputs");
+        assert!(matches!(parse(&["-i", &path(&directory, "still.png")]).unwrap().input, InputSelection::Image(_)));
+        assert!(matches!(parse(&["-i", &path(&directory, "clip.y4m")]).unwrap().input, InputSelection::Y4m(_)));
+        assert!(matches!(parse(&["--input", &directory.to_string_lossy(), "--fps", "12"]).unwrap().input, InputSelection::Sequence{fps, ..} if fps == 12.0));
+        assert!(matches!(parse(&["-i", "pattern:zoneplate", "--seed", "7"]).unwrap().input, InputSelection::Pattern{seed: 7, ..}));
+
+        let options = parse(&["-i", &path(&directory, "dump.nv12"), "--raw-size", "64x48"]).unwrap();
+        match options.input
+        {
+            InputSelection::Raw{format, conversion, frame_size, ..} =>
+            {
+                assert_eq!((format, conversion, frame_size), (PixelFormat::Nv12, YuvConversion::default(), (64, 48)));
+            }
+            _ => panic!("expected raw input"),
+        }
+        let options = parse(&["-i", &path(&directory, "dump.bin"), "--raw-format", "YUYV", "--raw-size", "64x48", "--yuv", "bt709", "--yuv-range", "full"]).unwrap();
+        match options.input
+        {
+            InputSelection::Raw{format, conversion, ..} =>
+            {
+                assert_eq!((format, conversion), (PixelFormat::Yuyv, YuvConversion{matrix: YuvMatrix::Bt709, range: YuvRange::Full}));
+            }
+            _ => panic!("expected raw input"),
+        }
+        let options = parse(&["--batch", "-i", &path(&directory, "still.png")]).unwrap();
+        assert!(options.batch);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn missing_values_are_refused()
+    {
+        for flag in ["-p", "--fps", "--size", "--input", "--processors", "--palette", "--auto-palette", "--preset", "-o", "--threads", "--yuv"]
+        {
+            assert_eq!(error(&[flag]), format!("{} needs a value", flag));
+        }
+    }
+
+    #[test]
+    fn invalid_values_are_refused()
+    {
+        assert_eq!(error(&["-p", "0"]), "--pixel-size must be between 1 and 32, got 0");
+        assert_eq!(error(&["--pixel-size", "33"]), "--pixel-size must be between 1 and 32, got 33");
+        assert_eq!(error(&["-p", "two"]), "-p expects a number, got two");
+        assert_eq!(error(&["--fps", "0"]), "--fps must be between 0 and 240, got 0");
+        assert_eq!(error(&["--camera", "-1"]), "--camera expects a number, got -1");
+        assert_eq!(error(&["--size", "640"]), "--size expects a size like 640x480, got 640");
+        assert_eq!(error(&["--display-size", "0x10"]), "--display-size expects a size like 640x480, got 0x10");
+        assert_eq!(error(&["--fit", "zoom"]), "unknown fit mode zoom, expected crop, letterbox or stretch");
+        assert_eq!(error(&["--raw-format", "rgb32"]), "unknown raw pixel format rgb32, expected yuyv, uyvy, nv12, i420, rgb24 or bgr24");
+        assert_eq!(error(&["--processors", "Blur"]), "unknown processor Blur");
+        assert_eq!(error(&["--processors", ","]), "--processors needs at least one processor");
+        assert_eq!(error(&["--input-mode", "fast"]), "unknown input mode fast, expected normal, timeblend or denoising");
+        assert_eq!(error(&["--border", "bounce"]), "unknown border mode bounce, expected clamp, mirror, wrap, crop or constant #rrggbb");
+        assert_eq!(error(&["--auto-palette", "kmeans 0"]), "expected a method, colours and adaptation like 'kmeans 16 0.05', got kmeans 0");
+        assert_eq!(error(&["--threads", "0"]), "--threads must be at least 1");
+        assert_eq!(error(&["--yuv", "bt2020"]), "unknown YUV matrix bt2020, expected bt601 or bt709");
+        assert_eq!(error(&["--yuv-range", "tv"]), "unknown YUV range tv, expected limited or full");
+        assert_eq!(error(&["-i", "pattern:stripes"]), "unknown test pattern stripes, expected one of bars, hgradient, vgradient, zoneplate, checkerboard, edge, noise");
+        assert_eq!(error(&["-i", "no/such/file.png"]), "input no/such/file.png does not exist");
+        assert_eq!(error(&["--verbose"]), "unknown argument --verbose");
+        assert_eq!(error(&["--fps=fast"]), "--fps expects a number, got fast");
+    }
+
+    #[test]
+    fn conflicting_options_are_refused()
+    {
+        let directory = input_files("cli_conflicts");
+        assert_eq!(error(&["--batch"]), "--batch needs an image, video, raw dump or directory as --input");
+        assert_eq!(error(&["--batch", "-i", "pattern:bars"]), "--batch needs an image, video, raw dump or directory as --input");
+        assert_eq!(error(&["--yuv", "bt709", "-i", &path(&directory, "still.png")]), "--yuv and --yuv-range only apply to raw input");
+        assert_eq!(error(&["--yuv-range", "full"]), "--yuv and --yuv-range only apply to raw input");
+        let raw = path(&directory, "dump.nv12");
+        assert_eq!(error(&["-i", &raw]), format!("raw input {} needs --raw-size", raw));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn flags_override_the_preset_and_palette_file()
+    {
+        let directory = input_files("cli_overrides");
+        let (preset, palette) = (path(&directory, "look.preset"), path(&directory, "colours.hex"));
+        let colours = vec![Rgba8::rgb(255, 0, 0), Rgba8::rgb(0, 255, 0)];
+
+        let mut options = parse(&["--preset", &preset]).unwrap();
+        options.load_files().unwrap();
+        assert_eq!(stage_names(&options.pipeline), vec!["Sobel", "Threshold"]);
+        assert_eq!((options.pipeline.input_mode, options.pipeline.border), (InputMode::TimeBlend, BorderMode::Mirror));
+        assert_eq!(options.pipeline.auto_palette, AutoPalette::from_name("octree 8"));
+
+        let mut options = parse(&["--preset", &preset, "--processors", "Emboss", "--input-mode", "normal", "--border", "wrap", "--palette", &palette]).unwrap();
+        options.load_files().unwrap();
+        assert_eq!(stage_names(&options.pipeline), vec!["Emboss"]);
+        assert_eq!((options.pipeline.input_mode, options.pipeline.border), (InputMode::Normal, BorderMode::Wrap));
+        // a palette file replaces the automatic palette of the preset
+        assert_eq!((&options.pipeline.palette, &options.pipeline.auto_palette), (&colours, &None));
+
+        // unless --auto-palette asks for one, which then wins
+        let mut options = parse(&["--preset", &preset, "--palette", &palette, "--auto-palette", "kmeans 3"]).unwrap();
+        options.load_files().unwrap();
+        assert_eq!(options.pipeline.palette, colours);
+        assert_eq!(options.pipeline.auto_palette, AutoPalette::from_name("kmeans 3"));
+
+        let mut options = parse(&["--palette", &path(&directory, "missing.gpl")]).unwrap();
+        assert!(options.load_files().unwrap_err().starts_with("could not load palette"));
+        let mut options = parse(&["--preset", &path(&directory, "missing.preset")]).unwrap();
+        assert!(options.load_files().unwrap_err().starts_with("could not load preset"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,527 @@
+use crate::pixel::{decode_srgb, Rgba8};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How the palette based processors decide which palette colour is nearest to a pixel.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DistanceMetric
+{
+    /// Straight distance between the sRGB values.
+    Rgb,
+    /// sRGB distance with the channels weighted 2, 4 and 3, a cheap stand-in for how sensitive the eye is to each.
+    WeightedRgb,
+    /// Distance between the linear-light values, which matches how colours mix.
+    LinearRgb,
+    /// Distance in [OKLab], which is close to how different colours look.
+    ///
+    /// [OKLab]: https://bottosson.github.io/posts/oklab/
+    OkLab,
+    /// The [CIEDE2000] colour difference in CIELAB, the most accurate and the slowest.
+    ///
+    /// [CIEDE2000]: https://en.wikipedia.org/wiki/Color_difference#CIEDE2000
+    Ciede2000,
+}
+
+impl DistanceMetric
+{
+    pub const ALL: [DistanceMetric; 5] = [DistanceMetric::Rgb, DistanceMetric::WeightedRgb, DistanceMetric::LinearRgb, DistanceMetric::OkLab, DistanceMetric::Ciede2000];
+
+    /// The names of `ALL`, in the same order.
+    pub const NAMES: [&'static str; 5] = ["Rgb", "WeightedRgb", "LinearRgb", "OkLab", "Ciede2000"];
+
+    /// Moves an sRGB colour, with channels from 0 to 255, into a space where the metric is the straight distance.
+    /// CIEDE2000 isn't a straight distance anywhere, so it gets CIELAB, which `ciede2000` takes.
+    fn transform(&self, [r, g, b]: [f32; 3]) -> [f32; 3]
+    {
+        let linear = || [r, g, b].map(|c| decode_srgb(c / 255.0));
+        match self
+        {
+            DistanceMetric::Rgb => [r, g, b],
+            DistanceMetric::WeightedRgb => [r * 2f32.sqrt(), g * 2.0, b * 3f32.sqrt()],
+            DistanceMetric::LinearRgb => linear(),
+            DistanceMetric::OkLab => linear_to_oklab(linear()),
+            DistanceMetric::Ciede2000 => linear_to_lab(linear()),
+        }
+    }
+}
+
+fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3]
+{
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+/// CIELAB with the D65 white point of sRGB.
+fn linear_to_lab([r, g, b]: [f32; 3]) -> [f32; 3]
+{
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+    let f = |t: f32| if t > 216.0 / 24389.0 {t.cbrt()} else {t * 841.0 / 108.0 + 4.0 / 29.0};
+    [116.0 * f(y) - 16.0, 500.0 * (f(x) - f(y)), 200.0 * (f(y) - f(z))]
+}
+
+/// The CIEDE2000 difference between two CIELAB colours, following Sharma, Wu and Dalal's
+/// [implementation notes](https://hajim.rochester.edu/ece/sites/gsharma/ciede2000/).
+pub fn ciede2000([l1, a1, b1]: [f32; 3], [l2, a2, b2]: [f32; 3]) -> f32
+{
+    let (l1, a1, b1, l2, a2, b2) = (l1 as f64, a1 as f64, b1 as f64, l2 as f64, a2 as f64, b2 as f64);
+    let pow7 = |v: f64| v.powi(7);
+    let mean_chroma = ((a1.hypot(b1)) + (a2.hypot(b2))) / 2.0;
+    let g = 0.5 * (1.0 - (pow7(mean_chroma) / (pow7(mean_chroma) + pow7(25.0))).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |b: f64, a: f64| if a == 0.0 && b == 0.0 {0.0} else {b.atan2(a).to_degrees().rem_euclid(360.0)};
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0
+    {
+        0.0
+    }
+    else
+    {
+        let d = h2 - h1;
+        if d > 180.0 {d - 360.0} else if d < -180.0 {d + 360.0} else {d}
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let mean_l = (l1 + l2) / 2.0;
+    let mean_c = (c1 + c2) / 2.0;
+    let mean_h = if c1 * c2 == 0.0
+    {
+        h1 + h2
+    }
+    else if (h1 - h2).abs() <= 180.0
+    {
+        (h1 + h2) / 2.0
+    }
+    else if h1 + h2 < 360.0
+    {
+        (h1 + h2 + 360.0) / 2.0
+    }
+    else
+    {
+        (h1 + h2 - 360.0) / 2.0
+    };
+    let cos = |degrees: f64| degrees.to_radians().cos();
+    let t = 1.0 - 0.17 * cos(mean_h - 30.0) + 0.24 * cos(2.0 * mean_h) + 0.32 * cos(3.0 * mean_h + 6.0) - 0.20 * cos(4.0 * mean_h - 63.0);
+    let delta_theta = 30.0 * (-((mean_h - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (pow7(mean_c) / (pow7(mean_c) + pow7(25.0))).sqrt();
+    let s_l = 1.0 + 0.015 * (mean_l - 50.0).powi(2) / (20.0 + (mean_l - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * mean_c;
+    let s_h = 1.0 + 0.015 * mean_c * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+    (l * l + c * c + h * h + r_t * c * h).max(0.0).sqrt() as f32
+}
+
+/// Finds the nearest palette colour under a `DistanceMetric`.
+///
+/// Metrics that are a straight distance in some colour space search a k-d tree of the palette in that space,
+/// which takes about `log2(colours)` comparisons instead of one per colour. CIEDE2000 can't be searched that way,
+/// so it fills a 64x64x64 table of sRGB colours as they come up, finding the nearest colour to the middle of each
+/// cell once; later pixels in the same cell just look the answer up.
+pub struct ColourMatcher
+{
+    pub palette: Vec<Rgba8>,
+    pub metric: DistanceMetric,
+    lookup: Lookup,
+}
+
+enum Lookup
+{
+    Tree(KdTree),
+    /// The palette in CIELAB, a tree of it for a first guess, the palette indices in order of lightness,
+    /// and the index of the nearest colour plus 1 for every cell, 0 while it's unknown.
+    Table{lab: Vec<[f32; 3]>, tree: KdTree, by_lightness: Vec<usize>, cells: Vec<AtomicU32>},
+}
+
+/// How many matchers `ColourMatcher::cached` keeps.
+const CACHED: usize = 8;
+/// Cells per channel of the CIEDE2000 table.
+const TABLE_SIZE: usize = 64;
+/// The largest lightness weighting of CIEDE2000, at L = 0 or 100. A colour whose lightness differs by `d`
+/// is at least `d / MAX_LIGHTNESS_WEIGHT` away.
+const MAX_LIGHTNESS_WEIGHT: f32 = 1.75;
+
+impl ColourMatcher
+{
+    pub fn new(palette: &[Rgba8], metric: DistanceMetric) -> Self
+    {
+        let transformed = palette.iter().map(|c| metric.transform([c.r as f32, c.g as f32, c.b as f32])).collect::<Vec<_>>();
+        let lookup = match metric
+        {
+            DistanceMetric::Ciede2000 =>
+            {
+                let mut by_lightness = (0..transformed.len()).collect::<Vec<_>>();
+                by_lightness.sort_by(|&a, &b| transformed[a][0].total_cmp(&transformed[b][0]));
+                let cells = (0..TABLE_SIZE.pow(3)).map(|_| AtomicU32::new(0)).collect();
+                Lookup::Table{tree: KdTree::new(transformed.clone()), lab: transformed, by_lightness, cells}
+            }
+            _ => Lookup::Tree(KdTree::new(transformed)),
+        };
+        Self{palette: palette.to_vec(), metric, lookup}
+    }
+
+    /// A matcher for `palette` and `metric`, reusing one made before for the same ones, so that a table filled on
+    /// one frame serves the next ones too. The `CACHED` most recently used matchers are kept, enough for every
+    /// palette stage of a chain to keep its own.
+    pub fn cached(palette: &[Rgba8], metric: DistanceMetric) -> Arc<ColourMatcher>
+    {
+        static RECENT: Mutex<Vec<Arc<ColourMatcher>>> = Mutex::new(Vec::new());
+        let mut recent = RECENT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let matcher = match recent.iter().position(|matcher| matcher.metric == metric && matcher.palette == palette)
+        {
+            Some(index) => recent.remove(index),
+            None => Arc::new(ColourMatcher::new(palette, metric)),
+        };
+        recent.insert(0, matcher.clone());
+        recent.truncate(CACHED);
+        matcher
+    }
+
+    /// The palette colour nearest to `colour`, given as sRGB channels from 0 to 255. Ties go to the first of the palette.
+    /// An empty palette gives black.
+    pub fn nearest(&self, colour: [f32; 3]) -> Rgba8
+    {
+        if self.palette.is_empty()
+        {
+            return Rgba8::BLACK;
+        }
+        let index = match &self.lookup
+        {
+            Lookup::Tree(tree) => tree.nearest(self.metric.transform(colour)),
+            Lookup::Table{lab, tree, by_lightness, cells} =>
+            {
+                let [r, g, b] = colour.map(|c| (c.clamp(0.0, 255.0) * (TABLE_SIZE - 1) as f32 / 255.0).round() as usize);
+                let cell = &cells[(r * TABLE_SIZE + g) * TABLE_SIZE + b];
+                match cell.load(Ordering::Relaxed)
+                {
+                    0 =>
+                    {
+                        let centre = [r, g, b].map(|c| c as f32 * 255.0 / (TABLE_SIZE - 1) as f32);
+                        let centre = self.metric.transform(centre);
+                        let index = nearest_ciede2000(centre, lab, tree, by_lightness);
+                        // threads that fill the same cell at once store the same answer
+                        cell.store(index as u32 + 1, Ordering::Relaxed);
+                        index
+                    }
+                    stored => stored as usize - 1,
+                }
+            }
+        };
+        self.palette[index]
+    }
+}
+
+/// The index of the colour of `lab` nearest to `target` by CIEDE2000, the lowest one if several are.
+///
+/// The nearest colour by plain CIELAB distance is usually close, and no colour further away in lightness than
+/// `MAX_LIGHTNESS_WEIGHT` times its CIEDE2000 distance can beat it, so only that band of `by_lightness` is looked at.
+/// Within the band, colours whose lightness difference alone puts them further away than the best so far are skipped.
+fn nearest_ciede2000(target: [f32; 3], lab: &[[f32; 3]], tree: &KdTree, by_lightness: &[usize]) -> usize
+{
+    let guess = tree.nearest(target);
+    let mut best = (ciede2000(target, lab[guess]), guess);
+    let reach = best.0 * MAX_LIGHTNESS_WEIGHT + 0.01;
+    let start = by_lightness.partition_point(|&i| lab[i][0] < target[0] - reach);
+    let end = by_lightness.partition_point(|&i| lab[i][0] <= target[0] + reach);
+    for &i in &by_lightness[start..end]
+    {
+        if (lab[i][0] - target[0]).abs() / lightness_weight(lab[i][0], target[0]) > best.0 + 0.01
+        {
+            continue;
+        }
+        let distance = ciede2000(target, lab[i]);
+        if distance < best.0 || (distance == best.0 && i < best.1)
+        {
+            best = (distance, i);
+        }
+    }
+    best.1
+}
+
+/// The weighting CIEDE2000 divides the lightness difference of two colours by.
+fn lightness_weight(l1: f32, l2: f32) -> f32
+{
+    let offset = (l1 + l2) / 2.0 - 50.0;
+    1.0 + 0.015 * offset * offset / (20.0 + offset * offset).sqrt()
+}
+
+/// A [k-d tree] of points with the index they came with, stored as a sorted array: the middle of every range
+/// splits the rest of it along `axes[middle]`.
+///
+/// [k-d tree]: https://en.wikipedia.org/wiki/K-d_tree
+struct KdTree
+{
+    points: Vec<([f32; 3], usize)>,
+    axes: Vec<usize>,
+}
+
+impl KdTree
+{
+    fn new(points: Vec<[f32; 3]>) -> Self
+    {
+        let mut points = points.into_iter().enumerate().map(|(i, p)| (p, i)).collect::<Vec<_>>();
+        let mut axes = vec![0; points.len()];
+        Self::build(&mut points, &mut axes);
+        Self{points, axes}
+    }
+
+    /// Sorts the range so that its middle splits it along its widest axis, then does the same for both halves.
+    fn build(points: &mut [([f32; 3], usize)], axes: &mut [usize])
+    {
+        if points.len() <= 1
+        {
+            return;
+        }
+        let spread = |axis: usize|
+        {
+            let values = points.iter().map(|(p, _)| p[axis]);
+            let (min, max) = values.fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(v), max.max(v)));
+            max - min
+        };
+        let axis = (0..3).max_by(|&a, &b| spread(a).total_cmp(&spread(b))).unwrap_or(0);
+        let middle = points.len() / 2;
+        points.select_nth_unstable_by(middle, |a, b| a.0[axis].total_cmp(&b.0[axis]));
+        axes[middle] = axis;
+        let (lower_points, upper_points) = points.split_at_mut(middle);
+        let (lower_axes, upper_axes) = axes.split_at_mut(middle);
+        Self::build(lower_points, lower_axes);
+        Self::build(&mut upper_points[1..], &mut upper_axes[1..]);
+    }
+
+    /// The index of the point nearest to `target`. Ties go to the lowest index, like a search through all points would.
+    fn nearest(&self, target: [f32; 3]) -> usize
+    {
+        let mut best = (f32::MAX, usize::MAX);
+        self.search(target, 0, self.points.len(), &mut best);
+        best.1
+    }
+
+    fn search(&self, target: [f32; 3], start: usize, end: usize, best: &mut (f32, usize))
+    {
+        if start >= end
+        {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let (point, index) = self.points[middle];
+        let distance = point.iter().zip(&target).map(|(a, b)| (a - b) * (a - b)).sum::<f32>();
+        if distance < best.0 || distance == best.0 && index < best.1
+        {
+            *best = (distance, index);
+        }
+        let axis = self.axes[middle];
+        let offset = target[axis] - point[axis];
+        let (near, far) = if offset < 0.0 {((start, middle), (middle + 1, end))} else {((middle + 1, end), (start, middle))};
+        self.search(target, near.0, near.1, best);
+        // the other side can only hold something nearer, or as near, if the splitting plane is close enough
+        if offset * offset <= best.0
+        {
+            self.search(target, far.0, far.1, best);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn cache_keeps_a_matcher_per_palette_and_metric()
+    {
+        let first = [Rgba8::rgb(1, 2, 3), Rgba8::rgb(200, 201, 202)];
+        let second = [Rgba8::rgb(4, 5, 6), Rgba8::rgb(100, 101, 102)];
+        let matchers = [(&first, DistanceMetric::Ciede2000), (&second, DistanceMetric::OkLab), (&first, DistanceMetric::Rgb)]
+            .map(|(palette, metric)| ColourMatcher::cached(palette, metric));
+        // stages taking turns get the matcher they had before, with its table already filled
+        for _ in 0..3
+        {
+            assert!(Arc::ptr_eq(&ColourMatcher::cached(&first, DistanceMetric::Ciede2000), &matchers[0]));
+            assert!(Arc::ptr_eq(&ColourMatcher::cached(&second, DistanceMetric::OkLab), &matchers[1]));
+            assert!(Arc::ptr_eq(&ColourMatcher::cached(&first, DistanceMetric::Rgb), &matchers[2]));
+        }
+        assert!(!Arc::ptr_eq(&matchers[0], &matchers[2]));
+    }
+
+    /// Sharma, Wu and Dalal's test data: two CIELAB colours and their CIEDE2000 difference.
+    const SHARMA_PAIRS: [([f32; 3], [f32; 3], f32); 34] =
+    [
+        ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+        ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+        ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+        ([50.0, -1.3802, -84.2814], [50.0, 0.0, -82.7485], 1.0000),
+        ([50.0, -1.1848, -84.8006], [50.0, 0.0, -82.7485], 1.0000),
+        ([50.0, -0.9009, -85.5211], [50.0, 0.0, -82.7485], 1.0000),
+        ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+        ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0009], 7.1792),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.001], 7.1792),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0011], 7.2195),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0012], 7.2195),
+        ([50.0, -0.001, 2.49], [50.0, 0.0009, -2.49], 4.8045),
+        ([50.0, -0.001, 2.49], [50.0, 0.001, -2.49], 4.8045),
+        ([50.0, -0.001, 2.49], [50.0, 0.0011, -2.49], 4.7461),
+        ([50.0, 2.5, 0.0], [50.0, 0.0, -2.5], 4.3065),
+        ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+        ([50.0, 2.5, 0.0], [61.0, -5.0, 29.0], 22.8977),
+        ([50.0, 2.5, 0.0], [56.0, -27.0, -3.0], 31.9030),
+        ([50.0, 2.5, 0.0], [58.0, 24.0, 15.0], 19.4535),
+        ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0000),
+        ([50.0, 2.5, 0.0], [50.0, 3.2972, 0.0], 1.0000),
+        ([50.0, 2.5, 0.0], [50.0, 1.8634, 0.5757], 1.0000),
+        ([50.0, 2.5, 0.0], [50.0, 3.2592, 0.3350], 1.0000),
+        ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+        ([63.0109, -31.0961, -5.8663], [62.8187, -29.7946, -4.0864], 1.2630),
+        ([61.2901, 3.7196, -5.3901], [61.4292, 2.2480, -4.9620], 1.8731),
+        ([35.0831, -44.1164, 3.7933], [35.0232, -40.0716, 1.5901], 1.8645),
+        ([22.7233, 20.0904, -46.6940], [23.0331, 14.9730, -42.5619], 2.0373),
+        ([36.4612, 47.8580, 18.3852], [36.2715, 50.5065, 21.2231], 1.4146),
+        ([90.8027, -2.0831, 1.4410], [91.1528, -1.6435, 0.0447], 1.4441),
+        ([90.9257, -0.5406, -0.9208], [88.6381, -0.8985, -0.7239], 1.5381),
+        ([6.7747, -0.2908, -2.4247], [5.8714, -0.0985, -2.2286], 0.6377),
+        ([2.0776, 0.0795, -1.1350], [0.9033, -0.0636, -0.5514], 0.9082),
+    ];
+
+    #[test]
+    fn ciede2000_matches_the_published_pairs()
+    {
+        for (i, &(lab1, lab2, expected)) in SHARMA_PAIRS.iter().enumerate()
+        {
+            let difference = ciede2000(lab1, lab2);
+            assert!((difference - expected).abs() < 1.5e-4, "pair {}: {} instead of {}", i + 1, difference, expected);
+            assert_eq!(ciede2000(lab2, lab1), difference, "pair {} isn't symmetric", i + 1);
+        }
+    }
+
+    /// A xorshift generator, so the random palettes are the same on every run.
+    struct Random(u32);
+
+    impl Random
+    {
+        fn byte(&mut self) -> u8
+        {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 24) as u8
+        }
+
+        fn colour(&mut self) -> Rgba8
+        {
+            Rgba8::rgb(self.byte(), self.byte(), self.byte())
+        }
+
+        /// A palette of `size` colours with a few repeated, so that ties come up.
+        fn palette(&mut self, size: usize) -> Vec<Rgba8>
+        {
+            let mut palette = (0..size).map(|_| self.colour()).collect::<Vec<_>>();
+            for i in (3..size).step_by(4)
+            {
+                palette[i] = palette[i / 2];
+            }
+            palette
+        }
+    }
+
+    fn channels(colour: Rgba8) -> [f32; 3]
+    {
+        [colour.r as f32, colour.g as f32, colour.b as f32]
+    }
+
+    /// The index of the first palette colour with the smallest `distance`, comparing every colour.
+    fn brute_force(palette: &[Rgba8], distance: impl Fn(Rgba8) -> f32) -> usize
+    {
+        palette.iter().enumerate().map(|(i, &c)| (distance(c), i)).min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1))).unwrap().1
+    }
+
+    #[test]
+    fn tree_finds_what_a_search_through_every_colour_finds()
+    {
+        let mut random = Random(0x2545_f491);
+        for metric in DistanceMetric::ALL.into_iter().filter(|&m| m != DistanceMetric::Ciede2000)
+        {
+            for size in [1, 2, 3, 7, 16, 100, 256]
+            {
+                let palette = random.palette(size);
+                let matcher = ColourMatcher::new(&palette, metric);
+                // wanted colours are off the 8 bit grid after error diffusion, and on it, where ties are common
+                let mut wanted_colours = (0..400).map(|_| channels(random.colour())).collect::<Vec<_>>();
+                for wanted in wanted_colours.iter_mut().take(200)
+                {
+                    *wanted = wanted.map(|c| c - 8.0 + random.byte() as f32 / 16.0);
+                }
+                for wanted in wanted_colours
+                {
+                    let wanted_point = metric.transform(wanted);
+                    let expected = brute_force(&palette, |c|
+                    {
+                        metric.transform(channels(c)).iter().zip(&wanted_point).map(|(a, b)| (a - b) * (a - b)).sum()
+                    });
+                    assert_eq!(matcher.nearest(wanted), palette[expected], "{:?} with {} colours for {:?}", metric, size, wanted);
+                    if let Lookup::Tree(tree) = &matcher.lookup
+                    {
+                        assert_eq!(tree.nearest(wanted_point), expected, "{:?} with {} colours for {:?}", metric, size, wanted);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lightness_band_finds_what_a_search_through_every_colour_finds()
+    {
+        let mut random = Random(0x9e37_79b9);
+        for size in [1, 2, 5, 16, 64, 256]
+        {
+            let palette = random.palette(size);
+            let lab = palette.iter().map(|&c| DistanceMetric::Ciede2000.transform(channels(c))).collect::<Vec<_>>();
+            let tree = KdTree::new(lab.clone());
+            let mut by_lightness = (0..lab.len()).collect::<Vec<_>>();
+            by_lightness.sort_by(|&a, &b| lab[a][0].total_cmp(&lab[b][0]));
+            for _ in 0..2000
+            {
+                let target = DistanceMetric::Ciede2000.transform(channels(random.colour()));
+                let expected = (0..lab.len()).map(|i| (ciede2000(target, lab[i]), i)).min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1))).unwrap().1;
+                assert_eq!(nearest_ciede2000(target, &lab, &tree, &by_lightness), expected, "{} colours, {:?}", size, target);
+            }
+        }
+    }
+
+    #[test]
+    fn table_gives_the_nearest_colour_to_the_middle_of_the_cell()
+    {
+        let mut random = Random(0x1234_5678);
+        let palette = random.palette(48);
+        let matcher = ColourMatcher::new(&palette, DistanceMetric::Ciede2000);
+        for _ in 0..3000
+        {
+            let wanted = channels(random.colour());
+            let centre = wanted.map(|c| (c * (TABLE_SIZE - 1) as f32 / 255.0).round() * 255.0 / (TABLE_SIZE - 1) as f32);
+            let centre = DistanceMetric::Ciede2000.transform(centre);
+            let expected = brute_force(&palette, |c| ciede2000(centre, DistanceMetric::Ciede2000.transform(channels(c))));
+            // twice, the second time from the table
+            assert_eq!(matcher.nearest(wanted), palette[expected]);
+            assert_eq!(matcher.nearest(wanted), palette[expected]);
+        }
+    }
+
+    #[test]
+    fn empty_palettes_give_black()
+    {
+        for metric in DistanceMetric::ALL
+        {
+            assert_eq!(ColourMatcher::new(&[], metric).nearest([10.0, 20.0, 30.0]), Rgba8::BLACK);
+        }
+    }
+}
@@ -0,0 +1,644 @@
+use crate::image::Image;
+use crate::pixel::Rgba8;
+use crate::pixel_traits::*;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// How the quantisation error of a pixel is spread over the pixels that haven't been quantised yet.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DiffusionKernel
+{
+    FloydSteinberg,
+    JarvisJudiceNinke,
+    Stucki,
+    Burkes,
+    /// Also called Sierra-3.
+    Sierra,
+    SierraTwoRow,
+    SierraLite,
+    /// Passes on only 6/8 of the error, which keeps more contrast but loses detail in highlights and shadows.
+    Atkinson,
+}
+
+impl DiffusionKernel
+{
+    pub const ALL: [DiffusionKernel; 8] =
+    [
+        DiffusionKernel::FloydSteinberg,
+        DiffusionKernel::JarvisJudiceNinke,
+        DiffusionKernel::Stucki,
+        DiffusionKernel::Burkes,
+        DiffusionKernel::Sierra,
+        DiffusionKernel::SierraTwoRow,
+        DiffusionKernel::SierraLite,
+        DiffusionKernel::Atkinson,
+    ];
+
+    /// The names of `ALL`, in the same order.
+    pub const NAMES: [&'static str; 8] = ["FloydSteinberg", "JarvisJudiceNinke", "Stucki", "Burkes", "Sierra", "SierraTwoRow", "SierraLite", "Atkinson"];
+
+    /// Where the error goes, as `(dx, dy, weight)` relative to the current pixel, scanning left to right.
+    pub fn taps(&self) -> &'static [(i32, i32, i32)]
+    {
+        match self
+        {
+            DiffusionKernel::FloydSteinberg => &
+            [
+                                        (1, 0, 7),
+                (-1, 1, 3), (0, 1, 5), (1, 1, 1),
+            ],
+            DiffusionKernel::JarvisJudiceNinke => &
+            [
+                                                               (1, 0, 7), (2, 0, 5),
+                (-2, 1, 3), (-1, 1, 5), (0, 1, 7), (1, 1, 5), (2, 1, 3),
+                (-2, 2, 1), (-1, 2, 3), (0, 2, 5), (1, 2, 3), (2, 2, 1),
+            ],
+            DiffusionKernel::Stucki => &
+            [
+                                                               (1, 0, 8), (2, 0, 4),
+                (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+                (-2, 2, 1), (-1, 2, 2), (0, 2, 4), (1, 2, 2), (2, 2, 1),
+            ],
+            DiffusionKernel::Burkes => &
+            [
+                                                               (1, 0, 8), (2, 0, 4),
+                (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+            ],
+            DiffusionKernel::Sierra => &
+            [
+                                                               (1, 0, 5), (2, 0, 3),
+                (-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+                            (-1, 2, 2), (0, 2, 3), (1, 2, 2),
+            ],
+            DiffusionKernel::SierraTwoRow => &
+            [
+                                                               (1, 0, 4), (2, 0, 3),
+                (-2, 1, 1), (-1, 1, 2), (0, 1, 3), (1, 1, 2), (2, 1, 1),
+            ],
+            DiffusionKernel::SierraLite => &
+            [
+                                        (1, 0, 2),
+                (-1, 1, 1), (0, 1, 1),
+            ],
+            DiffusionKernel::Atkinson => &
+            [
+                                        (1, 0, 1), (2, 0, 1),
+                (-1, 1, 1), (0, 1, 1), (1, 1, 1),
+                            (0, 2, 1),
+            ],
+        }
+    }
+
+    /// The weights of `taps` are fractions of this.
+    pub fn divisor(&self) -> i32
+    {
+        match self
+        {
+            DiffusionKernel::FloydSteinberg => 16,
+            DiffusionKernel::JarvisJudiceNinke => 48,
+            DiffusionKernel::Stucki => 42,
+            DiffusionKernel::Burkes => 32,
+            DiffusionKernel::Sierra => 32,
+            DiffusionKernel::SierraTwoRow => 16,
+            DiffusionKernel::SierraLite => 4,
+            DiffusionKernel::Atkinson => 8,
+        }
+    }
+
+    /// How many columns the kernel reaches to either side.
+    fn reach(&self) -> usize
+    {
+        self.taps().iter().map(|&(dx, _, _)| dx.unsigned_abs() as usize).max().unwrap_or(0)
+    }
+}
+
+/// Quantises every pixel of `source` with `quantise` and spreads the difference to the wanted colour
+/// over the neighbours that come later, as `kernel` says. The result is written to `target`.
+///
+/// `quantise` gets the wanted red, green and blue from 0 to 255, clamped to that range, and returns the output colour.
+/// The alpha of the source is kept.
+/// The error is signed and kept per channel as a float, so it can push neighbours both up and down.
+/// Error that would land outside the image is dropped. With `serpentine` every other row is scanned right to left,
+/// with the kernel mirrored, which breaks up the diagonal patterns that scanning in one direction leaves.
+///
+/// Raster scans run on the rayon thread pool in a wavefront: a row may quantise a pixel once the row above has finished every
+/// column within twice the kernel's reach, so that it never adds error to a pixel at the same time as the rows above
+/// and every pixel sees the same additions in the same order as on one thread. The result is identical for any number of threads.
+/// A serpentine row starts at the end the row above finishes at, so it has to wait for that whole row,
+/// and serpentine scans run on the calling thread alone.
+pub fn error_diffusion<F>(source: &Image, target: &mut Image, kernel: DiffusionKernel, serpentine: bool, quantise: F) where F: Fn([f32; 3]) -> Rgba8 + Sync
+{
+    let (width, height) = (source.width, source.height);
+    let taps = kernel.taps().iter().map(|&(dx, dy, weight)| (dx as isize, dy as usize, weight as f32 / kernel.divisor() as f32)).collect::<Vec<_>>();
+    let window = 2 * kernel.reach();
+
+    // the wanted colours, the source plus the error diffused so far, as the bits of f32s because neighbouring rows write to them
+    let wanted = source.pixels.iter().flat_map(|p| [p.r, p.g, p.b]).map(|c| AtomicU32::new((c as f32).to_bits())).collect::<Vec<_>>();
+    let output = source.pixels.iter().map(|_| AtomicU32::new(0)).collect::<Vec<_>>();
+    // how many pixels of every row are done, counted in the row's scanning direction
+    let progress = (0..height).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>();
+    let reversed = |y: usize| serpentine && y % 2 == 1;
+    let finished = |y: usize, first: usize, last: usize|
+    {
+        let done = progress[y].load(Ordering::Acquire);
+        if reversed(y) {done >= width - first} else {done > last}
+    };
+
+    let dither_row = |y: usize|
+    {
+        for i in 0..width
+        {
+            let x = if reversed(y) {width - 1 - i} else {i};
+            if y > 0
+            {
+                while !finished(y - 1, x.saturating_sub(window), (x + window).min(width - 1))
+                {
+                    std::thread::yield_now();
+                }
+            }
+            let index = y * width + x;
+            let value: [f32; 3] = std::array::from_fn(|c| f32::from_bits(wanted[index * 3 + c].load(Ordering::Relaxed)).clamp(0.0, 255.0));
+            let new = quantise(value);
+            let error = [value[0] - new.r as f32, value[1] - new.g as f32, value[2] - new.b as f32];
+
+            for &(dx, dy, weight) in &taps
+            {
+                let target_x = if reversed(y) {x as isize - dx} else {x as isize + dx};
+                let target_y = y + dy;
+                if target_x < 0 || target_x >= width as isize || target_y >= height
+                {
+                    continue;
+                }
+                let target_index = target_y * width + target_x as usize;
+                for (c, error) in error.iter().enumerate()
+                {
+                    let cell = &wanted[target_index * 3 + c];
+                    cell.store((f32::from_bits(cell.load(Ordering::Relaxed)) + error * weight).to_bits(), Ordering::Relaxed);
+                }
+            }
+            output[index].store(u32::from_le_bytes([new.r, new.g, new.b, 0]), Ordering::Relaxed);
+            progress[y].store(i + 1, Ordering::Release);
+        }
+    };
+
+    if serpentine
+    {
+        (0..height).for_each(dither_row);
+    }
+    else
+    {
+        // every task takes the next row nobody has started yet, so a task only ever waits for rows that are already being worked on
+        // and never for one that sits unstarted in the queue of a thread that is busy waiting itself, even when called from inside the pool.
+        // Tasks wait for each other, so there are never more of them than threads to run them at once.
+        let next_row = AtomicUsize::new(0);
+        let threads = rayon::current_num_threads().clamp(1, height.max(1));
+        rayon::scope(|scope|
+        {
+            for _ in 0..threads
+            {
+                let (dither_row, next_row) = (&dither_row, &next_row);
+                scope.spawn(move |_| loop
+                {
+                    let y = next_row.fetch_add(1, Ordering::Relaxed);
+                    if y >= height
+                    {
+                        break;
+                    }
+                    dither_row(y);
+                });
+            }
+        });
+    }
+    for ((pixel, cell), original) in target.pixels.iter_mut().zip(&output).zip(&source.pixels)
+    {
+        let [r, g, b, _] = cell.load(Ordering::Relaxed).to_le_bytes();
+        *pixel = Rgba8::rgba(r, g, b, original.a);
+    }
+}
+
+/// The threshold matrix ordered dithering tiles over the image.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ThresholdMap
+{
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    Bayer16,
+    /// 8x8 dots on a 45 degree screen that grow with the brightness, like print halftoning.
+    ClusteredDot,
+    /// A 64x64 blue-noise texture made with void-and-cluster, which has no visible pattern.
+    BlueNoise,
+}
+
+impl ThresholdMap
+{
+    pub const ALL: [ThresholdMap; 6] = [ThresholdMap::Bayer2, ThresholdMap::Bayer4, ThresholdMap::Bayer8, ThresholdMap::Bayer16, ThresholdMap::ClusteredDot, ThresholdMap::BlueNoise];
+
+    /// The names of `ALL`, in the same order.
+    pub const NAMES: [&'static str; 6] = ["Bayer2", "Bayer4", "Bayer8", "Bayer16", "ClusteredDot", "BlueNoise"];
+
+    /// The matrix of the map, made the first time it's asked for.
+    pub fn matrix(&self) -> &'static ThresholdMatrix
+    {
+        static MATRICES: [std::sync::OnceLock<ThresholdMatrix>; 6] = [const {std::sync::OnceLock::new()}; 6];
+        let index = ThresholdMap::ALL.iter().position(|map| map == self).unwrap_or(0);
+        MATRICES[index].get_or_init(|| match self
+        {
+            ThresholdMap::Bayer2 => ThresholdMatrix::bayer(2),
+            ThresholdMap::Bayer4 => ThresholdMatrix::bayer(4),
+            ThresholdMap::Bayer8 => ThresholdMatrix::bayer(8),
+            ThresholdMap::Bayer16 => ThresholdMatrix::bayer(16),
+            ThresholdMap::ClusteredDot => ThresholdMatrix::clustered_dot(8),
+            ThresholdMap::BlueNoise => ThresholdMatrix::blue_noise(64, 0),
+        })
+    }
+}
+
+/// A square tile of thresholds between 0 and 1, each one the rank of its cell plus a half, over the number of cells,
+/// so that every fraction of the cells lies below some threshold.
+pub struct ThresholdMatrix
+{
+    pub size: usize,
+    pub thresholds: Vec<f32>,
+}
+
+impl ThresholdMatrix
+{
+    /// Turns the order in which cells switch on into thresholds, see the type.
+    pub fn from_ranks(size: usize, ranks: &[usize]) -> Self
+    {
+        assert_eq!(ranks.len(), size * size);
+        let thresholds = ranks.iter().map(|&rank| (rank as f32 + 0.5) / ranks.len() as f32).collect();
+        Self{size, thresholds}
+    }
+
+    /// The [Bayer matrix] of `size`, which must be a power of two.
+    ///
+    /// [Bayer matrix]: https://en.wikipedia.org/wiki/Ordered_dithering
+    pub fn bayer(size: usize) -> Self
+    {
+        assert!(size.is_power_of_two(), "Bayer matrices are a power of two wide, got {}", size);
+        let mut ranks = vec![0];
+        let mut current = 1;
+        // every doubling puts four copies of the matrix side by side, interleaved as 0 2 / 3 1
+        while current < size
+        {
+            let doubled = current * 2;
+            ranks = (0..doubled * doubled).map(|i|
+            {
+                let (x, y) = (i % doubled, i / doubled);
+                let offset = [[0, 2], [3, 1]][y / current][x / current];
+                4 * ranks[(y % current) * current + x % current] + offset
+            }).collect();
+            current = doubled;
+        }
+        Self::from_ranks(size, &ranks)
+    }
+
+    /// A `size` by `size` tile with two dots on a 45 degree screen, one growing from the middle of the edges
+    /// and one shrinking at the corners and the centre, so light and dark areas both get round dots.
+    pub fn clustered_dot(size: usize) -> Self
+    {
+        let spot = |i: usize|
+        {
+            let angle = |c: usize| 2.0 * std::f32::consts::PI * (c as f32 + 0.5) / size as f32;
+            angle(i % size).cos() * angle(i / size).cos()
+        };
+        let mut order = (0..size * size).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| spot(a).total_cmp(&spot(b)));
+        let mut ranks = vec![0; size * size];
+        for (rank, &cell) in order.iter().enumerate()
+        {
+            ranks[cell] = rank;
+        }
+        Self::from_ranks(size, &ranks)
+    }
+
+    /// A `size` by `size` tile of blue noise, made with Ulichney's [void-and-cluster] method from a random start picked by `seed`.
+    ///
+    /// Every cell has an energy, the Gaussian weighted count of the switched on cells around it, wrapping around the tile.
+    /// Cells are ranked by repeatedly switching off the tightest cluster or filling the largest void,
+    /// so that every prefix of the ranking is spread out as evenly as it can be.
+    ///
+    /// [void-and-cluster]: https://doi.org/10.1117/12.152707
+    pub fn blue_noise(size: usize, seed: u64) -> Self
+    {
+        let cells = size * size;
+        let mut field = EnergyField::new(size, 1.5);
+        let mut pattern = vec![false; cells];
+
+        // a random tenth of the cells to start from
+        let rng = fastrand::Rng::with_seed(seed);
+        let mut order = (0..cells).collect::<Vec<_>>();
+        rng.shuffle(&mut order);
+        let initial = (cells / 10).max(1);
+        for &cell in &order[..initial]
+        {
+            pattern[cell] = true;
+            field.splat(cell, 1.0);
+        }
+
+        // move the tightest cluster into the largest void until that changes nothing
+        for _ in 0..cells
+        {
+            let cluster = field.tightest_cluster(&pattern, true);
+            pattern[cluster] = false;
+            field.splat(cluster, -1.0);
+            let void = field.largest_void(&pattern, false);
+            pattern[void] = true;
+            field.splat(void, 1.0);
+            if void == cluster
+            {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0; cells];
+        // the cells of the starting pattern, ranked by taking them out tightest cluster first
+        let (mut removing, mut removing_field) = (pattern.clone(), field.clone());
+        for rank in (0..initial).rev()
+        {
+            let cluster = removing_field.tightest_cluster(&removing, true);
+            removing[cluster] = false;
+            removing_field.splat(cluster, -1.0);
+            ranks[cluster] = rank;
+        }
+        // up to half full, filling the largest void
+        for rank in initial..cells / 2
+        {
+            let void = field.largest_void(&pattern, false);
+            pattern[void] = true;
+            field.splat(void, 1.0);
+            ranks[void] = rank;
+        }
+        // past half the cells that are off are the minority, so they form the clusters to break up
+        let mut field = EnergyField::new(size, 1.5);
+        for cell in (0..cells).filter(|&cell| !pattern[cell])
+        {
+            field.splat(cell, 1.0);
+        }
+        for rank in cells / 2..cells
+        {
+            let cluster = field.tightest_cluster(&pattern, false);
+            pattern[cluster] = true;
+            field.splat(cluster, -1.0);
+            ranks[cluster] = rank;
+        }
+        Self::from_ranks(size, &ranks)
+    }
+
+    /// The threshold at `(x, y)` of the image, tiling the matrix.
+    pub fn at(&self, x: usize, y: usize) -> f32
+    {
+        self.thresholds[(y % self.size) * self.size + x % self.size]
+    }
+}
+
+/// The energies of void-and-cluster, see `ThresholdMatrix::blue_noise`.
+#[derive(Clone)]
+struct EnergyField
+{
+    size: usize,
+    energy: Vec<f32>,
+    /// `(dx, dy, weight)` of the Gaussian, cut off at three sigmas or half the tile.
+    taps: Vec<(usize, usize, f32)>,
+}
+
+impl EnergyField
+{
+    fn new(size: usize, sigma: f32) -> Self
+    {
+        let radius = ((sigma * 3.0).ceil() as usize).min((size - 1) / 2);
+        let mut taps = vec![];
+        for dy in 0..=2 * radius
+        {
+            for dx in 0..=2 * radius
+            {
+                let (x, y) = (dx as f32 - radius as f32, dy as f32 - radius as f32);
+                // offsets are stored wrapped into the tile so adding them needs no signs
+                taps.push(((dx + size - radius) % size, (dy + size - radius) % size, (-(x * x + y * y) / (2.0 * sigma * sigma)).exp()));
+            }
+        }
+        Self{size, energy: vec![0.0; size * size], taps}
+    }
+
+    /// Adds `sign` times the Gaussian around `cell` to the energies.
+    fn splat(&mut self, cell: usize, sign: f32)
+    {
+        let (x, y) = (cell % self.size, cell / self.size);
+        for &(dx, dy, weight) in &self.taps
+        {
+            self.energy[((y + dy) % self.size) * self.size + (x + dx) % self.size] += sign * weight;
+        }
+    }
+
+    /// Of the cells where `pattern` is `of`, the one with the highest energy. Ties go to the first cell.
+    fn tightest_cluster(&self, pattern: &[bool], of: bool) -> usize
+    {
+        self.first_best(pattern, of, |energy, best| energy > best)
+    }
+
+    /// Of the cells where `pattern` is `of`, the one with the lowest energy. Ties go to the first cell.
+    fn largest_void(&self, pattern: &[bool], of: bool) -> usize
+    {
+        self.first_best(pattern, of, |energy, best| energy < best)
+    }
+
+    fn first_best<F>(&self, pattern: &[bool], of: bool, better: F) -> usize where F: Fn(f32, f32) -> bool
+    {
+        let mut best: Option<(usize, f32)> = None;
+        for (cell, (&energy, &set)) in self.energy.iter().zip(pattern).enumerate()
+        {
+            if set == of && best.is_none_or(|(_, best)| better(energy, best))
+            {
+                best = Some((cell, energy));
+            }
+        }
+        best.map(|(cell, _)| cell).unwrap_or(0)
+    }
+}
+
+/// Ordered dithering of `source` into `target`: every pixel is moved by its threshold from `map`, minus a half,
+/// times `spread`, on all channels, then `quantise` picks the nearest output colour. The alpha of the source is kept.
+///
+/// With `spread` the distance between neighbouring output levels, the share of pixels that go up to the next level
+/// matches how far the colour lies between the two, so areas keep their average colour.
+pub fn ordered_dithering<F>(source: &Image, target: &mut Image, map: ThresholdMap, spread: f32, quantise: F) where F: Fn([f32; 3]) -> Rgba8 + Sync
+{
+    let matrix = map.matrix();
+    target.for_each_row_parallel(|y, row|
+    {
+        for (x, pixel) in row.iter_mut().enumerate()
+        {
+            let original = source[(x, y)];
+            let offset = (matrix.at(x, y) - 0.5) * spread;
+            let wanted = [original.r, original.g, original.b].map(|c| (c as f32 + offset).clamp(0.0, 255.0));
+            let new = quantise(wanted);
+            *pixel = Rgba8::rgba(new.r, new.g, new.b, original.a);
+        }
+    });
+}
+
+/// Ordered dithering of `source` into `target` for palettes, where colours aren't evenly spaced, with Thomas Knoll's
+/// pattern dithering: every pixel makes a plan of `candidates` colours with `quantise`, feeding back the error of
+/// the ones picked so far so that the plan averages to the wanted colour. Sorted by brightness, the threshold from `map`
+/// picks one of them, so areas keep their average colour whatever the palette. The alpha of the source is kept.
+pub fn pattern_dithering<F>(source: &Image, target: &mut Image, map: ThresholdMap, candidates: usize, quantise: F) where F: Fn([f32; 3]) -> Rgba8 + Sync
+{
+    let matrix = map.matrix();
+    target.for_each_row_parallel(|y, row|
+    {
+        let mut plan = Vec::with_capacity(candidates);
+        for (x, pixel) in row.iter_mut().enumerate()
+        {
+            let original = source[(x, y)];
+            let colour = [original.r, original.g, original.b].map(|c| c as f32);
+            let mut error = [0.0f32; 3];
+            plan.clear();
+            for _ in 0..candidates
+            {
+                let wanted: [f32; 3] = std::array::from_fn(|c| (colour[c] + error[c]).clamp(0.0, 255.0));
+                let candidate = quantise(wanted);
+                error = std::array::from_fn(|c| error[c] + colour[c] - [candidate.r, candidate.g, candidate.b][c] as f32);
+                plan.push(candidate);
+            }
+            plan.sort_by_key(|candidate| candidate.brightness());
+            let new = plan[((matrix.at(x, y) * candidates as f32) as usize).min(candidates - 1)];
+            *pixel = Rgba8::rgba(new.r, new.g, new.b, original.a);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// A colourful image with fine detail, the same on every run.
+    fn test_image(width: usize, height: usize) -> Image
+    {
+        let mut image = Image::new(width, height, Rgba8::BLACK);
+        for (i, pixel) in image.pixels.iter_mut().enumerate()
+        {
+            let hash = (i as u32).wrapping_mul(2654435761).rotate_left(13).wrapping_mul(40503);
+            let [r, g, b, _] = hash.to_le_bytes();
+            *pixel = Rgba8::rgb(r / 2 + (i % width) as u8, g, b / 2 + (i / width) as u8);
+        }
+        image
+    }
+
+    fn dither_on_pool(threads: usize, source: &Image, kernel: DiffusionKernel, serpentine: bool) -> Image
+    {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        let mut target = source.clone();
+        let quantise = |wanted: [f32; 3]| { let [r, g, b] = wanted.map(|c| if c < 128.0 {0} else {255}); Rgba8::rgb(r, g, b) };
+        pool.install(|| error_diffusion(source, &mut target, kernel, serpentine, quantise));
+        target
+    }
+
+    #[test]
+    fn threads_give_the_same_result_as_one_thread()
+    {
+        let source = test_image(97, 61);
+        for kernel in [DiffusionKernel::FloydSteinberg, DiffusionKernel::Stucki, DiffusionKernel::JarvisJudiceNinke]
+        {
+            let single = dither_on_pool(1, &source, kernel, false);
+            for threads in [2, 3, 8]
+            {
+                let multi = dither_on_pool(threads, &source, kernel, false);
+                assert!(single.pixels == multi.pixels, "{:?} differs on {} threads", kernel, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn serpentine_gives_the_same_result_on_any_pool()
+    {
+        let source = test_image(97, 61);
+        for kernel in [DiffusionKernel::FloydSteinberg, DiffusionKernel::Stucki, DiffusionKernel::JarvisJudiceNinke]
+        {
+            let single = dither_on_pool(1, &source, kernel, true);
+            assert!(single.pixels != dither_on_pool(1, &source, kernel, false).pixels, "{:?} scans the same both ways", kernel);
+            for threads in [2, 3, 8]
+            {
+                let multi = dither_on_pool(threads, &source, kernel, true);
+                assert!(single.pixels == multi.pixels, "{:?} serpentine differs on {} threads", kernel, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn runs_inside_the_pool()
+    {
+        use rayon::prelude::*;
+        // every pool thread starts a dithering of its own, so the row tasks queue up behind tasks that wait for rows
+        let source = test_image(61, 47);
+        let single = dither_on_pool(1, &source, DiffusionKernel::Stucki, false);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(3).build().unwrap();
+        let results = pool.install(|| (0..12).into_par_iter().map(|_|
+        {
+            let mut target = source.clone();
+            let quantise = |wanted: [f32; 3]| { let [r, g, b] = wanted.map(|c| if c < 128.0 {0} else {255}); Rgba8::rgb(r, g, b) };
+            error_diffusion(&source, &mut target, DiffusionKernel::Stucki, false, quantise);
+            target
+        }).collect::<Vec<_>>());
+        assert!(results.iter().all(|target| target.pixels == single.pixels));
+    }
+
+    #[test]
+    fn threshold_matrices_rank_every_cell_once()
+    {
+        for map in ThresholdMap::ALL
+        {
+            let matrix = map.matrix();
+            let cells = matrix.size * matrix.size;
+            assert_eq!(matrix.thresholds.len(), cells, "{:?}", map);
+            let mut ranks = matrix.thresholds.iter().map(|t| (t * cells as f32 - 0.5).round() as usize).collect::<Vec<_>>();
+            ranks.sort_unstable();
+            assert!(ranks.iter().copied().eq(0..cells), "{:?} is not a permutation of 0..{}", map, cells);
+        }
+        for size in [2, 4, 8, 16]
+        {
+            assert_eq!(ThresholdMatrix::bayer(size).size, size);
+        }
+        let bayer2 = ThresholdMatrix::bayer(2).thresholds.iter().map(|t| (t * 4.0 - 0.5).round() as usize).collect::<Vec<_>>();
+        assert_eq!(bayer2, vec![0, 2, 3, 1]);
+    }
+
+    #[test]
+    fn ordered_dithering_keeps_the_mean_of_flat_greys()
+    {
+        for map in ThresholdMap::ALL
+        {
+            let cells = (map.matrix().size * map.matrix().size) as f32;
+            for levels in [2, 3, 4, 16, 256]
+            {
+                // a matrix can only mix the two levels around a grey in steps of one cell,
+                // so the mean may be off by half such a step, and by a level of the 8 bit output on top
+                let step = 255.0 / (levels - 1) as f32;
+                let tolerance = 1.0 + step / cells / 2.0;
+                for grey in [0, 1, 37, 100, 128, 200, 254, 255]
+                {
+                    // 64 is a multiple of every matrix size, so every threshold shows up equally often
+                    let source = Image::new(64, 64, Rgba8::rgb(grey, grey, grey));
+                    let mut target = source.clone();
+                    source.ordered_dithering(&mut target, levels, map);
+                    let mean = target.pixels.iter().map(|p| p.g as f32).sum::<f32>() / target.pixels.len() as f32;
+                    assert!((mean - grey as f32).abs() <= tolerance, "{:?} to {} levels turns {} into {}", map, levels, grey, mean);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn error_is_signed_and_kept()
+    {
+        // a flat mid grey averages out to the same brightness instead of drifting darker or lighter
+        let source = Image::new(64, 64, Rgba8::rgb(100, 100, 100));
+        for kernel in DiffusionKernel::ALL.into_iter().filter(|&k| k != DiffusionKernel::Atkinson)
+        {
+            let target = dither_on_pool(2, &source, kernel, true);
+            let mean = target.pixels.iter().map(|p| p.r as f32).sum::<f32>() / target.pixels.len() as f32;
+            assert!((mean - 100.0).abs() < 2.0, "{:?} gives a mean of {}", kernel, mean);
+        }
+    }
+}
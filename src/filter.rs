@@ -0,0 +1,941 @@
+use crate::pixel::{Grey8, Rgba8, RgbF32};
+use crate::image::{BorderMode, Image};
+use crate::colour_match::DistanceMetric;
+use crate::dither::{DiffusionKernel, ThresholdMap};
+
+/// An effect that can be used as a stage of the processor chain.
+/// 
+/// Filters are stateless: the values of their parameters are kept by the `Stage` that uses them and passed to `apply` in a `FilterContext`, together with the palette.
+/// To add a filter, implement this trait and add it to `FILTERS`.
+pub trait Filter: Sync
+{
+    /// The name shown in the window and used on the command line, e.g. `GaussianBlur`.
+    fn name(&self) -> &'static str;
+
+    /// A one-line explanation of what the filter does.
+    fn description(&self) -> &'static str;
+
+    /// The values that tune the filter, in the order `apply` receives them.
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &[]
+    }
+
+    /// Reads `source` and writes the filtered image to `target`, which has the same size.
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext);
+
+    /// Whether the pipeline should call `apply_linear` instead of `apply`.
+    /// 
+    /// Filters that average colours, like blurs, should opt in: averaging sRGB encoded values darkens edges and fine detail.
+    fn works_in_linear_light(&self) -> bool
+    {
+        false
+    }
+
+    /// Like `apply`, but on linear-light images, which the pipeline keeps as floats between consecutive linear stages.
+    /// 
+    /// The default encodes to sRGB and calls `apply`, so only filters that opt in need to implement it.
+    fn apply_linear(&self, source: &Image<RgbF32>, target: &mut Image<RgbF32>, context: &FilterContext)
+    {
+        let mut encoded = target.to_srgb();
+        self.apply(&source.to_srgb(), &mut encoded, context);
+        encoded.to_linear_into(target);
+    }
+
+    /// Whether the filter only produces a brightness, so the pipeline should call `apply_grey` instead of `apply`
+    /// and keep the result in one channel.
+    fn writes_grey(&self) -> bool
+    {
+        false
+    }
+
+    /// Like `apply`, but writes to a greyscale image. The default calls `apply` and converts the result.
+    fn apply_grey(&self, source: &Image, target: &mut Image<Grey8>, context: &FilterContext)
+    {
+        let mut colour = source.clone();
+        self.apply(source, &mut colour, context);
+        colour.convert_into(target);
+    }
+}
+
+/// What a filter gets besides the images.
+pub struct FilterContext<'a>
+{
+    /// One value per entry of `Filter::parameters()`, each within its range.
+    pub values: &'a [f32],
+    /// The colours of the pipeline's palette, for filters that reduce the image to a set of colours.
+    pub palette: &'a [Rgba8],
+    /// What kernels read past the edge of the image.
+    pub border: BorderMode,
+}
+
+/// Every available filter, in the order the window cycles through them.
+pub static FILTERS: [&dyn Filter; 22] =
+[
+    &Normal,
+    &Sobel,
+    &SobelColour,
+    &Threshold,
+    &ThresholdColour,
+    &RandomBiasDithering,
+    &OrderedDithering,
+    &OrderedDitheringCustomPalette,
+    &FloydSteinbergDithering,
+    &FloydSteinbergDitheringCustomPalette,
+    &ErrorDiffusionDithering,
+    &ErrorDiffusionDitheringCustomPalette,
+    &GaussianBlur,
+    &GaussianBlurSigma,
+    &BoxBlur,
+    &Emboss,
+    &Outline,
+    &GreyScale,
+    &ChromaticAberration,
+    &Sharpen,
+    &SharpenColour,
+    &CrossBlur,
+];
+
+/// Filters that were folded into others, by their old name, so that old presets and command lines still work.
+/// `PatternedDithering` was an 8x8 Bayer ordered dither, which is `OrderedDithering` with its default map.
+const FORMER_NAMES: [(&str, &dyn Filter); 1] = [("PatternedDithering", &OrderedDithering)];
+
+/// Looks a filter up by its name, ignoring case. The names in `FORMER_NAMES` find the filter that replaced them.
+pub fn find(name: &str) -> Option<&'static dyn Filter>
+{
+    let name = name.trim();
+    FILTERS.iter().copied().find(|f| f.name().eq_ignore_ascii_case(name))
+        .or_else(|| FORMER_NAMES.iter().find(|(former, _)| former.eq_ignore_ascii_case(name)).map(|&(_, filter)| filter))
+}
+
+/// Turns `parameter` of a filter in `FORMER_NAMES` into the index and value of a parameter of the filter that replaced it.
+pub fn former_parameter(name: &str, parameter: &str, value: f32) -> Option<(usize, f32)>
+{
+    if name.trim().eq_ignore_ascii_case("PatternedDithering") && parameter.eq_ignore_ascii_case("bits")
+    {
+        // n bits per channel are 2^n levels
+        return Some((0, (1u32 << value.clamp(1.0, 8.0) as u32) as f32));
+    }
+    None
+}
+
+/// The position of `filter` in `FILTERS`.
+pub fn index_of(filter: &dyn Filter) -> usize
+{
+    FILTERS.iter().position(|f| f.name() == filter.name()).unwrap_or(0)
+}
+
+const LEVEL: [Parameter; 1] = [Parameter::integer("level", 0, 255, 1, 128)];
+const BITS: [Parameter; 1] = [Parameter::integer("bits", 1, 8, 1, 1)];
+const SIZE: [Parameter; 1] = [Parameter::integer("size", 3, 49, 2, 9)];
+const OFFSET: [Parameter; 1] = [Parameter::integer("offset", 0, 20, 1, 6)];
+const SIGMA: [Parameter; 1] = [Parameter::float("sigma", 0.3, 30.0, 2.0)];
+const DIFFUSION_KERNEL: Parameter = Parameter::choice("kernel", &DiffusionKernel::NAMES, 0);
+const SCAN: Parameter = Parameter::choice("scan", &["raster", "serpentine"], 0);
+const ERROR_DIFFUSION: [Parameter; 3] = [BITS[0], DIFFUSION_KERNEL, SCAN];
+const THRESHOLD_MAP: Parameter = Parameter::choice("map", &ThresholdMap::NAMES, 2);
+const ORDERED: [Parameter; 2] = [Parameter::integer("levels", 2, 256, 1, 2), THRESHOLD_MAP];
+const METRIC: Parameter = Parameter::choice("metric", &DistanceMetric::NAMES, 0);
+const ORDERED_PALETTE: [Parameter; 2] = [THRESHOLD_MAP, METRIC];
+const FLOYD_STEINBERG_PALETTE: [Parameter; 1] = [METRIC];
+const ERROR_DIFFUSION_PALETTE: [Parameter; 3] = [DIFFUSION_KERNEL, SCAN, METRIC];
+
+pub struct Normal;
+
+impl Filter for Normal
+{
+    fn name(&self) -> &'static str
+    {
+        "Normal"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Shows the input unchanged."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, _context: &FilterContext)
+    {
+        target.pixels.copy_from_slice(&source.pixels);
+    }
+}
+
+pub struct Sobel;
+
+impl Filter for Sobel
+{
+    fn name(&self) -> &'static str
+    {
+        "Sobel"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Sobel edge detection on the brightness."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.sobel_edge_detection_3x3(target, context.border);
+    }
+
+    fn writes_grey(&self) -> bool
+    {
+        true
+    }
+
+    fn apply_grey(&self, source: &Image, target: &mut Image<Grey8>, context: &FilterContext)
+    {
+        source.sobel_edge_detection_3x3(target, context.border);
+    }
+}
+
+pub struct SobelColour;
+
+impl Filter for SobelColour
+{
+    fn name(&self) -> &'static str
+    {
+        "SobelColour"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Sobel edge detection on each colour channel."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.sobel_edge_detection_3x3_colour(target, context.border);
+    }
+}
+
+pub struct Threshold;
+
+impl Filter for Threshold
+{
+    fn name(&self) -> &'static str
+    {
+        "Threshold"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Black where the brightness is below the level, white elsewhere."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &LEVEL
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.threshold(target, context.values[0] as u8);
+    }
+
+    fn writes_grey(&self) -> bool
+    {
+        true
+    }
+
+    fn apply_grey(&self, source: &Image, target: &mut Image<Grey8>, context: &FilterContext)
+    {
+        source.threshold(target, context.values[0] as u8);
+    }
+}
+
+pub struct ThresholdColour;
+
+impl Filter for ThresholdColour
+{
+    fn name(&self) -> &'static str
+    {
+        "ThresholdColour"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Thresholds each colour channel at the level."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &LEVEL
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.threshold_colour(target, context.values[0] as u8);
+    }
+}
+
+pub struct RandomBiasDithering;
+
+impl Filter for RandomBiasDithering
+{
+    fn name(&self) -> &'static str
+    {
+        "RandomBiasDithering"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Reduces each channel to a number of bits, adding random noise before rounding."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &BITS
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.random_bias_dithering(target, context.values[0] as usize);
+    }
+}
+
+pub struct OrderedDithering;
+
+impl Filter for OrderedDithering
+{
+    fn name(&self) -> &'static str
+    {
+        "OrderedDithering"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Reduces each channel to a number of levels with a Bayer, clustered-dot or blue-noise threshold map."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &ORDERED
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.ordered_dithering(target, context.values[0] as usize, ThresholdMap::ALL[context.values[1] as usize]);
+    }
+}
+
+pub struct OrderedDitheringCustomPalette;
+
+impl Filter for OrderedDitheringCustomPalette
+{
+    fn name(&self) -> &'static str
+    {
+        "OrderedDitheringCustomPalette"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Ordered dithering to the colours of the palette, with a choice of threshold maps."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &ORDERED_PALETTE
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        let metric = DistanceMetric::ALL[context.values[1] as usize];
+        source.ordered_dithering_with_palette(target, context.palette, ThresholdMap::ALL[context.values[0] as usize], metric);
+    }
+}
+
+pub struct FloydSteinbergDithering;
+
+impl Filter for FloydSteinbergDithering
+{
+    fn name(&self) -> &'static str
+    {
+        "FloydSteinbergDithering"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Reduces each channel to a number of bits with Floyd-Steinberg error diffusion."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &BITS
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.floyd_steinberg_dithering(target, context.values[0] as usize);
+    }
+}
+
+pub struct FloydSteinbergDitheringCustomPalette;
+
+impl Filter for FloydSteinbergDitheringCustomPalette
+{
+    fn name(&self) -> &'static str
+    {
+        "FloydSteinbergDitheringCustomPalette"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Floyd-Steinberg error diffusion to the colours of the palette."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &FLOYD_STEINBERG_PALETTE
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.floyd_steinberg_with_custom_colour_palette(target, context.palette, DistanceMetric::ALL[context.values[0] as usize]);
+    }
+}
+
+pub struct ErrorDiffusionDithering;
+
+impl Filter for ErrorDiffusionDithering
+{
+    fn name(&self) -> &'static str
+    {
+        "ErrorDiffusionDithering"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Reduces each channel to a number of bits with a choice of error diffusion kernels."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &ERROR_DIFFUSION
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        let kernel = DiffusionKernel::ALL[context.values[1] as usize];
+        source.error_diffusion_dithering(target, context.values[0] as usize, kernel, context.values[2] == 1.0);
+    }
+}
+
+pub struct ErrorDiffusionDitheringCustomPalette;
+
+impl Filter for ErrorDiffusionDitheringCustomPalette
+{
+    fn name(&self) -> &'static str
+    {
+        "ErrorDiffusionDitheringCustomPalette"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Error diffusion to the colours of the palette, with a choice of kernels."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &ERROR_DIFFUSION_PALETTE
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        let kernel = DiffusionKernel::ALL[context.values[0] as usize];
+        let metric = DistanceMetric::ALL[context.values[2] as usize];
+        source.error_diffusion_with_palette(target, context.palette, kernel, context.values[1] == 1.0, metric);
+    }
+}
+
+pub struct GaussianBlur;
+
+impl Filter for GaussianBlur
+{
+    fn name(&self) -> &'static str
+    {
+        "GaussianBlur"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "3x3 Gaussian blur."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.gaussian_blur_3x3(target, context.border);
+    }
+
+    fn works_in_linear_light(&self) -> bool
+    {
+        true
+    }
+
+    fn apply_linear(&self, source: &Image<RgbF32>, target: &mut Image<RgbF32>, context: &FilterContext)
+    {
+        source.gaussian_blur_3x3(target, context.border);
+    }
+}
+
+pub struct GaussianBlurSigma;
+
+impl Filter for GaussianBlurSigma
+{
+    fn name(&self) -> &'static str
+    {
+        "GaussianBlurSigma"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Gaussian blur of any strength, set by the standard deviation in pixels."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &SIGMA
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.gaussian_blur(target, context.values[0], context.border);
+    }
+
+    fn works_in_linear_light(&self) -> bool
+    {
+        true
+    }
+
+    fn apply_linear(&self, source: &Image<RgbF32>, target: &mut Image<RgbF32>, context: &FilterContext)
+    {
+        source.gaussian_blur(target, context.values[0], context.border);
+    }
+}
+
+pub struct BoxBlur;
+
+impl Filter for BoxBlur
+{
+    fn name(&self) -> &'static str
+    {
+        "BoxBlur"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Averages a square of pixels around each pixel."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &SIZE
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.box_blur(target, box_blur_size(source.width, source.height, context), context.border);
+    }
+
+    fn works_in_linear_light(&self) -> bool
+    {
+        true
+    }
+
+    fn apply_linear(&self, source: &Image<RgbF32>, target: &mut Image<RgbF32>, context: &FilterContext)
+    {
+        source.box_blur(target, box_blur_size(source.width, source.height, context), context.border);
+    }
+}
+
+/// The kernel size of `BoxBlur`, limited so that the kernel fits into the image.
+fn box_blur_size(width: usize, height: usize, context: &FilterContext) -> usize
+{
+    (context.values[0] as usize).min(((width.min(height) / 2) * 2).saturating_sub(1).max(1)).max(1)
+}
+
+pub struct Emboss;
+
+impl Filter for Emboss
+{
+    fn name(&self) -> &'static str
+    {
+        "Emboss"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Makes the image look like it was pressed into metal."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.emboss(target, context.border);
+    }
+}
+
+pub struct Outline;
+
+impl Filter for Outline
+{
+    fn name(&self) -> &'static str
+    {
+        "Outline"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Highlights the outlines of shapes."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.outline(target, context.border);
+    }
+}
+
+pub struct GreyScale;
+
+impl Filter for GreyScale
+{
+    fn name(&self) -> &'static str
+    {
+        "GreyScale"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Converts the image to shades of grey."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, _context: &FilterContext)
+    {
+        source.greyscale(target);
+    }
+
+    fn writes_grey(&self) -> bool
+    {
+        true
+    }
+
+    fn apply_grey(&self, source: &Image, target: &mut Image<Grey8>, _context: &FilterContext)
+    {
+        source.greyscale(target);
+    }
+}
+
+pub struct ChromaticAberration;
+
+impl Filter for ChromaticAberration
+{
+    fn name(&self) -> &'static str
+    {
+        "ChromaticAberration"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Shifts the red and blue channels diagonally in opposite directions."
+    }
+
+    fn parameters(&self) -> &'static [Parameter]
+    {
+        &OFFSET
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.chromatic_aberration(target, (context.values[0] as usize).min(source.width.min(source.height)));
+    }
+}
+
+pub struct Sharpen;
+
+impl Filter for Sharpen
+{
+    fn name(&self) -> &'static str
+    {
+        "Sharpen"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Sharpens the brightness, giving a grey image."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.sharpen(target, context.border);
+    }
+
+    fn writes_grey(&self) -> bool
+    {
+        true
+    }
+
+    fn apply_grey(&self, source: &Image, target: &mut Image<Grey8>, context: &FilterContext)
+    {
+        source.sharpen(target, context.border);
+    }
+}
+
+pub struct SharpenColour;
+
+impl Filter for SharpenColour
+{
+    fn name(&self) -> &'static str
+    {
+        "SharpenColour"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Sharpens each colour channel."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.sharpen_colour(target, context.border);
+    }
+}
+
+pub struct CrossBlur;
+
+impl Filter for CrossBlur
+{
+    fn name(&self) -> &'static str
+    {
+        "CrossBlur"
+    }
+
+    fn description(&self) -> &'static str
+    {
+        "Blurs with a cross shaped kernel."
+    }
+
+    fn apply(&self, source: &Image, target: &mut Image, context: &FilterContext)
+    {
+        source.cross_blur(target, context.border);
+    }
+
+    fn writes_grey(&self) -> bool
+    {
+        true
+    }
+
+    fn apply_grey(&self, source: &Image, target: &mut Image<Grey8>, context: &FilterContext)
+    {
+        source.cross_blur(target, context.border);
+    }
+}
+
+/// Whether a parameter only takes whole numbers, any number, or one of a list of options.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ParameterType
+{
+    Integer,
+    Float,
+    /// The value is the index of the chosen option.
+    Choice(&'static [&'static str]),
+}
+
+/// A named value that tunes a processor, with its range and default.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Parameter
+{
+    pub name: &'static str,
+    pub kind: ParameterType,
+    pub min: f32,
+    pub max: f32,
+    /// Values are snapped to `min + n * step`. A step of 0 allows any value in the range.
+    pub step: f32,
+    pub default: f32,
+}
+
+impl Parameter
+{
+    pub const fn integer(name: &'static str, min: i32, max: i32, step: i32, default: i32) -> Self
+    {
+        Self{name, kind: ParameterType::Integer, min: min as f32, max: max as f32, step: step as f32, default: default as f32}
+    }
+
+    pub const fn float(name: &'static str, min: f32, max: f32, default: f32) -> Self
+    {
+        Self{name, kind: ParameterType::Float, min, max, step: 0.0, default}
+    }
+
+    pub const fn choice(name: &'static str, options: &'static [&'static str], default: usize) -> Self
+    {
+        Self{name, kind: ParameterType::Choice(options), min: 0.0, max: (options.len() - 1) as f32, step: 1.0, default: default as f32}
+    }
+
+    /// Clamps `value` to the range and snaps it to the step size.
+    pub fn constrain(&self, value: f32) -> f32
+    {
+        let value = value.clamp(self.min, self.max);
+        if self.step <= 0.0
+        {
+            return value;
+        }
+        let steps = ((value - self.min) / self.step).round();
+        (self.min + steps * self.step).min(self.max)
+    }
+
+    /// Where `value` lies in the range, from 0 at `min` to 1 at `max`.
+    pub fn fraction(&self, value: f32) -> f32
+    {
+        if self.max <= self.min
+        {
+            return 0.0;
+        }
+        ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0)
+    }
+
+    /// The inverse of `fraction`, constrained to a valid value.
+    pub fn from_fraction(&self, fraction: f32) -> f32
+    {
+        self.constrain(self.min + fraction * (self.max - self.min))
+    }
+
+    /// Formats `value` for display, without decimals for integer parameters and as the option's name for choices.
+    pub fn format(&self, value: f32) -> String
+    {
+        match self.kind
+        {
+            ParameterType::Integer => format!("{}", value as i32),
+            ParameterType::Float => format!("{:.2}", value),
+            ParameterType::Choice(options) => options[self.constrain(value) as usize].to_string(),
+        }
+    }
+
+    /// Reads a value written by hand or by `to_text`: a number, or for choices also the name of an option, ignoring case.
+    pub fn parse(&self, text: &str) -> Option<f32>
+    {
+        if let ParameterType::Choice(options) = self.kind
+        {
+            if let Some(index) = options.iter().position(|option| option.eq_ignore_ascii_case(text))
+            {
+                return Some(index as f32);
+            }
+        }
+        text.parse().ok()
+    }
+
+    /// Writes `value` so that `parse` reads it back exactly.
+    pub fn to_text(&self, value: f32) -> String
+    {
+        match self.kind
+        {
+            ParameterType::Choice(_) => self.format(value),
+            _ => value.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn box_blur_fits_tiny_images()
+    {
+        for (width, height) in [(1, 1), (1, 7), (7, 1), (2, 2), (3, 3)]
+        {
+            let source = Image::new(width, height, Rgba8::rgb(10, 200, 90));
+            let mut target = source.clone();
+            let values = [49.0];
+            let context = FilterContext{values: &values, palette: &[], border: BorderMode::Clamp};
+            assert!(box_blur_size(width, height, &context) <= width.min(height).max(1));
+            BoxBlur.apply(&source, &mut target, &context);
+            BoxBlur.apply_linear(&source.to_linear(), &mut source.to_linear(), &context);
+            assert!(target.pixels == source.pixels);
+        }
+    }
+
+    /// Every parameter of every filter, and one without a range.
+    fn all_parameters() -> Vec<Parameter>
+    {
+        let mut parameters: Vec<Parameter> = FILTERS.iter().flat_map(|f| f.parameters().iter().copied()).collect();
+        parameters.push(Parameter::integer("fixed", 4, 4, 1, 4));
+        parameters
+    }
+
+    #[test]
+    fn constrained_values_are_in_range_and_on_a_step()
+    {
+        for parameter in all_parameters()
+        {
+            for i in -40..=340
+            {
+                let value = parameter.constrain(parameter.min - 10.0 + i as f32 * (parameter.max - parameter.min + 20.0) / 300.0);
+                assert!(value >= parameter.min && value <= parameter.max, "{} {}", parameter.name, value);
+                if parameter.step > 0.0
+                {
+                    let steps = (value - parameter.min) / parameter.step;
+                    assert!((steps - steps.round()).abs() < 1e-4, "{} {}", parameter.name, value);
+                }
+                assert_eq!(parameter.constrain(value), value, "{}", parameter.name);
+            }
+        }
+        assert_eq!([0.0, 3.0, 4.0, 10.0, 11.9, 48.0, 100.0].map(|v| SIZE[0].constrain(v)), [3.0, 3.0, 5.0, 11.0, 11.0, 49.0, 49.0]);
+        assert_eq!([0.0, 0.25, 29.0, 31.0].map(|v| SIGMA[0].constrain(v)), [0.3, 0.3, 29.0, 30.0]);
+        assert_eq!([-1.0, 1.4, 1.6, 9.0].map(|v| THRESHOLD_MAP.constrain(v)), [0.0, 1.0, 2.0, 5.0]);
+    }
+
+    #[test]
+    fn fractions_round_trip()
+    {
+        for parameter in all_parameters()
+        {
+            assert_eq!(parameter.from_fraction(0.0), parameter.min);
+            assert_eq!(parameter.from_fraction(1.0), parameter.max);
+            assert_eq!((parameter.fraction(parameter.min - 1.0), parameter.fraction(parameter.max + 1.0)), (0.0, if parameter.max > parameter.min {1.0} else {0.0}));
+            for i in 0..=200
+            {
+                let fraction = i as f32 / 200.0;
+                let value = parameter.from_fraction(fraction);
+                assert_eq!(value, parameter.constrain(value), "{}", parameter.name);
+                assert!((parameter.from_fraction(parameter.fraction(value)) - value).abs() <= 1e-4 * parameter.max.abs().max(1.0), "{} {}", parameter.name, value);
+                if parameter.max > parameter.min
+                {
+                    // snapping moves the value by at most half a step
+                    let slack = parameter.step / (parameter.max - parameter.min) / 2.0 + 1e-5;
+                    assert!((parameter.fraction(value) - fraction).abs() <= slack, "{} {}", parameter.name, fraction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn text_round_trips_every_kind_of_parameter()
+    {
+        for parameter in all_parameters()
+        {
+            for i in 0..=100
+            {
+                let value = parameter.from_fraction(i as f32 / 100.0);
+                assert_eq!(parameter.parse(&parameter.to_text(value)), Some(value), "{} {}", parameter.name, value);
+            }
+        }
+        assert_eq!((SIZE[0].to_text(15.0), SIZE[0].parse("15")), ("15".to_string(), Some(15.0)));
+        assert_eq!(SIGMA[0].parse(&SIGMA[0].to_text(0.1 + 0.2)), Some(0.1 + 0.2));
+        assert_eq!(SIGMA[0].parse("two"), None);
+        assert_eq!((THRESHOLD_MAP.to_text(3.0), THRESHOLD_MAP.parse("bayer16"), THRESHOLD_MAP.parse("1")), ("Bayer16".to_string(), Some(3.0), Some(1.0)));
+        assert_eq!(SCAN.parse("zigzag"), None);
+    }
+
+    #[test]
+    fn former_names_find_the_filter_that_replaced_them()
+    {
+        assert_eq!(find(" patterneddithering").map(|f| f.name()), Some("OrderedDithering"));
+        assert!(FILTERS.iter().all(|f| !f.name().eq_ignore_ascii_case("PatternedDithering")));
+
+        let pipeline = crate::preset::parse("stage = PatternedDithering bits=3").unwrap();
+        assert_eq!(pipeline.stages[0].filter.name(), "OrderedDithering");
+        assert_eq!(pipeline.stages[0].values, vec![8.0, THRESHOLD_MAP.default]);
+        assert_eq!(ThresholdMap::ALL[THRESHOLD_MAP.default as usize], ThresholdMap::Bayer8);
+        assert!(crate::preset::parse("stage = OrderedDithering bits=3").is_err());
+    }
+}
@@ -1,17 +1,178 @@
-use olc_pge as olc;
+use crate::pixel::{Pixel, Rgba8, RgbF32};
 use crate::pixel_traits::*;
+use crate::kernel::Kernel;
+use crate::integral::IntegralImage;
+use rayon::prelude::*;
+use crate::colour_match::{ColourMatcher, DistanceMetric};
+use crate::dither::{error_diffusion, ordered_dithering, pattern_dithering, DiffusionKernel, ThresholdMap};
 
+/// A grid of pixels of type `P`, stored row by row.
+/// 
+/// Most processing works on `Rgba8`, the default. Effects that only produce a brightness can also write to an `Image<Grey8>`,
+/// and `RgbF32` keeps intermediate results from being rounded to 8 bits. `convert` changes between pixel types.
 #[derive(Clone)]
-pub struct Image
+pub struct Image<P = Rgba8>
 {
     pub width:usize,
     pub height:usize,
-    pub pixels: Vec<olc::Pixel>
+    pub pixels: Vec<P>
 }
 
-impl std::ops::Index<(usize, usize)> for Image
+/// The interpolation used by `Image::resize`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ResampleFilter
 {
-    type Output = olc::Pixel;
+    Nearest,
+    Bilinear,
+    /// Catmull-Rom spline.
+    Bicubic,
+    /// Windowed sinc with three lobes.
+    Lanczos3,
+}
+
+impl ResampleFilter
+{
+    /// How far the filter reaches, in source pixels at a scale of 1.
+    fn support(&self) -> f32
+    {
+        match self
+        {
+            ResampleFilter::Nearest => 0.5,
+            ResampleFilter::Bilinear => 1.0,
+            ResampleFilter::Bicubic => 2.0,
+            ResampleFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32
+    {
+        let x = x.abs();
+        match self
+        {
+            ResampleFilter::Nearest => if x < 0.5 {1.0} else {0.0},
+            ResampleFilter::Bilinear => (1.0 - x).max(0.0),
+            ResampleFilter::Bicubic =>
+            {
+                if x < 1.0 {1.5 * x * x * x - 2.5 * x * x + 1.0}
+                else if x < 2.0 {-0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0}
+                else {0.0}
+            }
+            ResampleFilter::Lanczos3 =>
+            {
+                if x < 1e-6 {1.0}
+                else if x < 3.0
+                {
+                    let pi_x = std::f32::consts::PI * x;
+                    3.0 * pi_x.sin() * (pi_x / 3.0).sin() / (pi_x * pi_x)
+                }
+                else {0.0}
+            }
+        }
+    }
+}
+
+/// What `Image::fit` does when the aspect ratios of the image and the target size differ.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FitMode
+{
+    /// Scale both axes independently, distorting the image.
+    Stretch,
+    /// Show the whole image and fill the remaining space with black bars.
+    Letterbox,
+    /// Fill the whole target and cut off what sticks out.
+    Crop,
+}
+
+/// What a kernel reads where it reaches past the edge of the image.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BorderMode
+{
+    /// Repeat the nearest edge pixel.
+    Clamp,
+    /// Reflect the image at its edge, without repeating the edge pixel.
+    Mirror,
+    /// Continue from the opposite edge, as if the image was tiled.
+    Wrap,
+    /// Read a fixed colour.
+    Constant(Rgba8),
+    /// Leave out the taps outside the image and scale the rest up to the full weight of the kernel.
+    Crop,
+}
+
+impl BorderMode
+{
+    pub const ALL: [BorderMode; 5] = [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Constant(Rgba8::BLACK), BorderMode::Crop];
+
+    /// Accepts the names printed by `name`, e.g. `mirror` or `constant #ff8000`. `constant` on its own is black.
+    pub fn from_name(name: &str) -> Option<BorderMode>
+    {
+        let mut words = name.split_whitespace();
+        let mode = match words.next()?.to_ascii_lowercase().as_str()
+        {
+            "clamp" => BorderMode::Clamp,
+            "mirror" => BorderMode::Mirror,
+            "wrap" => BorderMode::Wrap,
+            "crop" => BorderMode::Crop,
+            "constant" => BorderMode::Constant(words.next().map_or(Some(Rgba8::BLACK), crate::palette::parse_hex)?),
+            _ => return None,
+        };
+        if words.next().is_some()
+        {
+            return None;
+        }
+        Some(mode)
+    }
+
+    pub fn name(&self) -> String
+    {
+        match self
+        {
+            BorderMode::Clamp => "clamp".to_string(),
+            BorderMode::Mirror => "mirror".to_string(),
+            BorderMode::Wrap => "wrap".to_string(),
+            BorderMode::Constant(colour) => format!("constant {}", crate::palette::to_hex(*colour)),
+            BorderMode::Crop => "crop".to_string(),
+        }
+    }
+
+    /// The position of the mode in `ALL`. Every constant colour counts as the `Constant` entry.
+    pub fn index(&self) -> usize
+    {
+        BorderMode::ALL.iter().position(|m| std::mem::discriminant(m) == std::mem::discriminant(self)).unwrap_or(0)
+    }
+
+    /// Maps the coordinate `i` on an axis of length `size` into the image.
+    /// Returns `None` if the tap doesn't read the image, which is always the case for `Constant` and `Crop`.
+    pub fn resolve(&self, i: isize, size: usize) -> Option<usize>
+    {
+        let size = size as isize;
+        if (0..size).contains(&i)
+        {
+            return Some(i as usize);
+        }
+        match self
+        {
+            BorderMode::Clamp => Some(i.clamp(0, size - 1) as usize),
+            BorderMode::Mirror =>
+            {
+                if size == 1
+                {
+                    return Some(0);
+                }
+                // reflecting repeats with a period of two image lengths, minus the edge pixels that aren't repeated
+                let period = 2 * (size - 1);
+                let i = i.rem_euclid(period);
+                Some(if i < size {i} else {period - i} as usize)
+            }
+            BorderMode::Wrap => Some(i.rem_euclid(size) as usize),
+            BorderMode::Constant(_) | BorderMode::Crop => None,
+        }
+    }
+}
+
+impl<P> std::ops::Index<(usize, usize)> for Image<P>
+{
+    type Output = P;
     fn index(&self, index: (usize, usize)) -> &Self::Output
     {
         debug_assert!(index.0 < self.width && index.1 < self.height, "Can't index Image with invalid coordinates.");
@@ -19,7 +180,7 @@ impl std::ops::Index<(usize, usize)> for Image
     }
 }
 
-impl std::ops::IndexMut<(usize, usize)> for Image
+impl<P> std::ops::IndexMut<(usize, usize)> for Image<P>
 {
     fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output
     {
@@ -29,12 +190,149 @@ impl std::ops::IndexMut<(usize, usize)> for Image
 }
 
 
+impl<P: Pixel> Image<P>
+{
+    /// Creates an image filled with `pixel`.
+    pub fn new(width: usize, height: usize, pixel: P) -> Self
+    {
+        Image{width, height, pixels: vec![pixel; width * height]}
+    }
+
+    /// Returns a copy of the image with every pixel converted to `Q`.
+    pub fn convert<Q: Pixel>(&self) -> Image<Q>
+    {
+        Image{width: self.width, height: self.height, pixels: self.pixels.par_iter().map(|&p| p.convert()).collect()}
+    }
+
+    /// Like `convert`, but reuses `target`, which must have the same size.
+    pub fn convert_into<Q: Pixel>(&self, target: &mut Image<Q>)
+    {
+        target.pixels.par_iter_mut().zip(&self.pixels).for_each(|(converted, &pixel)| *converted = pixel.convert());
+    }
+
+    /// Returns a copy of the image scaled to `width` by `height`.
+    /// 
+    /// Apart from `Nearest`, the filters are applied separably (first horizontally, then vertically)
+    /// and widened when shrinking, so downscaled images don't alias.
+    pub fn resize(&self, width: usize, height: usize, filter: ResampleFilter) -> Image<P>
+    {
+        if width == self.width && height == self.height
+        {
+            return self.clone();
+        }
+        if filter == ResampleFilter::Nearest
+        {
+            let mut pixels = Vec::with_capacity(width * height);
+            for y in 0..height
+            {
+                let source_y = y * self.height / height;
+                for x in 0..width
+                {
+                    let source_x = x * self.width / width;
+                    pixels.push(self[(source_x, source_y)]);
+                }
+            }
+            return Image{width, height, pixels};
+        }
+
+        let source = self.pixels.iter().map(|p| p.to_rgba()).collect::<Vec<[f32; 4]>>();
+
+        let horizontal_taps = resample_taps(self.width, width, filter);
+        let mut horizontal = vec![[0.0; 4]; width * self.height];
+        for y in 0..self.height
+        {
+            let row = &source[y * self.width..(y + 1) * self.width];
+            for (x, taps) in horizontal_taps.iter().enumerate()
+            {
+                horizontal[y * width + x] = weighted_sum(taps.iter().map(|&(i, w)| (&row[i], w)));
+            }
+        }
+
+        let vertical_taps = resample_taps(self.height, height, filter);
+        let mut pixels = Vec::with_capacity(width * height);
+        for taps in &vertical_taps
+        {
+            for x in 0..width
+            {
+                pixels.push(P::from_rgba(weighted_sum(taps.iter().map(|&(i, w)| (&horizontal[i * width + x], w)))));
+            }
+        }
+        Image{width, height, pixels}
+    }
+
+    /// Scales the image to `width` by `height` without distorting it, see `FitMode`.
+    pub fn fit(&self, width: usize, height: usize, fit_mode: FitMode, filter: ResampleFilter) -> Image<P>
+    {
+        if fit_mode == FitMode::Stretch || self.width * height == self.height * width
+        {
+            return self.resize(width, height, filter);
+        }
+        let is_wider = self.width * height > self.height * width;
+        let (scaled_width, scaled_height) = if is_wider == (fit_mode == FitMode::Letterbox)
+        {
+            (width, (self.height * width / self.width).max(1))
+        }
+        else
+        {
+            ((self.width * height / self.height).max(1), height)
+        };
+        let scaled = self.resize(scaled_width, scaled_height, filter);
+
+        // offset of the scaled image inside the output, negative if it gets cropped
+        let left = (width as isize - scaled_width as isize) / 2;
+        let top = (height as isize - scaled_height as isize) / 2;
+        let mut output = Image::new(width, height, P::from_rgba([0.0, 0.0, 0.0, 1.0]));
+        for y in 0..height
+        {
+            let source_y = y as isize - top;
+            if source_y < 0 || source_y >= scaled_height as isize
+            {
+                continue;
+            }
+            for x in 0..width
+            {
+                let source_x = x as isize - left;
+                if source_x >= 0 && source_x < scaled_width as isize
+                {
+                    output[(x, y)] = scaled[(source_x as usize, source_y as usize)];
+                }
+            }
+        }
+        output
+    }
+
+}
+
 impl Image
 {
+    /// Decodes the sRGB pixels to linear light, see `RgbF32::from_srgb`.
+    pub fn to_linear(&self) -> Image<RgbF32>
+    {
+        let mut linear = Image::new(self.width, self.height, RgbF32::default());
+        self.to_linear_into(&mut linear);
+        linear
+    }
+
+    /// Like `to_linear`, but reuses `target`, which must have the same size.
+    pub fn to_linear_into(&self, target: &mut Image<RgbF32>)
+    {
+        target.pixels.par_iter_mut().zip(&self.pixels).for_each(|(linear, &pixel)| *linear = RgbF32::from_srgb(pixel));
+    }
+
+    /// Like `fit`, but scales in linear light, so that fine detail keeps its brightness instead of getting darker.
+    pub fn fit_linear(&self, width: usize, height: usize, fit_mode: FitMode, filter: ResampleFilter) -> Image
+    {
+        if (width, height) == (self.width, self.height) || filter == ResampleFilter::Nearest
+        {
+            return self.fit(width, height, fit_mode, filter);
+        }
+        self.to_linear().fit(width, height, fit_mode, filter).to_srgb()
+    }
+
     /// **Not** a [mathematical convolution].
     /// 
     /// 
-    /// This function applies one [kernel] to an image and should be paired with the [handle_edges] function.
+    /// This function applies one [kernel] to every pixel of an image, reading past the edges as `border` says.
     /// 
     /// The kernel is applied to each channel, resulting in colourful images.
     /// 
@@ -44,36 +342,22 @@ impl Image
     /// # 
     /// `target: &mut Image` is a mutable reference to the target Image that the result of the convolution will be written to.
     /// # 
-    /// `kernel_size`. The convolve function assumes a square shaped kernel.
-    /// `kernel_size` should be equal to the width or height of the kernel. Or given an example kernel `[0,0,0, 0,0,0, 0,0,0]` with `9` elements, 
-    /// `kernel_size` should be equal to `sqrt(9)`. 
-    /// # 
-    /// `kernel_generator`. It is a function object that takes in 
-    /// `kernel_size: usize, (kernel_x: usize, kernel_y: usize)`
-    /// and returns an `i32`, representing an integer multiple of `1/denominator`.
+    /// `kernel`. The weights, of any odd width and height. They are used as they are, so a kernel that should keep
+    /// the brightness of the image has to add up to 1, see `Kernel::normalised`.
+    /// Separable kernels are run as a horizontal and a vertical pass, see `Kernel::separate`.
     /// # 
-    /// `denominator`. It can be thought of as the "divisor". Since convolve only uses integers, 
-    /// fractions are represented by multiplying the kernel values with 1/denominator. 
+    /// `border`. What the kernel reads where it reaches past the edge of the image, see `BorderMode`.
     /// 
-    /// Hence a kernel `[2,2,2, 4,4,4, 2,2,2]`, combined with the denominator equal to `4`, 
-    /// works the same as applying this kernel with these floating point values:
-    /// 
-    /// `[0.5,0.5,0.5, 1.0,1.0,1.0, 0.5,0.5,0.5]`.
+    /// The sums are kept as floats until the end, then rounded and clamped to 8 bits.
     /// 
     /// ## Example
     /// ```rust
-    /// pub fn box_blur(&self, target: &mut Image)
+    /// pub fn box_blur(&self, target: &mut Image, border: BorderMode)
     /// {
-    ///     self.convolve(target, 3, |s,(x,y)| 1, 9); // here kernel_generator always returns 1
-    ///     // self.handle_edges(target, 3,
-    ///     // |img, _s, (x,y)|
-    ///     // img[(x,y)]
-    ///     // );
+    ///     self.convolve(target, &Kernel::from_fn(3, 3, |_x, _y| 1.0 / 9.0), border); // here every weight is 1/9
     /// }
     /// ```
     /// 
-    /// Returning `1` with the `denominator` being `9` has the effect of multiplying the pixel with `1/9`.
-    /// 
     /// ## When to use
     /// 
     /// This function gets rid of a lot of redundancy for image processing effects that:
@@ -85,95 +369,169 @@ impl Image
     /// 
     /// [kernel]: https://en.wikipedia.org/wiki/Kernel_(image_processing)
     /// [mathematical convolution]: https://en.wikipedia.org/wiki/Convolution
-    pub fn convolve<F>(&self, target: &mut Image, kernel_size: usize, mut kernel_generator: F, denominator: i32) where F: FnMut(usize, (usize, usize)) -> i32
+    pub fn convolve(&self, target: &mut Image, kernel: &Kernel, border: BorderMode)
     {
-        if denominator == 0 {panic!("In function convolve of Image, denominator may not equal 0.");}
-        for y in kernel_size/2..self.height - kernel_size/2
-        {
-            for x in kernel_size/2..self.width - kernel_size/2
-            {
-                let (mut r,mut g,mut b) = (0, 0, 0);
-                for kernel_y in 0..kernel_size
-                {
-                    for kernel_x in 0..kernel_size
-                    {
-                        let kernel_value = kernel_generator(kernel_size, (kernel_x, kernel_y));
-                        let pixel = self[(x - kernel_size/2 + kernel_x, y - kernel_size/2 + kernel_y)];
-                        r += pixel.r as i32 * kernel_value;
-                        g += pixel.g as i32 * kernel_value;
-                        b += pixel.b as i32 * kernel_value;
-                    }
-                }
-                r /= denominator;
-                g /= denominator;
-                b /= denominator;
-                let r = r.min(255).max(0) as u8;
-                let g = g.min(255).max(0) as u8;
-                let b = b.min(255).max(0) as u8;
-                target[(x, y)] =  olc::Pixel::rgb(r as u8, g as u8, b as u8);
-            }
-        }
+        let mut result = Image::new(self.width, self.height, RgbF32::default());
+        self.convert::<RgbF32>().convolve_with_constant(&mut result, kernel, border, border_colour(border).convert());
+        target.pixels.par_iter_mut().zip(&result.pixels).for_each(|(pixel, &value)| *pixel = value.convert());
     }
+}
 
-
-    /// Also called padding, this function handles the pixels that an [image processing kernel] couldn't reach.
+impl<P: Pixel> Image<P>
+{
+    /// Also called padding, this function visits every tap of an [image processing kernel] centred on `(x, y)`,
+    /// reading the pixels past the edge of the image the way `border` says.
     /// 
     /// ## Expected arguments
     /// 
-    /// `&self` is the Image from which pixels are read. 
+    /// `&self` is the Image from which pixels are read.
     /// # 
-    /// `target: &mut Image` is the Image that is written to.
+    /// `(x, y)` is the pixel the kernel is centred on.
     /// # 
-    /// `kernel_size`. `handle_edges` assumes a square shaped kernel.
-    /// `kernel_size` should be equal to the width or height of the kernel. Or given an example kernel `[0,0,0, 0,0,0, 0,0,0]` with `9` elements, 
-    /// `kernel_size` should be equal to `sqrt(9)`. 
+    /// `kernel_size`. The width and height of the kernel, both odd.
     /// # 
-    /// `edge_handler` is a function object that, unlike the kernel generator in `convolve()`, takes in 
-    /// 
-    /// `kernel_size: usize, (image_x: usize, image_y: usize)`, and returns an `olc::Pixel` instead of an `i32`, which the target pixel will be set to.
-    /// 
-    /// The x and y in this function do **not** represent the coordinates of the kernel values.
+    /// `border` decides what taps outside the image read. `constant` is the pixel used for `BorderMode::Constant`,
+    /// already converted to `P`, and `BorderMode::Crop` leaves those taps out.
+    /// # 
+    /// `tap` is a function object that takes in `(kernel_x: usize, kernel_y: usize), pixel: P`.
     /// 
     /// ## Example
     /// ```rust
-    /// pub fn box_blur(&self, target: &mut Image)
-    /// {
-    ///     // self.convolve(target, 3, |s,(x,y)| 1, 9);
-    ///     self.handle_edges(target, 3,
-    ///     |img, _s, (x,y)|
-    ///     img[(x,y)] // here the `edge_handler` just returns the original colour of the image.
-    ///     );
-    /// }
+    /// let mut sum = 0;
+    /// self.for_each_tap((x, y), (3, 3), BorderMode::Clamp, Rgba8::BLACK,
+    ///     |(_kx, _ky), p|
+    ///     sum += p.r as u32
+    /// );
     /// ```
     /// 
-    /// 
     /// [image processing kernel]: https://en.wikipedia.org/wiki/Kernel_(image_processing)
-    pub fn handle_edges<F>(&self, target: &mut Image, kernel_size: usize, edge_handler: F) where F: Fn(&Image, usize, (usize, usize)) -> olc::Pixel
+    pub fn for_each_tap<F>(&self, (x, y): (usize, usize), (kernel_width, kernel_height): (usize, usize), border: BorderMode, constant: P, mut tap: F) where F: FnMut((usize, usize), P)
     {
-        for y in (0..kernel_size/2).chain(self.height - kernel_size/2 .. self.height)
+        let (half_width, half_height) = (kernel_width / 2, kernel_height / 2);
+        if x >= half_width && y >= half_height && x + half_width < self.width && y + half_height < self.height
         {
-            for x in 0..self.width
+            for kernel_y in 0..kernel_height
             {
-                let pixel = edge_handler(self, kernel_size, (x, y));
-                target[(x, y)] = pixel;
+                for kernel_x in 0..kernel_width
+                {
+                    tap((kernel_x, kernel_y), self[(x - half_width + kernel_x, y - half_height + kernel_y)]);
+                }
             }
+            return;
         }
-        for y in 0..self.height
+        for kernel_y in 0..kernel_height
         {
-            for x in (0..kernel_size/2).chain(self.width - kernel_size/2..self.width)
+            let image_y = border.resolve((y + kernel_y) as isize - half_height as isize, self.height);
+            for kernel_x in 0..kernel_width
             {
-                let pixel = edge_handler(self, kernel_size, (x, y));
-                target[(x, y)] = pixel;
+                let image_x = border.resolve((x + kernel_x) as isize - half_width as isize, self.width);
+                match (image_x, image_y)
+                {
+                    (Some(image_x), Some(image_y)) => tap((kernel_x, kernel_y), self[(image_x, image_y)]),
+                    _ if matches!(border, BorderMode::Constant(_)) => tap((kernel_x, kernel_y), constant),
+                    _ => (),
+                }
             }
         }
     }
+}
 
+impl<P: Pixel> Image<P>
+{
+    /// Averages a `kernel_size` square around every pixel. The sums come from an `IntegralImage`,
+    /// so the cost doesn't grow with the size of the square.
+    fn box_blur_with_constant(&self, target: &mut Image<P>, kernel_size: usize, border: BorderMode, constant: P)
+    {
+        let radius = kernel_size / 2;
+        let table = IntegralImage::new(self, radius, border, constant);
+        let radius = radius as isize;
+        target.for_each_row_parallel(|y, row|
+        {
+            for (x, target_pixel) in row.iter_mut().enumerate()
+            {
+                let (left, top) = (x as isize - radius, y as isize - radius);
+                let [r, g, b] = table.mean((left, top), (left + 2 * radius + 1, top + 2 * radius + 1));
+                *target_pixel = P::from_rgba([r as f32, g as f32, b as f32, 1.0]);
+            }
+        });
+    }
+}
+
+/// Row based helpers for running filters on the thread pool.
+/// 
+/// Every row is computed exactly as it would be on one thread, so the results don't depend on the number of threads.
+impl<P: Pixel> Image<P>
+{
+    /// Calls `row_function` with the index and the pixels of every row, spread over the thread pool.
+    pub fn for_each_row_parallel<F>(&mut self, row_function: F) where F: Fn(usize, &mut [P]) + Sync
+    {
+        self.pixels.par_chunks_mut(self.width).enumerate().for_each(|(y, row)| row_function(y, row));
+    }
+
+    /// The image mirrored along its diagonal, so that columns become rows.
+    pub fn transposed(&self) -> Image<P>
+    {
+        let mut target = Image::new(self.height, self.width, P::default());
+        self.transpose_into(&mut target);
+        target
+    }
+
+    /// Like `transposed`, but reuses `target`, which must be `height` wide and `width` high.
+    pub fn transpose_into(&self, target: &mut Image<P>)
+    {
+        target.for_each_row_parallel(|y, row|
+        {
+            for (x, target_pixel) in row.iter_mut().enumerate()
+            {
+                *target_pixel = self[(y, x)];
+            }
+        });
+    }
+}
+
+/// Rounds every channel to the nearest of `levels` evenly spaced values, for the dithering functions.
+fn level_quantiser(levels: usize) -> impl Fn([f32; 3]) -> Rgba8 + Sync
+{
+    let step = 255.0 / (levels - 1) as f32;
+    move |wanted: [f32; 3]|
+    {
+        let [r, g, b] = wanted.map(|c| ((c / step).round() * step).round() as u8);
+        Rgba8::rgb(r, g, b)
+    }
+}
+
+/// Picks the colour of `colour_palette` nearest under `metric`, for the dithering functions. An empty palette gives black.
+fn palette_quantiser(colour_palette: &[Rgba8], metric: DistanceMetric) -> impl Fn([f32; 3]) -> Rgba8 + Sync
+{
+    let matcher = ColourMatcher::cached(colour_palette, metric);
+    move |wanted: [f32; 3]| matcher.nearest(wanted)
+}
+
+/// The colour taps past the edge read for `BorderMode::Constant`. Other modes don't use it.
+fn border_colour(border: BorderMode) -> Rgba8
+{
+    match border
+    {
+        BorderMode::Constant(colour) => colour,
+        _ => Rgba8::BLACK,
+    }
+}
+
+/// The factor `BorderMode::Crop` scales a result with when only the kernel weight `used` of the `total` was inside the image.
+/// Kernels that add up to nothing, like edge detectors, are left as they are.
+fn crop_scale(total: f32, used: f32) -> f32
+{
+    if total == 0.0 || used == 0.0 {1.0} else {total / used}
+}
+
+impl Image
+{
     /// Applies `transformer` to each pixel in the image.
     /// 
     /// ## Expected arguments
     /// `&self` is the Image from which pixels are read.
     /// 
-    /// `target: &mut Image` is the Image that is written to.
+    /// `target: &mut Image<Q>` is the Image that is written to. It can have a different pixel type, like `Grey8`.
     /// 
     /// `tranformer: F` is a function that takes in a pixel, and returns a transformed version of it.
     /// 
@@ -185,113 +543,100 @@ impl Image
     ///         |p|
     ///         {
     ///             let brt = p.brightness();
-    ///             olc::Pixel::rgb(brt,brt,brt)
+    ///             Rgba8::rgb(brt,brt,brt)
     ///         }
     ///     );
     /// }
     /// ```
-    pub fn map<F>(&self, target: &mut Image, transformer: F) where F: Fn(olc::Pixel) -> olc::Pixel
+    pub fn map<Q, F>(&self, target: &mut Image<Q>, transformer: F) where Q: Send, F: Fn(Rgba8) -> Q + Sync
     {
         //let (prefix, simd, suffix) = target.pixels.iter().map(|p| [p.r, p.g, p.b, p.a].iter()).as_simd_mut();
-        for (i, &pixel) in self.pixels.iter().enumerate()
+        target.pixels.par_iter_mut().zip(&self.pixels).for_each(|(target_pixel, &pixel)|
         {
-            target.pixels[i] = transformer(pixel);
-        }
+            *target_pixel = transformer(pixel);
+        });
     }
     
-    pub fn greyscale(&self, target: &mut Image)
+    /// Writes a brightness, so `target` can be an `Image<Grey8>`.
+    pub fn greyscale<Q: Pixel>(&self, target: &mut Image<Q>)
     {
         self.map(target,
             |p|
             {
                 let brt = p.brightness();
-                olc::Pixel::rgb(brt,brt,brt)
+                Q::grey(brt)
             }
         );
     }
     
-    pub fn sharpen(&self, target: &mut Image)
+    /// Writes a brightness, so `target` can be an `Image<Grey8>`.
+    pub fn sharpen<Q: Pixel>(&self, target: &mut Image<Q>, border: BorderMode)
     {
-        let kernel = [0, -1, 0, -1, 5, -1, 0, -1, 0];
+        let kernel = sharpen_kernel();
+        let constant = border_colour(border);
 
-        for y in 0..self.height
+        target.for_each_row_parallel(|y, row|
         {
-            for x in 0..self.width
+            for (x, target_pixel) in row.iter_mut().enumerate()
             {
-                if !(1..self.width-1).contains(&x)
-                || !(1..self.height-1).contains(&y)
-                {
-                    target[(x, y)] = olc::BLACK;
-                    continue;
-                }
-                let mut output = 0;
-                for kernel_y in 0..3
-                {
-                    for kernel_x in 0..3
+                let mut output = 0.0;
+                let mut used = 0.0;
+                self.for_each_tap((x, y), (kernel.width, kernel.height), border, constant,
+                    |(kernel_x, kernel_y), pixel|
                     {
-                        let kernel_value = kernel[kernel_y*3+kernel_x];
-                        let brightness = self[(x - 3/2 + kernel_x, y - 3/2 + kernel_y)].brightness() as i32;
-                        output += brightness * kernel_value;
+                        let kernel_value = kernel.weights[kernel_y*kernel.width+kernel_x];
+                        output += pixel.brightness() as f32 * kernel_value;
+                        used += kernel_value;
                     }
-                }
-                let value = output.min(255).max(0) as u8;
-                target[(x, y)] = olc::Pixel::rgb(value, value, value);
+                );
+                let output = output * crop_scale(1.0, used);
+                let value = output.round().clamp(0.0, 255.0) as u8;
+                *target_pixel = Q::grey(value);
             }                   
-        }
+        });
     }
 
-    pub fn sharpen_colour(&self, target: &mut Image)
+    pub fn sharpen_colour(&self, target: &mut Image, border: BorderMode)
     {
-        self.convolve(target, 3, |s,(x,y)| [0, -1, 0, -1, 5, -1, 0, -1, 0][y*s+x], 1);
-        self.handle_edges(target, 3,
-            |img, _s, (x,y)|
-            img[(x,y)]
-        );
+        self.convolve(target, &sharpen_kernel(), border);
     }
 
-    pub fn sobel_edge_detection_3x3(&self, target: &mut Image)
+    /// Writes a brightness, so `target` can be an `Image<Grey8>`.
+    pub fn sobel_edge_detection_3x3<Q: Pixel>(&self, target: &mut Image<Q>, border: BorderMode)
     {
         let s_x: [i32;9] = [1, 0, -1, 2, 0, -2, 1, 0, -1];
         let s_y: [i32;9] = [1, 2, 1, 0, 0, 0, -1, -2, -1];
-        for y in 0..self.height
+        let constant = border_colour(border);
+        target.for_each_row_parallel(|y, row|
         {
-            for x in 0..self.width
+            for (x, target_pixel) in row.iter_mut().enumerate()
             {
-                if !(1..self.width-1).contains(&x)
-                || !(1..self.height-1).contains(&y)
-                {
-                    //handling edges here
-                    target[(x, y)] = olc::BLACK;
-                    continue;
-                }
                 let mut val_x = 0;
                 let mut val_y = 0;
-                for kernel_y in 0..3
-                {
-                    for kernel_x in 0..3
+                self.for_each_tap((x, y), (3, 3), border, constant,
+                    |(kernel_x, kernel_y), pixel|
                     {
-                        let ix = x + kernel_x - 1;
-                        let iy = y + kernel_y - 1;
                         let ik = kernel_y * 3 + kernel_x;
-                        let current_brightness = self[(ix,iy)].brightness();
+                        let current_brightness = pixel.brightness();
                         val_x += current_brightness as i32 * s_x[ik];
                         val_y += current_brightness as i32 * s_y[ik];
                     }
-                }
+                );
 
                 let value = ((val_x*val_x + val_y*val_y) as f32).sqrt() as u8;
-                target[(x, y)] = olc::Pixel::rgb(value, value, value);
+                *target_pixel = Q::grey(value);
             }                   
-        }
+        });
     }
 
-    pub fn threshold(&self, target: &mut Image, threshold: u8)
+    /// Writes black or white, so `target` can be an `Image<Grey8>`.
+    pub fn threshold<Q: Pixel>(&self, target: &mut Image<Q>, threshold: u8)
     {
         self.map(target,
             |p|
             {
                 let brt = p.brightness(); 
-                if brt >= threshold {olc::WHITE} else {olc::BLACK}
+                Q::grey(if brt >= threshold {255} else {0})
             }
         );
     }
@@ -301,7 +646,7 @@ impl Image
         self.map(target,
             |p|
             {
-                olc::Pixel::rgb
+                Rgba8::rgb
                 (
                     (p.r >= threshold) as u8 * 255,
                     (p.g >= threshold) as u8 * 255,
@@ -311,55 +656,6 @@ impl Image
         );
     }
 
-    pub fn patterned_dithering(&self, target: &mut Image, bits_per_channel:usize)
-    {
-        assert_ne!(bits_per_channel, 0);
-        let max_values_per_channel = (1<<bits_per_channel).min(255);
-        self.patterned_dithering_impl(target, max_values_per_channel);
-    }
-
-    pub fn patterned_dithering_impl(&self, target: &mut Image, max_values_per_channel: usize)
-    {
-        let quantisation_factor = 255/(max_values_per_channel-1);
-        let quantise = |mut p: olc::Pixel, factor|
-            {
-                p = p.div(factor).clamping_mul(factor);
-                p
-            };
-        for y in 0..self.height
-        {
-            for x in 0..self.width
-            {
-                let mut pixel = self[(x,y)];
-                let mut bias = 0;
-                let quantisation_factor = quantisation_factor as u16;
-                let divisor = 20;
-
-                if false
-                {
-                    //checkerboard kinda pattern
-                    bias += (x%2 == y%2) as u16 * 4 * quantisation_factor / (divisor + 1);
-                }
-                else
-                {
-                    //square squiggles pattern
-                    bias += (x%2==0) as u16 * 4 * quantisation_factor / (divisor + 1);
-                    bias += (x%4==0) as u16 * 2 * quantisation_factor / (divisor + 1);
-                    bias += (x%8==0) as u16 * 2 * quantisation_factor / (divisor + 1);
-
-                    bias += (y%2==0) as u16 * 4 * quantisation_factor / (divisor + 1);
-                    bias += (y%4==0) as u16 * 2 * quantisation_factor / (divisor + 1);
-                    bias += (y%8==0) as u16 * 2 * quantisation_factor / (divisor + 1);
-                }
-
-                bias += ((x%4 == 0) == (y%4==0)) as u16 * 4 * quantisation_factor / (divisor + 1);
-                let bias = bias.min(255) as u8;
-                pixel = pixel.clamping_add(olc::Pixel::rgb(bias,bias,bias));
-                target[(x,y)] = quantise(pixel, quantisation_factor as u8);
-            }
-        }
-    }
-
     pub fn random_bias_dithering(&self, target: &mut Image, bits_per_channel:usize)
     {
         assert_ne!(bits_per_channel, 0);
@@ -370,8 +666,8 @@ impl Image
     pub fn random_bias_dithering_impl(&self, target: &mut Image, max_values_per_channel: usize)
     {
         let quantisation_factor = 255/(max_values_per_channel- 1) as u8;
-        let quantise = |p: olc::Pixel, factor|
-            olc::Pixel::rgb(
+        let quantise = |p: Rgba8, factor|
+            Rgba8::rgb(
                 (p.r / factor) * factor,
                 (p.g / factor) * factor,
                 (p.b / factor) * factor,
@@ -382,153 +678,76 @@ impl Image
             {
                 let mut pixel = self[(x,y)];
                 let r = fastrand::u8(0..(quantisation_factor.max(2) as u16 *4/5) as u8);
-                pixel = pixel.clamping_add(olc::Pixel::rgb(r,r,r));
+                pixel = pixel.clamping_add(Rgba8::rgb(r,r,r));
                 target[(x,y)] = quantise(pixel, quantisation_factor);
             }
         }
     }
 
-    pub fn floyd_steinberg_dithering(&self, target: &mut Image, bits_per_channel:usize)
+    /// Error diffusion down to `bits_per_channel` bits per channel, every channel rounded to the nearest of its levels.
+    /// See `dither::error_diffusion`.
+    pub fn error_diffusion_dithering(&self, target: &mut Image, bits_per_channel: usize, kernel: DiffusionKernel, serpentine: bool)
     {
         assert_ne!(bits_per_channel, 0);
-        let max_values_per_channel = if bits_per_channel > 7 {255} else{1 << bits_per_channel};
-        self.floyd_steinberg_dithering_impl(target, max_values_per_channel);
+        error_diffusion(self, target, kernel, serpentine, level_quantiser(1 << bits_per_channel.min(8)));
     }
 
-    pub fn floyd_steinberg_with_custom_colour_palette(&self, target: &mut Image, colour_palette: &[olc::Pixel])
+    /// Error diffusion to the colours of `colour_palette`, every pixel becoming the nearest colour under `metric`.
+    /// See `dither::error_diffusion`.
+    pub fn error_diffusion_with_palette(&self, target: &mut Image, colour_palette: &[Rgba8], kernel: DiffusionKernel, serpentine: bool, metric: DistanceMetric)
     {
-        target.pixels.copy_from_slice(&self.pixels);
-
-        let quantise = |pixel: olc::Pixel|
-        {
-            let mut nearest_dist = 999999999;
-            let mut nearest_pixel = olc::BLACK;
-            for &other in colour_palette
-            {
-                if other.r <= pixel.r
-                && other.g <= pixel.g
-                && other.b <= pixel.b
-                {
-                    let dist = pixel.distance_squared(other);
-                    if dist < nearest_dist
-                    {
-                        nearest_dist = dist;
-                        nearest_pixel = other;
-                    }
-                }
-            }
-            nearest_pixel
-        };
-
-        let weighted_error = |error: olc::Pixel, factor:u8| error.clamping_fraction_mul((factor, 16));
-
-        for y in 1..self.height-1
-        {
-            for x in 1..self.width-1
-            {
-                let old_pixel = target[(x,y)];
-                let new = quantise(old_pixel);
-
-                //we know that quantising a pixel always makes it either the same or darker than before
-                //this means that the error is always positive, and we can use u8s to store it
-                let error = old_pixel.sub(new);
-                
-                let mut diffuse_error = |pos:(usize,usize), factor :u8|
-                {
-                    target[pos] = target[pos].clamping_add
-                    (
-                        weighted_error(error, factor)
-                    );
-                };
-
-                diffuse_error((x+1,   y),7);
-                diffuse_error((x-1, y+1),3);
-                diffuse_error((x  , y+1),5);
-                diffuse_error((x+1, y+1),1);
-                target[(x,y)] = new;
-            }
-        }
+        error_diffusion(self, target, kernel, serpentine, palette_quantiser(colour_palette, metric));
     }
 
-    pub fn floyd_steinberg_dithering_impl(&self, target: &mut Image, max_values_per_channel: usize)
+    /// Ordered dithering to `levels` evenly spaced values per channel, from 2 to 256, with the thresholds of `map`.
+    /// See `dither::ordered_dithering`.
+    pub fn ordered_dithering(&self, target: &mut Image, levels: usize, map: ThresholdMap)
     {
-        let quantisation_factor = (255/(max_values_per_channel-1) as u16) as u8;
-
-        target.pixels.copy_from_slice(&self.pixels);
-
-        let quantise = |pixel: olc::Pixel| pixel.div(quantisation_factor).clamping_mul(quantisation_factor);
+        assert!(levels >= 2);
+        let levels = levels.min(256);
+        ordered_dithering(self, target, map, 255.0 / (levels - 1) as f32, level_quantiser(levels));
+    }
 
-        let weighted_error = |error: olc::Pixel, factor:u8| error.clamping_fraction_mul((factor, 16));
+    /// Ordered dithering to the colours of `colour_palette`, mixing 16 of them per pixel, see `dither::pattern_dithering`.
+    /// The mix is made of the colours nearest under `metric`.
+    pub fn ordered_dithering_with_palette(&self, target: &mut Image, colour_palette: &[Rgba8], map: ThresholdMap, metric: DistanceMetric)
+    {
+        pattern_dithering(self, target, map, 16, palette_quantiser(colour_palette, metric));
+    }
 
-        for y in 1..self.height-1
-        {
-            for x in 1..self.width-1
-            {
-                let old_pixel = target[(x,y)];
-                let new = quantise(old_pixel);
+    pub fn floyd_steinberg_dithering(&self, target: &mut Image, bits_per_channel: usize)
+    {
+        self.error_diffusion_dithering(target, bits_per_channel, DiffusionKernel::FloydSteinberg, false);
+    }
 
-                //we know that quantising a pixel always makes it either the same or darker than before
-                //this means that the error is always positive, and we can use u8s to store it
-                let error = old_pixel.sub(new);
-                
-                let mut diffuse_error = |pos:(usize,usize), factor :u8|
-                {
-                    target[pos] = target[pos].clamping_add
-                    (
-                        weighted_error(error, factor)
-                    );
-                };
-
-                diffuse_error((x+1,   y),7);
-                diffuse_error((x-1, y+1),3);
-                diffuse_error((x  , y+1),5);
-                diffuse_error((x+1, y+1),1);
-                target[(x,y)] = new;
-            }
-        }
+    pub fn floyd_steinberg_with_custom_colour_palette(&self, target: &mut Image, colour_palette: &[Rgba8], metric: DistanceMetric)
+    {
+        self.error_diffusion_with_palette(target, colour_palette, DiffusionKernel::FloydSteinberg, false, metric);
     }
 
-    pub fn gaussian_blur_3x3(&mut self, target: &mut Image)
+    pub fn gaussian_blur_3x3(&self, target: &mut Image, border: BorderMode)
     {
-        self.convolve(target, 3, |s, (x,y)|
-            [
-                1, 2, 1,
-                2, 4, 2,
-                1, 2, 1,
-            ][y*s+x], 16
-        );
-        self.handle_edges(target, 3,
-            |img, _s, (x,y)|
-            img[(x,y)]
-        );
+        self.convolve(target, &gaussian_kernel_3x3(), border);
     }
-    pub fn emboss(&mut self, target: &mut Image)
+    pub fn emboss(&self, target: &mut Image, border: BorderMode)
     {
-        self.convolve(target, 3, |s, (x,y)|
+        let kernel = Kernel::new(3, 3, vec!
             [
-                -2, -1, 0,
-                -1, 1, 1,
-                0, 1, 2
-            ][y*s+x],1
-        );
-        self.handle_edges(target,3,
-            |img, _s, (x,y)|
-            img[(x,y)]
-        );
+                -2.0, -1.0, 0.0,
+                -1.0, 1.0, 1.0,
+                0.0, 1.0, 2.0
+            ]);
+        self.convolve(target, &kernel, border);
     }
-    pub fn outline(&mut self, target: &mut Image)
+    pub fn outline(&self, target: &mut Image, border: BorderMode)
     {
-        self.convolve(target, 3, |s, (x,y)|
+        let kernel = Kernel::new(3, 3, vec!
             [
-                -1, -1, -1,
-                -1, 8, -1,
-                -1, -1, -1
-            ][y*s+x],1
-        );
-        self.handle_edges(target,3,
-            |img, _s, (x,y)|
-            img[(x,y)]
-        );
+                -1.0, -1.0, -1.0,
+                -1.0, 8.0, -1.0,
+                -1.0, -1.0, -1.0
+            ]);
+        self.convolve(target, &kernel, border);
     }
 
 
@@ -563,61 +782,40 @@ impl Image
         }
     }
 
-    pub fn get_average_colour(&self) -> olc::Pixel
+    /// Averages a `kernel_size` square around every pixel, see `box_blur_with_constant`.
+    pub fn box_blur(&self, target: &mut Image, kernel_size: usize, border: BorderMode)
     {
-        let mut average_colour = (0,0,0);
-        for &pixel in &self.pixels
-        {
-            average_colour.0 += pixel.r as u32;
-            average_colour.1 += pixel.g as u32;
-            average_colour.2 += pixel.b as u32;
-        }
-        average_colour.0 /= self.pixels.len() as u32;
-        average_colour.1 /= self.pixels.len() as u32;
-        average_colour.2 /= self.pixels.len() as u32;
-        olc::Pixel::rgb(average_colour.0 as u8, average_colour.1 as u8, average_colour.2 as u8)
+        self.box_blur_with_constant(target, kernel_size, border, border_colour(border));
     }
 
-    pub fn box_blur(&self, target: &mut Image, kernel_size: usize)
+    /// Blurs with a Gaussian of standard deviation `sigma`, in pixels, see `Image<RgbF32>::gaussian_blur`.
+    pub fn gaussian_blur(&self, target: &mut Image, sigma: f32, border: BorderMode)
     {
-        self.convolve(target, kernel_size, |_s, (_x, _y)| 1, (kernel_size*kernel_size) as i32);
-        let average_colour = self.get_average_colour();
-        self.handle_edges(target, kernel_size, 
-            |_, _, _|
-            average_colour
-        );
+        let mut result = Image::new(self.width, self.height, RgbF32::default());
+        self.convert::<RgbF32>().gaussian_blur_with_constant(&mut result, sigma, border, border_colour(border).convert());
+        target.pixels.par_iter_mut().zip(&result.pixels).for_each(|(pixel, &value)| *pixel = value.convert());
     }
 
-    pub fn sobel_edge_detection_3x3_colour(&mut self, target: &mut Image)
+    pub fn sobel_edge_detection_3x3_colour(&self, target: &mut Image, border: BorderMode)
     {
         let s_x = [1,0,-1,2,0,-2,1,0,-1];
         let s_y = [1,2,1,0,0,0,-1,-2,-1];
+        let constant = border_colour(border);
         
-        for y in 0..self.height
+        target.for_each_row_parallel(|y, row|
         {
-            for x in 0..self.width
+            for (x, target_pixel) in row.iter_mut().enumerate()
             {
-                if !(1..self.width-1).contains(&x)
-                || !(1..self.height-1).contains(&y)
-                {
-                    //handling edges here
-                    target[(x, y)] = olc::BLACK;
-                    continue;
-                }
                 let mut rx = 0;
                 let mut gx = 0;
                 let mut bx = 0;
                 let mut ry = 0;
                 let mut gy = 0;
                 let mut by = 0;
-                for kernel_y in 0..3
-                {
-                    for kernel_x in 0..3
+                self.for_each_tap((x, y), (3, 3), border, constant,
+                    |(kernel_x, kernel_y), current_pixel|
                     {
-                        let ix = x + kernel_x - 1;
-                        let iy = y + kernel_y - 1;
                         let ik = kernel_y * 3 + kernel_x;
-                        let current_pixel = self[(ix,iy)];
                         rx += current_pixel.r as i32 * s_x[ik];
                         gx += current_pixel.g as i32 * s_x[ik];
                         bx += current_pixel.b as i32 * s_x[ik];
@@ -625,17 +823,18 @@ impl Image
                         gy += current_pixel.g as i32 * s_y[ik];
                         by += current_pixel.b as i32 * s_y[ik];
                     }
-                }
+                );
 
                 let r = ((rx*rx + ry*ry) as f32).sqrt() as u8;
                 let g = ((gx*gx + gy*gy) as f32).sqrt() as u8;
                 let b = ((bx*bx + by*by) as f32).sqrt() as u8;
-                target[(x, y)] = olc::Pixel::rgb(r, g, b);
+                *target_pixel = Rgba8::rgb(r, g, b);
             }                   
-        }
+        });
     }
 
-    pub fn cross_blur(&mut self, target: &mut Image)
+    /// Writes a brightness, so `target` can be an `Image<Grey8>`.
+    pub fn cross_blur<Q: Pixel>(&self, target: &mut Image<Q>, border: BorderMode)
     {
         let s_y =  [   -1, 1,-1,
                                  1, 0, 1,
@@ -643,29 +842,518 @@ impl Image
         let s_x = [   1, -1, 1,
                                -1, 1, -1,
                                 1, -1,1];
+        let constant = border_colour(border);
         
-        for y in 1..self.height - 1
+        target.for_each_row_parallel(|y, row|
         {
-            for x in 1..self.width - 1
+            for (x, target_pixel) in row.iter_mut().enumerate()
             {
                 let mut val_x = 0;
                 let mut val_y = 0;
-                for kernel_y in 0..3
-                {
-                    for kernel_x in 0..3
+                self.for_each_tap((x, y), (3, 3), border, constant,
+                    |(kernel_x, kernel_y), pixel|
                     {
-                        let ix = x + kernel_x - 1;
-                        let iy = y + kernel_y - 1;
                         let ik = kernel_y * 3 + kernel_x;
-                        let current_brightness = self[(ix,iy)].brightness();
+                        let current_brightness = pixel.brightness();
                         val_x += current_brightness as i32 * s_x[ik];
                         val_y += current_brightness as i32 * s_y[ik];
                     }
-                }
+                );
 
                 let value = ((val_x*val_x + val_y*val_y) as f32).sqrt() as u8;
-                target[(x, y)] = olc::Pixel::rgb(value, value, value);
+                *target_pixel = Q::grey(value);
+            }
+        });
+    }
+}
+
+/// Linear-light versions of the effects that average colours, used by filters that work in linear light.
+impl Image<RgbF32>
+{
+    /// Encodes the pixels to opaque sRGB, see `RgbF32::to_srgb`.
+    pub fn to_srgb(&self) -> Image
+    {
+        let mut encoded = Image::new(self.width, self.height, Rgba8::BLACK);
+        self.to_srgb_into(&mut encoded);
+        encoded
+    }
+
+    /// Like `to_srgb`, but reuses `target`, which must have the same size.
+    pub fn to_srgb_into(&self, target: &mut Image)
+    {
+        target.pixels.par_iter_mut().zip(&self.pixels).for_each(|(encoded, &pixel)| *encoded = pixel.to_srgb());
+    }
+
+    /// The floating point version of `Image::convolve`. Nothing is rounded or clamped,
+    /// and the colour of `BorderMode::Constant` is decoded to linear light.
+    pub fn convolve(&self, target: &mut Image<RgbF32>, kernel: &Kernel, border: BorderMode)
+    {
+        self.convolve_with_constant(target, kernel, border, RgbF32::from_srgb(border_colour(border)));
+    }
+
+    /// `convolve` with the pixel `BorderMode::Constant` reads given as it should be used.
+    fn convolve_with_constant(&self, target: &mut Image<RgbF32>, kernel: &Kernel, border: BorderMode, constant: RgbF32)
+    {
+        // `Crop` leaves kernels that add up to nothing unscaled, but one of their factors may well add up to something
+        // and would be scaled in its pass, so those run whole
+        let separated = if border == BorderMode::Crop && kernel.sum() == 0.0 {None} else {kernel.separate()};
+        match separated
+        {
+            Some((row, column)) =>
+            {
+                let mut rows = Image::new(self.width, self.height, RgbF32::default());
+                self.convolve_taps(&mut rows, (row.len(), 1), &row, border, constant);
+                // the vertical pass reads rows the horizontal pass has already summed, including constant ones
+                let row_sum = row.iter().sum::<f32>();
+                let row_constant = RgbF32::new(constant.r * row_sum, constant.g * row_sum, constant.b * row_sum);
+                rows.convolve_taps(target, (1, column.len()), &column, border, row_constant);
             }
+            None => self.convolve_taps(target, (kernel.width, kernel.height), &kernel.weights, border, constant),
+        }
+    }
+
+    /// Runs the `size.0` by `size.1` kernel `weights` over the image in one pass.
+    fn convolve_taps(&self, target: &mut Image<RgbF32>, size: (usize, usize), weights: &[f32], border: BorderMode, constant: RgbF32)
+    {
+        let total = weights.iter().sum::<f32>();
+        target.for_each_row_parallel(|y, row|
+        {
+            for (x, target_pixel) in row.iter_mut().enumerate()
+            {
+                let mut sum = RgbF32::default();
+                let mut used = 0.0;
+                self.for_each_tap((x, y), size, border, constant,
+                    |(kernel_x, kernel_y), pixel|
+                    {
+                        let kernel_value = weights[kernel_y*size.0+kernel_x];
+                        sum.r += pixel.r * kernel_value;
+                        sum.g += pixel.g * kernel_value;
+                        sum.b += pixel.b * kernel_value;
+                        used += kernel_value;
+                    }
+                );
+                if border == BorderMode::Crop
+                {
+                    let scale = crop_scale(total, used);
+                    sum = RgbF32::new(sum.r * scale, sum.g * scale, sum.b * scale);
+                }
+                *target_pixel = sum;
+            }
+        });
+    }
+
+    pub fn gaussian_blur_3x3(&self, target: &mut Image<RgbF32>, border: BorderMode)
+    {
+        self.convolve(target, &gaussian_kernel_3x3(), border);
+    }
+
+    pub fn box_blur(&self, target: &mut Image<RgbF32>, kernel_size: usize, border: BorderMode)
+    {
+        self.box_blur_with_constant(target, kernel_size, border, RgbF32::from_srgb(border_colour(border)));
+    }
+
+    /// Blurs with a Gaussian of standard deviation `sigma`, in pixels.
+    /// 
+    /// Sigmas below `STACKED_BOX_SIGMA` use `Kernel::gaussian`. Larger ones are approximated by three box blurs in a row,
+    /// which cost the same per pixel whatever their size, so the blur stays fast at any radius.
+    pub fn gaussian_blur(&self, target: &mut Image<RgbF32>, sigma: f32, border: BorderMode)
+    {
+        self.gaussian_blur_with_constant(target, sigma, border, RgbF32::from_srgb(border_colour(border)));
+    }
+
+    fn gaussian_blur_with_constant(&self, target: &mut Image<RgbF32>, sigma: f32, border: BorderMode, constant: RgbF32)
+    {
+        if sigma < STACKED_BOX_SIGMA
+        {
+            self.convolve_with_constant(target, &Kernel::gaussian(sigma), border, constant);
+            return;
         }
+        // the vertical passes run as horizontal ones on the transposed image, so that every pass works on whole rows
+        let radii = stacked_box_radii(sigma);
+        let mut temp = Image::new(self.width, self.height, RgbF32::default());
+        self.box_pass(&mut temp, radii[0], border, constant);
+        temp.box_pass(target, radii[1], border, constant);
+        target.box_pass(&mut temp, radii[2], border, constant);
+        let mut columns = temp.transposed();
+        let mut columns_temp = Image::new(columns.width, columns.height, RgbF32::default());
+        columns.box_pass(&mut columns_temp, radii[0], border, constant);
+        columns_temp.box_pass(&mut columns, radii[1], border, constant);
+        columns.box_pass(&mut columns_temp, radii[2], border, constant);
+        columns_temp.transpose_into(target);
     }
-}
\ No newline at end of file
+
+    /// Averages `2 * radius + 1` pixels along every row with a running sum, so the cost doesn't depend on the radius.
+    fn box_pass(&self, target: &mut Image<RgbF32>, radius: usize, border: BorderMode, constant: RgbF32)
+    {
+        let length = self.width;
+        let radius = radius as isize;
+        target.for_each_row_parallel(|y, row|
+        {
+            let sample = |i: isize|
+            {
+                match border.resolve(i, length)
+                {
+                    Some(i) => Some(self[(i, y)]),
+                    None if matches!(border, BorderMode::Constant(_)) => Some(constant),
+                    None => None,
+                }
+            };
+            let mut sum = RgbF32::default();
+            let mut count = 0;
+            let add = |i: isize, sign: f32, sum: &mut RgbF32, count: &mut i32|
+            {
+                if let Some(p) = sample(i)
+                {
+                    sum.r += sign * p.r;
+                    sum.g += sign * p.g;
+                    sum.b += sign * p.b;
+                    *count += sign as i32;
+                }
+            };
+            for i in -radius..=radius
+            {
+                add(i, 1.0, &mut sum, &mut count);
+            }
+            for (i, target_pixel) in row.iter_mut().enumerate()
+            {
+                let i = i as isize;
+                // crop averages only the pixels inside the image
+                let divisor = if border == BorderMode::Crop {count.max(1) as f32} else {(2 * radius + 1) as f32};
+                *target_pixel = RgbF32::new(sum.r / divisor, sum.g / divisor, sum.b / divisor);
+                add(i - radius, -1.0, &mut sum, &mut count);
+                add(i + radius + 1, 1.0, &mut sum, &mut count);
+            }
+        });
+    }
+}
+
+/// From this sigma up, `gaussian_blur` uses stacked box blurs instead of an exact kernel.
+const STACKED_BOX_SIGMA: f32 = 2.0;
+
+/// The radii of three box blurs that, run one after another, are close to a Gaussian with the standard deviation `sigma`.
+/// 
+/// Every box blur adds `(size² - 1) / 12` to the variance. The sizes are the two odd sizes around the ideal one,
+/// mixed so that the variances add up to `sigma²`, see [Kovesi, Fast Almost-Gaussian Filtering].
+/// 
+/// [Kovesi, Fast Almost-Gaussian Filtering]: https://www.peterkovesi.com/papers/FastGaussianSmoothing.pdf
+fn stacked_box_radii(sigma: f32) -> [usize; 3]
+{
+    let passes = 3.0;
+    let ideal = (12.0 * sigma * sigma / passes + 1.0).sqrt();
+    let mut lower = ideal.floor() as i32;
+    if lower % 2 == 0
+    {
+        lower -= 1;
+    }
+    let lower = lower.max(1) as f32;
+    let lower_count = ((12.0 * sigma * sigma - passes * lower * lower - 4.0 * passes * lower - 3.0 * passes) / (-4.0 * lower - 4.0)).round();
+    // the larger size is two pixels wider, one more of radius
+    let lower_radius = (lower as usize - 1) / 2;
+    std::array::from_fn(|i| if (i as f32) < lower_count {lower_radius} else {lower_radius + 1})
+}
+
+fn gaussian_kernel_3x3() -> Kernel
+{
+    Kernel::new(3, 3, vec!
+        [
+            1.0, 2.0, 1.0,
+            2.0, 4.0, 2.0,
+            1.0, 2.0, 1.0,
+        ]).normalised()
+}
+
+fn sharpen_kernel() -> Kernel
+{
+    Kernel::new(3, 3, vec![0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0])
+}
+
+/// For every output coordinate, the source indices and normalised weights that contribute to it.
+fn resample_taps(source_size: usize, target_size: usize, filter: ResampleFilter) -> Vec<Vec<(usize, f32)>>
+{
+    let scale = source_size as f32 / target_size as f32;
+    // widen the filter when shrinking, so that it also acts as a low pass filter
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+    (0..target_size).map(|i|
+    {
+        let centre = (i as f32 + 0.5) * scale - 0.5;
+        let first = (centre - support).floor() as isize;
+        let last = (centre + support).ceil() as isize;
+        let mut taps = (first..=last)
+            .map(|j| (j.clamp(0, source_size as isize - 1) as usize, filter.weight((j as f32 - centre) / filter_scale)))
+            .filter(|&(_, w)| w != 0.0)
+            .collect::<Vec<_>>();
+        let total = taps.iter().map(|&(_, w)| w).sum::<f32>();
+        if total == 0.0
+        {
+            taps = vec![((centre.round().max(0.0) as usize).min(source_size - 1), 1.0)];
+        }
+        else
+        {
+            taps.iter_mut().for_each(|(_, w)| *w /= total);
+        }
+        taps
+    }).collect()
+}
+
+fn weighted_sum<'a, I>(taps: I) -> [f32; 4] where I: Iterator<Item = (&'a [f32; 4], f32)>
+{
+    let mut sum = [0.0; 4];
+    for (value, weight) in taps
+    {
+        for channel in 0..4
+        {
+            sum[channel] += value[channel] * weight;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// An image of black with a single pixel of 1 in the middle.
+    fn impulse(size: usize) -> Image<RgbF32>
+    {
+        let mut image = Image::new(size, size, RgbF32::default());
+        image[(size / 2, size / 2)] = RgbF32::new(1.0, 1.0, 1.0);
+        image
+    }
+
+    const RESAMPLE_FILTERS: [ResampleFilter; 4] = [ResampleFilter::Nearest, ResampleFilter::Bilinear, ResampleFilter::Bicubic, ResampleFilter::Lanczos3];
+
+    /// Red up to `first`, green up to `second` and blue after that, along x or along y.
+    fn bands(width: usize, height: usize, (first, second): (usize, usize), along_x: bool) -> Image
+    {
+        let mut image = Image::new(width, height, Rgba8::BLACK);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                let position = if along_x {x} else {y};
+                image[(x, y)] = if position < first {Rgba8::rgb(255, 0, 0)} else if position < second {Rgba8::rgb(0, 255, 0)} else {Rgba8::rgb(0, 0, 255)};
+            }
+        }
+        image
+    }
+
+    /// The columns, or rows, of `image` that are all `colour`.
+    fn lines_of(image: &Image, colour: Rgba8, columns: bool) -> Vec<usize>
+    {
+        let (count, length) = if columns {(image.width, image.height)} else {(image.height, image.width)};
+        (0..count).filter(|&i| (0..length).all(|j| image[if columns {(i, j)} else {(j, i)}] == colour)).collect()
+    }
+
+    #[test]
+    fn resizing_and_fitting_give_the_asked_size()
+    {
+        let source = bands(37, 23, (10, 20), true);
+        for filter in RESAMPLE_FILTERS
+        {
+            for (width, height) in [(1, 1), (37, 23), (74, 46), (10, 30), (64, 9), (5, 200)]
+            {
+                let resized = source.resize(width, height, filter);
+                assert_eq!((resized.width, resized.height, resized.pixels.len()), (width, height, width * height));
+                for fit_mode in [FitMode::Stretch, FitMode::Letterbox, FitMode::Crop]
+                {
+                    for fitted in [source.fit(width, height, fit_mode, filter), source.fit_linear(width, height, fit_mode, filter)]
+                    {
+                        assert_eq!((fitted.width, fitted.height, fitted.pixels.len()), (width, height, width * height), "{:?} {:?}", filter, fit_mode);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn letterbox_centres_the_whole_image_between_bars()
+    {
+        let (red, black) = (Rgba8::rgb(255, 0, 0), Rgba8::BLACK);
+        // a wider image gets bars above and below: 40x10 is 20x5 in 20x20, 7 rows down
+        let fitted = Image::new(40, 10, red).fit(20, 20, FitMode::Letterbox, ResampleFilter::Nearest);
+        assert_eq!(lines_of(&fitted, red, false), (7..12).collect::<Vec<_>>());
+        assert_eq!(lines_of(&fitted, black, false), (0..7).chain(12..20).collect::<Vec<_>>());
+        // a taller one gets them left and right
+        let fitted = Image::new(10, 40, red).fit(20, 20, FitMode::Letterbox, ResampleFilter::Nearest);
+        assert_eq!(lines_of(&fitted, red, true), (7..12).collect::<Vec<_>>());
+        assert_eq!(lines_of(&fitted, black, true), (0..7).chain(12..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn crop_keeps_the_middle_and_fills_everything()
+    {
+        let green = Rgba8::rgb(0, 255, 0);
+        // 40x10 scaled to 80x20 and cut to 20x20 keeps source columns 15 to 25, the green band
+        let fitted = bands(40, 10, (15, 25), true).fit(20, 20, FitMode::Crop, ResampleFilter::Nearest);
+        assert_eq!(lines_of(&fitted, green, true), (0..20).collect::<Vec<_>>());
+        let fitted = bands(10, 40, (15, 25), false).fit(20, 20, FitMode::Crop, ResampleFilter::Nearest);
+        assert_eq!(lines_of(&fitted, green, false), (0..20).collect::<Vec<_>>());
+        // with a narrower green band, the edges of the other two show up
+        let fitted = bands(40, 10, (16, 24), true).fit(20, 20, FitMode::Crop, ResampleFilter::Nearest);
+        assert_eq!(lines_of(&fitted, Rgba8::rgb(255, 0, 0), true), vec![0, 1]);
+        assert_eq!(lines_of(&fitted, green, true), (2..18).collect::<Vec<_>>());
+        assert_eq!(lines_of(&fitted, Rgba8::rgb(0, 0, 255), true), vec![18, 19]);
+    }
+
+    #[test]
+    fn stretch_scales_each_axis_on_its_own()
+    {
+        let (red, green, blue) = (Rgba8::rgb(255, 0, 0), Rgba8::rgb(0, 255, 0), Rgba8::rgb(0, 0, 255));
+        let fitted = bands(40, 10, (10, 30), true).fit(20, 20, FitMode::Stretch, ResampleFilter::Nearest);
+        assert_eq!((lines_of(&fitted, red, true), lines_of(&fitted, green, true), lines_of(&fitted, blue, true)), ((0..5).collect(), (5..15).collect(), (15..20).collect()));
+        let fitted = bands(10, 40, (10, 30), false).fit(20, 20, FitMode::Stretch, ResampleFilter::Nearest);
+        assert_eq!((lines_of(&fitted, red, false), lines_of(&fitted, green, false), lines_of(&fitted, blue, false)), ((0..5).collect(), (5..15).collect(), (15..20).collect()));
+    }
+
+    #[test]
+    fn resampling_keeps_flat_colours_flat()
+    {
+        let colour = Rgba8::rgb(200, 100, 30);
+        let source = Image::new(31, 17, colour);
+        for filter in RESAMPLE_FILTERS
+        {
+            for (width, height) in [(7, 3), (31, 40), (100, 55), (1, 1)]
+            {
+                for resized in [source.resize(width, height, filter), source.fit_linear(width, height, FitMode::Stretch, filter)]
+                {
+                    let off = resized.pixels.iter().map(|p| p.r.abs_diff(colour.r).max(p.g.abs_diff(colour.g)).max(p.b.abs_diff(colour.b))).max().unwrap();
+                    assert!(off <= 1, "{:?} to {}x{} is off by {}", filter, width, height, off);
+                }
+            }
+        }
+    }
+
+    /// Where `border` maps the coordinates from -4 up to `size + 4`.
+    fn resolved(border: BorderMode, size: usize) -> Vec<Option<usize>>
+    {
+        (-4..size as isize + 4).map(|i| border.resolve(i, size)).collect()
+    }
+
+    #[test]
+    fn borders_map_coordinates_past_the_edges()
+    {
+        let inside = (0..4).map(Some).collect::<Vec<_>>();
+        let with = |before: [usize; 4], after: [usize; 4]| before.map(Some).into_iter().chain(inside.clone()).chain(after.map(Some)).collect::<Vec<_>>();
+        assert_eq!(resolved(BorderMode::Clamp, 4), with([0, 0, 0, 0], [3, 3, 3, 3]));
+        // mirroring doesn't repeat the edge pixel, so -1 reads 1 and 4 reads 2
+        assert_eq!(resolved(BorderMode::Mirror, 4), with([2, 3, 2, 1], [2, 1, 0, 1]));
+        assert_eq!(resolved(BorderMode::Wrap, 4), with([0, 1, 2, 3], [0, 1, 2, 3]));
+        for border in [BorderMode::Constant(Rgba8::WHITE), BorderMode::Crop]
+        {
+            assert_eq!(resolved(border, 4), [None; 4].into_iter().chain(inside.clone()).chain([None; 4]).collect::<Vec<_>>());
+        }
+
+        // a single pixel is its own neighbour everywhere
+        for border in [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap]
+        {
+            assert_eq!(resolved(border, 1), vec![Some(0); 9], "{:?}", border);
+        }
+        assert_eq!(resolved(BorderMode::Crop, 1), [None; 4].into_iter().chain([Some(0)]).chain([None; 4]).collect::<Vec<_>>());
+
+        // far away from the image too
+        for size in [1, 2, 3, 7]
+        {
+            for i in -50..50
+            {
+                let mirrored = BorderMode::Mirror.resolve(i, size).unwrap();
+                assert_eq!(BorderMode::Mirror.resolve(-i, size), Some(mirrored));
+                assert_eq!(BorderMode::Mirror.resolve(2 * (size as isize - 1) - i, size), Some(mirrored));
+                assert_eq!(BorderMode::Wrap.resolve(i + size as isize, size), BorderMode::Wrap.resolve(i, size));
+                assert!(BorderMode::Clamp.resolve(i, size).unwrap() < size);
+            }
+        }
+    }
+
+    #[test]
+    fn border_names_round_trip()
+    {
+        for border in BorderMode::ALL.into_iter().chain([BorderMode::Constant(Rgba8::rgb(255, 128, 0)), BorderMode::Constant(Rgba8::rgb(1, 2, 3))])
+        {
+            assert_eq!(BorderMode::from_name(&border.name()), Some(border), "{}", border.name());
+            assert_eq!(BorderMode::ALL[border.index()].name().split(' ').next(), border.name().split(' ').next());
+        }
+        assert_eq!(BorderMode::Constant(Rgba8::rgb(255, 128, 0)).name(), "constant #ff8000");
+        assert_eq!(BorderMode::from_name(" Constant   #FF8000 "), Some(BorderMode::Constant(Rgba8::rgb(255, 128, 0))));
+        assert_eq!(BorderMode::from_name("constant"), Some(BorderMode::Constant(Rgba8::BLACK)));
+        assert_eq!(BorderMode::from_name("MIRROR"), Some(BorderMode::Mirror));
+        for invalid in ["", "bounce", "constant #ff80", "constant #ff8000 #000000", "wrap around"]
+        {
+            assert_eq!(BorderMode::from_name(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn separable_kernels_give_the_same_result_in_two_passes()
+    {
+        let rng = fastrand::Rng::with_seed(3);
+        let mut image = Image::new(23, 17, RgbF32::default());
+        for pixel in image.pixels.iter_mut()
+        {
+            *pixel = RgbF32::new(rng.f32(), rng.f32(), rng.f32());
+        }
+        let kernels = [Kernel::gaussian(0.8), Kernel::gaussian(1.9), gaussian_kernel_3x3(), Kernel::from_fn(7, 3, |_, _| 1.0 / 21.0),
+            Kernel::new(3, 3, vec![1.0, 0.0, -1.0, 2.0, 0.0, -2.0, 1.0, 0.0, -1.0])];
+        for kernel in &kernels
+        {
+            assert!(kernel.separate().is_some());
+            for border in [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Constant(Rgba8::rgb(200, 100, 0)), BorderMode::Crop]
+            {
+                let constant = RgbF32::new(0.6, 0.3, 0.0);
+                let (mut two_passes, mut one_pass) = (image.clone(), image.clone());
+                image.convolve_with_constant(&mut two_passes, kernel, border, constant);
+                image.convolve_taps(&mut one_pass, (kernel.width, kernel.height), &kernel.weights, border, constant);
+                for (a, b) in two_passes.pixels.iter().zip(&one_pass.pixels)
+                {
+                    assert!((a.r - b.r).abs() < 1e-5 && (a.g - b.g).abs() < 1e-5 && (a.b - b.b).abs() < 1e-5, "{:?} {:?}: {:?} instead of {:?}", kernel, border, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stacked_boxes_have_the_variance_of_the_gaussian()
+    {
+        for step in 0..=280
+        {
+            let sigma = 2.0 + step as f32 / 10.0;
+            let radii = stacked_box_radii(sigma);
+            assert!(radii.iter().max().unwrap() - radii.iter().min().unwrap() <= 1, "{:?}", radii);
+            // a box 2r+1 wide has a variance of ((2r+1)^2 - 1) / 12, and the variances of blurs done in a row add up
+            let variance = radii.iter().map(|&r| (((2 * r + 1) * (2 * r + 1) - 1) as f32) / 12.0).sum::<f32>();
+            let difference = (variance - sigma * sigma).abs();
+            // widening one box by a pixel on each side adds 2(r+1)/3, so the best choice is off by half that at most
+            let smallest = *radii.iter().min().unwrap() as f32;
+            assert!(difference <= (smallest + 1.0) / 3.0 + 1e-3, "sigma {} gets {:?} with a variance of {}", sigma, radii, variance);
+            assert!(difference / (sigma * sigma) <= if sigma < 10.0 {0.17} else {0.035}, "sigma {} gets a variance of {}", sigma, variance);
+        }
+    }
+
+    #[test]
+    fn blurred_impulses_keep_their_weight_and_symmetry()
+    {
+        // both below and above STACKED_BOX_SIGMA, where the blur switches to boxes
+        for sigma in [0.5, 1.0, 1.9, 2.0, 3.7, 8.0]
+        {
+            let source = impulse(81);
+            let mut target = source.clone();
+            source.gaussian_blur(&mut target, sigma, BorderMode::Clamp);
+            let total = target.pixels.iter().map(|p| p.g as f64).sum::<f64>();
+            assert!((total - 1.0).abs() < 1e-4, "sigma {} sums to {}", sigma, total);
+            let centre = 40;
+            for dy in 0..=centre
+            {
+                for dx in 0..=centre
+                {
+                    let value = target[(centre + dx, centre + dy)].g;
+                    for (x, y) in [(centre - dx, centre + dy), (centre + dx, centre - dy), (centre - dx, centre - dy), (centre + dy, centre + dx)]
+                    {
+                        assert!((target[(x, y)].g - value).abs() < 1e-6, "sigma {} isn't symmetric at {}, {}", sigma, dx, dy);
+                    }
+                }
+            }
+            assert!(target.pixels.iter().all(|p| p.g <= target[(centre, centre)].g));
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::image::{BorderMode, Image};
+use crate::pixel::Pixel;
+
+/// A [summed-area table]: every entry holds the sum of all pixels above and to the left of it, per channel,
+/// so the sum or mean of any rectangle takes four lookups, however large the rectangle is.
+///
+/// The table can reach `padding` pixels past every edge of the image, filled the way a `BorderMode` says,
+/// so that rectangles centred on edge pixels can be summed like any other. Channels are the normalised
+/// red, green and blue of `Pixel::to_rgba`, summed as `f64` so that large images don't lose precision.
+///
+/// [summed-area table]: https://en.wikipedia.org/wiki/Summed-area_table
+pub struct IntegralImage
+{
+    /// The size of the image the table was built from.
+    pub width: usize,
+    pub height: usize,
+    padding: usize,
+    border: BorderMode,
+    /// `width + 2 * padding + 1` entries per row, the first row and column are zero.
+    sums: Vec<[f64; 3]>,
+}
+
+impl IntegralImage
+{
+    /// Builds the table of `image`, extended by `padding` pixels on every side.
+    /// `constant` is the pixel `BorderMode::Constant` reads, already converted to `P`,
+    /// and `BorderMode::Crop` leaves the padding empty, see `mean`.
+    pub fn new<P: Pixel>(image: &Image<P>, padding: usize, border: BorderMode, constant: P) -> Self
+    {
+        let padded_width = image.width + 2 * padding;
+        let padded_height = image.height + 2 * padding;
+        let stride = padded_width + 1;
+        let mut sums = vec![[0.0; 3]; stride * (padded_height + 1)];
+        let constant = constant.to_rgba();
+        let mut row = vec![[0.0f32; 4]; padded_width];
+        for y in 0..padded_height
+        {
+            let image_y = border.resolve(y as isize - padding as isize, image.height);
+            for (x, pixel) in row.iter_mut().enumerate()
+            {
+                let image_x = border.resolve(x as isize - padding as isize, image.width);
+                *pixel = match (image_x, image_y)
+                {
+                    (Some(image_x), Some(image_y)) => image[(image_x, image_y)].to_rgba(),
+                    _ if matches!(border, BorderMode::Constant(_)) => constant,
+                    _ => [0.0; 4],
+                };
+            }
+            // running sum of the row, added to the sums of the row above
+            let mut line = [0.0f64; 3];
+            for (x, pixel) in row.iter().enumerate()
+            {
+                for channel in 0..3
+                {
+                    line[channel] += pixel[channel] as f64;
+                }
+                let above = sums[y * stride + x + 1];
+                sums[(y + 1) * stride + x + 1] = std::array::from_fn(|channel| above[channel] + line[channel]);
+            }
+        }
+        Self{width: image.width, height: image.height, padding, border, sums}
+    }
+
+    /// The sum of every channel over the pixels from `(left, top)` up to but not including `(right, bottom)`.
+    /// The coordinates may reach `padding` pixels past the edges of the image.
+    pub fn sum(&self, (left, top): (isize, isize), (right, bottom): (isize, isize)) -> [f64; 3]
+    {
+        let stride = self.width + 2 * self.padding + 1;
+        let index = |x: isize, y: isize| (y + self.padding as isize) as usize * stride + (x + self.padding as isize) as usize;
+        let (a, b) = (self.sums[index(left, top)], self.sums[index(right, top)]);
+        let (c, d) = (self.sums[index(left, bottom)], self.sums[index(right, bottom)]);
+        std::array::from_fn(|channel| d[channel] - b[channel] - c[channel] + a[channel])
+    }
+
+    /// The mean of every channel over the rectangle, see `sum`.
+    /// With `BorderMode::Crop` only the pixels inside the image count.
+    pub fn mean(&self, (left, top): (isize, isize), (right, bottom): (isize, isize)) -> [f64; 3]
+    {
+        let area = if self.border == BorderMode::Crop
+        {
+            let width = right.min(self.width as isize) - left.max(0);
+            let height = bottom.min(self.height as isize) - top.max(0);
+            width.max(0) * height.max(0)
+        }
+        else
+        {
+            (right - left) * (bottom - top)
+        };
+        let sum = self.sum((left, top), (right, bottom));
+        if area <= 0
+        {
+            return [0.0; 3];
+        }
+        sum.map(|channel| channel / area as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::pixel::RgbF32;
+
+    const BORDERS: [BorderMode; 5] = [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Constant(crate::pixel::Rgba8::rgb(40, 80, 160)), BorderMode::Crop];
+
+    fn random_image(width: usize, height: usize, rng: &fastrand::Rng) -> Image<RgbF32>
+    {
+        let mut image = Image::new(width, height, RgbF32::default());
+        for pixel in image.pixels.iter_mut()
+        {
+            *pixel = RgbF32::new(rng.f32(), rng.f32(), rng.f32());
+        }
+        image
+    }
+
+    /// The sum of the rectangle and the number of pixels in it, one pixel at a time.
+    fn naive_sum(image: &Image<RgbF32>, border: BorderMode, constant: RgbF32, (left, top): (isize, isize), (right, bottom): (isize, isize)) -> ([f64; 3], usize)
+    {
+        let (mut sum, mut count) = ([0.0f64; 3], 0);
+        for y in top..bottom
+        {
+            for x in left..right
+            {
+                let pixel = match (border.resolve(x, image.width), border.resolve(y, image.height))
+                {
+                    (Some(x), Some(y)) => image[(x, y)],
+                    _ if border == BorderMode::Crop => continue,
+                    _ => constant,
+                };
+                for (channel, value) in [pixel.r, pixel.g, pixel.b].into_iter().enumerate()
+                {
+                    sum[channel] += value as f64;
+                }
+                count += 1;
+            }
+        }
+        (sum, count)
+    }
+
+    fn assert_close(actual: [f64; 3], expected: [f64; 3], what: &str)
+    {
+        for channel in 0..3
+        {
+            assert!((actual[channel] - expected[channel]).abs() < 1e-6, "{}: {:?} instead of {:?}", what, actual, expected);
+        }
+    }
+
+    #[test]
+    fn sums_and_means_match_adding_up_the_pixels()
+    {
+        let rng = fastrand::Rng::with_seed(7);
+        let constant = RgbF32::new(0.25, 0.5, 0.75);
+        for (width, height) in [(1, 1), (1, 5), (6, 1), (9, 7), (16, 16)]
+        {
+            let image = random_image(width, height, &rng);
+            for border in BORDERS
+            {
+                let padding = 3;
+                let table = IntegralImage::new(&image, padding, border, constant);
+                let (min, max_x, max_y) = (-(padding as isize), (width + padding) as isize, (height + padding) as isize);
+                let mut rectangles = vec![((0, 0), (width as isize, height as isize)), ((min, min), (max_x, max_y)), ((0, 0), (1, 1)), ((min, 0), (0, 1)), ((2, 2), (2, 2))];
+                for _ in 0..200
+                {
+                    let (left, top) = (rng.isize(min..=max_x), rng.isize(min..=max_y));
+                    rectangles.push(((left, top), (rng.isize(left..=max_x), rng.isize(top..=max_y))));
+                }
+                for (from, to) in rectangles
+                {
+                    let what = format!("{}x{} {:?} {:?} to {:?}", width, height, border, from, to);
+                    let (sum, count) = naive_sum(&image, border, constant, from, to);
+                    assert_close(table.sum(from, to), sum, &what);
+                    let mean = if count == 0 {[0.0; 3]} else {sum.map(|channel| channel / count as f64)};
+                    assert_close(table.mean(from, to), mean, &what);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_blur_matches_averaging_every_square()
+    {
+        let rng = fastrand::Rng::with_seed(11);
+        for (width, height) in [(1, 1), (3, 8), (13, 10)]
+        {
+            let image = random_image(width, height, &rng);
+            for border in BORDERS
+            {
+                let constant = RgbF32::from_srgb(match border {BorderMode::Constant(colour) => colour, _ => crate::pixel::Rgba8::BLACK});
+                for kernel_size in [1, 3, 5, 9]
+                {
+                    let mut blurred = image.clone();
+                    image.box_blur(&mut blurred, kernel_size, border);
+                    let radius = (kernel_size / 2) as isize;
+                    for y in 0..height
+                    {
+                        for x in 0..width
+                        {
+                            let (x, y) = (x as isize, y as isize);
+                            let (sum, count) = naive_sum(&image, border, constant, (x - radius, y - radius), (x + radius + 1, y + radius + 1));
+                            let pixel = blurred[(x as usize, y as usize)];
+                            let what = format!("{}x{} {:?} size {} at {}, {}", width, height, border, kernel_size, x, y);
+                            assert_close([pixel.r as f64, pixel.g as f64, pixel.b as f64], sum.map(|channel| channel / count as f64), &what);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+/// The weights of an [image processing kernel], `width` by `height`, stored row by row.
+///
+/// Both sizes must be odd, so that the kernel has a centre pixel. `Image::convolve` runs kernels that are separable,
+/// like box and Gaussian blurs, as a horizontal and a vertical pass, which costs `width + height` taps per pixel
+/// instead of `width * height`.
+///
+/// [image processing kernel]: https://en.wikipedia.org/wiki/Kernel_(image_processing)
+#[derive(PartialEq, Clone, Debug)]
+pub struct Kernel
+{
+    pub width: usize,
+    pub height: usize,
+    pub weights: Vec<f32>,
+}
+
+impl Kernel
+{
+    pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Self
+    {
+        assert!(width % 2 == 1 && height % 2 == 1, "kernel sizes must be odd, got {}x{}", width, height);
+        assert_eq!(weights.len(), width * height, "a {}x{} kernel needs {} weights", width, height, width * height);
+        Self{width, height, weights}
+    }
+
+    /// Creates a kernel from a function that takes in `(kernel_x, kernel_y)` and returns the weight there.
+    pub fn from_fn<F>(width: usize, height: usize, mut generator: F) -> Self where F: FnMut(usize, usize) -> f32
+    {
+        let weights = (0..width * height).map(|i| generator(i % width, i / width)).collect();
+        Kernel::new(width, height, weights)
+    }
+
+    /// A normalised Gaussian with the standard deviation `sigma`, reaching out three sigmas.
+    /// It is separable, so `Image::convolve` runs it as two passes.
+    pub fn gaussian(sigma: f32) -> Self
+    {
+        let radius = (sigma * 3.0).ceil().max(1.0) as usize;
+        let size = radius * 2 + 1;
+        let denominator = 2.0 * sigma * sigma;
+        Kernel::from_fn(size, size, |x, y|
+        {
+            let (dx, dy) = (x as f32 - radius as f32, y as f32 - radius as f32);
+            (-(dx * dx + dy * dy) / denominator).exp()
+        }).normalised()
+    }
+
+    pub fn sum(&self) -> f32
+    {
+        self.weights.iter().sum()
+    }
+
+    /// Scales the weights so that they add up to 1, so the kernel keeps the brightness of the image.
+    /// Kernels that add up to 0, like edge detectors, are returned unchanged.
+    pub fn normalised(mut self) -> Self
+    {
+        let sum = self.sum();
+        if sum != 0.0
+        {
+            for weight in &mut self.weights
+            {
+                *weight /= sum;
+            }
+        }
+        self
+    }
+
+    /// Splits the kernel into a row and a column whose product gives the kernel back, if that's possible.
+    ///
+    /// Running the row over the image and then the column over the result is the same as running the whole kernel.
+    pub fn separate(&self) -> Option<(Vec<f32>, Vec<f32>)>
+    {
+        // the largest weight gives the most accurate row and column
+        let (pivot, &largest) = self.weights.iter().enumerate().max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))?;
+        if largest == 0.0
+        {
+            return None;
+        }
+        let (pivot_x, pivot_y) = (pivot % self.width, pivot / self.width);
+        let row = self.weights[pivot_y * self.width..(pivot_y + 1) * self.width].to_vec();
+        let column = (0..self.height).map(|y| self.weights[y * self.width + pivot_x] / largest).collect::<Vec<_>>();
+        let tolerance = largest.abs() * 1e-5;
+        let matches = self.weights.chunks(self.width).zip(&column)
+            .all(|(weights, c)| weights.iter().zip(&row).all(|(&weight, r)| (weight - c * r).abs() <= tolerance));
+        if !matches
+        {
+            return None;
+        }
+        Some((row, column))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Checks that `row` times `column` gives back every weight of `kernel`.
+    fn assert_product(kernel: &Kernel, (row, column): &(Vec<f32>, Vec<f32>))
+    {
+        assert_eq!((row.len(), column.len()), (kernel.width, kernel.height));
+        for (y, c) in column.iter().enumerate()
+        {
+            for (x, r) in row.iter().enumerate()
+            {
+                assert!((c * r - kernel.weights[y * kernel.width + x]).abs() < 1e-6, "{:?} at {}, {}", kernel, x, y);
+            }
+        }
+    }
+
+    /// `factor` scaled so that its first largest entry is 1, to compare factors that may be split differently between row and column.
+    fn shape(factor: &[f32]) -> Vec<f32>
+    {
+        let largest = factor.iter().fold(0.0f32, |largest, &v| if v.abs() > largest.abs() {v} else {largest});
+        factor.iter().map(|v| v / largest).collect()
+    }
+
+    #[test]
+    fn blurs_separate_into_their_factors()
+    {
+        for sigma in [0.3, 0.8, 1.0, 1.7, 2.5]
+        {
+            let kernel = Kernel::gaussian(sigma);
+            let factors = kernel.separate().expect("Gaussians are separable");
+            assert_product(&kernel, &factors);
+            let (row, column) = &factors;
+            assert!(row.iter().zip(row.iter().rev()).all(|(a, b)| (a - b).abs() < 1e-7), "sigma {} gives {:?}", sigma, row);
+            assert!((row.iter().sum::<f32>() * column.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        }
+
+        let gaussian_3x3 = Kernel::new(3, 3, vec![1.0, 2.0, 1.0, 2.0, 4.0, 2.0, 1.0, 2.0, 1.0]).normalised();
+        let factors = gaussian_3x3.separate().unwrap();
+        assert_product(&gaussian_3x3, &factors);
+        assert_eq!((shape(&factors.0), shape(&factors.1)), (vec![0.5, 1.0, 0.5], vec![0.5, 1.0, 0.5]));
+
+        let box_5x3 = Kernel::from_fn(5, 3, |_, _| 1.0 / 15.0);
+        let factors = box_5x3.separate().unwrap();
+        assert_product(&box_5x3, &factors);
+        assert_eq!((shape(&factors.0), shape(&factors.1)), (vec![1.0; 5], vec![1.0; 3]));
+    }
+
+    #[test]
+    fn sobel_separates_into_a_difference_and_a_smoothing()
+    {
+        // Sobel is the outer product of [1, 2, 1] and [1, 0, -1], so it runs as two passes as well
+        let sobel_x = Kernel::new(3, 3, vec![1.0, 0.0, -1.0, 2.0, 0.0, -2.0, 1.0, 0.0, -1.0]);
+        let factors = sobel_x.separate().unwrap();
+        assert_product(&sobel_x, &factors);
+        assert_eq!((shape(&factors.0), shape(&factors.1)), (vec![1.0, 0.0, -1.0], vec![0.5, 1.0, 0.5]));
+        let sobel_y = Kernel::new(3, 3, vec![1.0, 2.0, 1.0, 0.0, 0.0, 0.0, -1.0, -2.0, -1.0]);
+        let factors = sobel_y.separate().unwrap();
+        assert_product(&sobel_y, &factors);
+        assert_eq!((shape(&factors.0), shape(&factors.1)), (vec![0.5, 1.0, 0.5], vec![1.0, 0.0, -1.0]));
+    }
+
+    #[test]
+    fn other_kernels_stay_whole()
+    {
+        let emboss = Kernel::new(3, 3, vec![-2.0, -1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0, 2.0]);
+        let outline = Kernel::new(3, 3, vec![-1.0, -1.0, -1.0, -1.0, 8.0, -1.0, -1.0, -1.0, -1.0]);
+        let sharpen = Kernel::new(3, 3, vec![0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0]);
+        let nearly_separable = Kernel::from_fn(5, 5, |x, y| if (x, y) == (4, 4) {1.1} else {1.0});
+        for kernel in [emboss, outline, sharpen, nearly_separable, Kernel::new(3, 1, vec![0.0; 3])]
+        {
+            assert_eq!(kernel.separate(), None, "{:?}", kernel);
+        }
+    }
+
+    #[test]
+    fn normalising_keeps_zero_sum_kernels()
+    {
+        let outline = Kernel::new(3, 3, vec![-1.0, -1.0, -1.0, -1.0, 8.0, -1.0, -1.0, -1.0, -1.0]);
+        assert_eq!(outline.clone().normalised(), outline);
+        assert!((Kernel::from_fn(3, 5, |x, y| (x + y) as f32).normalised().sum() - 1.0).abs() < 1e-6);
+    }
+}
@@ -1,23 +1,103 @@
+pub mod pixel;
 pub mod pixel_traits;
 pub mod image;
-
-use image::Image;
+pub mod kernel;
+pub mod integral;
+pub mod dither;
+pub mod source;
+pub mod y4m;
+pub mod pixel_format;
+pub mod cli;
+pub mod filter;
+pub mod processor;
+pub mod palette;
+pub mod colour_match;
+pub mod quantise;
+pub mod preset;
+pub mod png_io;
+pub mod batch;
+#[cfg(test)]
+mod test_support;
+
+use image::{BorderMode, Image, FitMode, ResampleFilter};
 use olc_pge as olc;
-use camera_capture;
-use pixel_traits::*;
+use processor::{Buffers, InputMode, Pipeline};
+use palette::PaletteLibrary;
+use quantise::{AutoPalette, QuantisationMethod};
+use source::{FrameSource, CameraSource, StaticSource, ImageSequenceSource, TestPattern, TestPatternSource, Y4mSource, RawFileSource, ThreadedCapture, FittedSource};
 
 fn main()
 {
+    let command = cli::parse_args(std::env::args().skip(1)).and_then(|command| match command
+    {
+        cli::Command::Run(mut options) => options.load_files().map(|_| cli::Command::Run(options)),
+        help => Ok(help),
+    });
+    let options = match command
+    {
+        Ok(cli::Command::Run(options)) => *options,
+        Ok(cli::Command::Help) =>
+        {
+            print!("{}", cli::USAGE);
+            println!("\nPROCESSORS:");
+            for filter in filter::FILTERS.iter()
+            {
+                println!("    {:<38}{}", filter.name(), filter.description());
+            }
+            return;
+        }
+        Err(e) =>
+        {
+            eprintln!("error: {}\n\nRun with --help to see all options.", e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(threads) = options.threads
+    {
+        if let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(threads).build_global()
+        {
+            exit_with_error(&format!("Could not start {} processing threads: {}", threads, e));
+        }
+    }
+    if let Err(e) = std::fs::create_dir_all(&options.output_dir)
+    {
+        exit_with_error(&format!("Could not create output directory {}: {}", options.output_dir.display(), e));
+    }
+    if options.batch
+    {
+        match batch::run(&options)
+        {
+            Ok(frames) => println!("Wrote {} processed frames to {}.", frames, options.output_dir.display()),
+            Err(e) => exit_with_error(&format!("Batch processing failed: {}", e)),
+        }
+        return;
+    }
+    #[cfg(windows)]
     println!("Make sure to put escapi.dll in the same folder as this executable.");
-    let pixelsize = get_pixel_size_input();
-    let width = 640/pixelsize;
-    let height = width * 9 / 16;
+
+    // cameras only support a few resolutions, so capture at a common one and fit the frames to the processing resolution,
+    // which in turn can differ from the size of the window
+    let capture_size = options.capture_size;
+    let processing_size = options.processing_size();
+    let display_size = options.display_size();
+    let fit_mode = options.fit_mode;
+    let fps = options.fps;
+    let pixelsize = options.pixel_size;
     
-    let cam = camera_capture::create(0).unwrap();
-    let cam_iter = cam.fps(30.0).unwrap().resolution(width as u32, height as u32).unwrap().start().unwrap();
+    let pixels = (0..processing_size.0*processing_size.1).map(|_x| pixel::Rgba8::MAGENTA).collect::<Vec<pixel::Rgba8>>();
+    let frame = Image{width: processing_size.0, height: processing_size.1, pixels};
 
-    let pixels = (0..width*height).map(|_x| olc::MAGENTA).collect::<Vec<olc::Pixel>>();
-    let frame = Image{width,height, pixels};
+    let selection = options.input.clone();
+    let capture = ThreadedCapture::spawn(3, move ||
+    {
+        let source = open_source(selection, fps, capture_size, processing_size)?;
+        Ok(Box::new(FittedSource::new(source, processing_size.0, processing_size.1, fit_mode, ResampleFilter::Bilinear)))
+    });
+    let capture = match capture
+    {
+        Ok(capture) => capture,
+        Err(e) => exit_with_error(&e),
+    };
     
     let slider = Slider
     {
@@ -26,69 +106,76 @@ fn main()
         w: 50,
         h: 20,
         start_val: 0,
-        end_val: (Processor::CrossBlur as u32),
+        end_val: (filter::FILTERS.len() - 1) as u32,
         step_size: 1,
-        current_val: Processor::Normal as u32,
+        current_val: filter::index_of(options.pipeline.stages[0].filter) as u32,
     };
 
-    let window = Window::new
-    (
-        cam_iter,
-        slider,
-        frame
-    );
-    olc::PixelGameEngine::construct(window, width, height, pixelsize*2, pixelsize*2).start();
+    let window = Window::new(capture, slider, frame, options);
+    olc::PixelGameEngine::construct(window, display_size.0, display_size.1, pixelsize*2, pixelsize*2).start();
 }
 
-fn get_pixel_size_input() -> usize
+/// Opens the selected input. Runs on the capture thread, which hands any error back to main.
+/// 
+/// Test patterns are generated at `processing_size`; everything else delivers frames at its own size.
+fn open_source(selection: cli::InputSelection, fps: f64, capture_size: (usize, usize), (width, height): (usize, usize)) -> Result<Box<dyn FrameSource>, String>
 {
-    println!("Please enter the pixel size you want. Pixel sizes larger than 1 come with decreased resolution (image looks pixelated).");
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-    let output = 
-    loop
+    let source: Box<dyn FrameSource> = match selection
     {
-        match input.trim().parse::<usize>()
+        cli::InputSelection::Sequence{directory, fps} =>
         {
-            Err(_e) => println!("Invalid input. Input must be a natural number between 1 and 32. Recommended values are: 1, 2, 4, and 8."),
-            Ok(num) => if num <= 32 && num >= 1{break num} else{println!("Value was outside of permissible range. Enter a value between 1 and 32.");},
-        };
-        std::io::stdin().read_line(&mut input).unwrap();
+            match ImageSequenceSource::open(&directory, fps)
+            {
+                Ok(sequence) => Box::new(sequence),
+                Err(e) => return Err(format!("Could not open image sequence {}: {}", directory.display(), e)),
+            }
+        }
+        cli::InputSelection::Y4m(path) =>
+        {
+            match Y4mSource::open(&path)
+            {
+                Ok(video) => Box::new(video),
+                Err(e) => return Err(format!("Could not open video {}: {}", path.display(), e)),
+            }
+        }
+        cli::InputSelection::Raw{path, format, conversion, frame_size, fps} =>
+        {
+            match RawFileSource::open(&path, format, conversion, frame_size, fps)
+            {
+                Ok(raw) => Box::new(raw),
+                Err(e) => return Err(format!("Could not open raw video {}: {}", path.display(), e)),
+            }
+        }
+        cli::InputSelection::Image(path) =>
+        {
+            match png_io::load_png(&path)
+            {
+                Ok(image) => Box::new(StaticSource::new(image)),
+                Err(e) => return Err(format!("Could not open image {}: {}", path.display(), e)),
+            }
+        }
+        cli::InputSelection::Pattern{pattern, seed} => Box::new(TestPatternSource::new(pattern, width, height).with_seed(seed).with_fps(fps)),
+        cli::InputSelection::Camera{index, fallback} =>
+        {
+            match CameraSource::new(index, fps, capture_size.0 as u32, capture_size.1 as u32)
+            {
+                Ok(camera) => Box::new(camera),
+                Err(e) if !fallback => return Err(format!("Could not open camera {}: {:?}", index, e)),
+                Err(e) =>
+                {
+                    eprintln!("Could not open camera {} ({:?}). Showing colour bars instead.", index, e);
+                    Box::new(TestPatternSource::new(TestPattern::ColourBars, width, height))
+                }
+            }
+        }
     };
-    return output;
+    Ok(source)
 }
 
-#[allow(dead_code)]
-#[derive(PartialEq, Clone, Copy, Debug)]
-enum Processor
+fn exit_with_error(message: &str) -> !
 {
-    Normal,
-    Sobel,
-    SobelColour,
-    Threshold,
-    ThresholdColour,
-    RandomBiasDithering,
-    PatternedDithering,
-    FloydSteinbergDithering,
-    FloydSteinbergDitheringCustomPalette,
-    GaussianBlur,
-    BoxBlur,
-    Emboss,
-    Outline,
-    GreyScale,
-    ChromaticAberration,
-    Sharpen,
-    SharpenColour,
-    CrossBlur,
-}
-
-#[allow(dead_code)]
-#[derive(PartialEq, Clone, Copy, Debug)]
-enum InputMode
-{
-    Normal, 
-    TimeBlend,
-    Denoising,
+    eprintln!("{}", message);
+    std::process::exit(1);
 }
 
 struct Slider
@@ -113,15 +200,15 @@ impl Slider
             let delta_val = self.end_val as i32 - self.start_val as i32;
             self.current_val = (self.start_val as i32 + (((x - self.x) * delta_val / self.w) / self.step_size as i32 ) * self.step_size as i32) as u32;
         }
-        return self.current_val;
+        self.current_val
     }
 
     fn is_hovering(&self, x: i32, y: i32) -> bool
     {
         let rightx = self.x + self.w;
         let bottomy = self.y + self.h;
-        return x >= self.x && x <= rightx
-        && y >= self.y && y <= bottomy;
+        x >= self.x && x <= rightx
+        && y >= self.y && y <= bottomy
     }
 
     fn get_slider_x(&self) -> i32
@@ -133,100 +220,260 @@ impl Slider
 
 struct Window
 {
-    cam_iter: camera_capture::ImageIterator,
+    capture: ThreadedCapture,
     slider: Slider,
-    processors: Vec<Processor>,
-    input_mode: InputMode,
+    /// The processor chain, its input mode and palette.
+    pipeline: Pipeline,
+    /// Index of the stage in `pipeline.stages` that the slider, [<] [>] and the parameter widgets change.
+    selected_stage: usize,
+    /// The preset that [L] loads: the one given with `--preset` or the last one saved.
+    preset_path: Option<std::path::PathBuf>,
+    /// The palette files that [N] goes through.
+    palette_library: PaletteLibrary,
     frame_time: std::time::Duration,
     hide_ui: bool,
     frame_counter: u64,
+    /// Capture timestamp and sequence number of the frame in `frame`.
+    last_frame: Option<(std::time::Instant, u64)>,
+    /// How the processed image is fitted to the window if their sizes differ.
+    fit_mode: FitMode,
+    /// Where saved images, recordings and presets are written to.
+    output_dir: std::path::PathBuf,
+    recorder: Option<y4m::Y4mWriter<std::io::BufWriter<std::fs::File>>>,
+    /// The frame rate recordings are played back at: the one of the source, or the one it was asked for.
+    frame_rate: (u32, u32),
     frame: Image,
     target: Image,
-    _temp: Image, //remove underscore when you actually need this
+    buffers: Buffers,
 }
 
 impl Window
 {
-    fn new(cam_iter: camera_capture::ImageIterator, slider: Slider, frame: Image) -> Self
+    fn new(capture: ThreadedCapture, slider: Slider, frame: Image, options: cli::Options) -> Self
     {
         Self
         {
-            cam_iter,
+            frame_rate: capture.frame_rate().unwrap_or_else(|| y4m::frame_rate(options.fps)),
+            fit_mode: options.fit_mode,
+            output_dir: options.output_dir,
+            capture,
+            last_frame: None,
             slider,
-            processors: vec![Processor::FloydSteinbergDitheringCustomPalette],
-            input_mode: InputMode::Normal,
+            pipeline: options.pipeline,
+            selected_stage: 0,
+            preset_path: options.preset_path,
+            palette_library: PaletteLibrary::new(options.palette_dir),
             hide_ui: false,
             frame_counter: 0,
+            recorder: None,
             frame_time: std::time::Duration::from_millis(0),
             target: frame.clone(),
-            _temp: frame.clone(),
+            buffers: Buffers::new(frame.width, frame.height),
             frame
         }
     }
 
-    fn pre_process_input(&mut self)
+    /// Reads the next frame from the source into `self.frame`. Returns `false` if the source had no new frame.
+    fn pre_process_input(&mut self) -> bool
     {
-        let frame = match self.cam_iter.next()
+        let frame = match self.capture.next_captured()
         {
             Some(f) => f,
-            None => return
+            None => return false
         };
-        match self.input_mode
-        {
-            InputMode::Normal
-            =>  {
-                    for (i, pixel) in frame.pixels().enumerate()
-                    {
-                        self.frame.pixels[i] = olc::Pixel::rgb(pixel.data[0], pixel.data[1], pixel.data[2]);
-                    }
-                }
-            ,
+        self.last_frame = Some((frame.timestamp, frame.sequence));
+        let frame = frame.image;
+        self.pipeline.input_mode.blend(&mut self.frame, &frame);
+        true
+    }
 
-            InputMode::TimeBlend
-            => 
-                // fraction.0 is proportional to the influence of the next frame
-                for (i, pixel) in frame.pixels().enumerate()
-                {
-                    let fraction = (2, 10);
-                    let pa = self.frame.pixels[i];
-                    let pb = olc::Pixel::rgb(pixel.data[0], pixel.data[1], pixel.data[2]);
+    /// Handles the keys that add, remove, select and reorder the stages of the processor chain.
+    fn edit_chain(&mut self, pge: &olc::PixelGameEngine)
+    {
+        if pge.get_key(olc::Key::Tab).pressed
+        {
+            self.selected_stage = (self.selected_stage + 1) % self.pipeline.stages.len();
+        }
+        if pge.get_key(olc::Key::A).pressed
+        {
+            // the new stage starts out as a copy of the selected one, which can then be changed with [<] [>]
+            self.pipeline.stages.insert(self.selected_stage + 1, self.pipeline.stages[self.selected_stage].clone());
+            self.selected_stage += 1;
+        }
+        if pge.get_key(olc::Key::X).pressed && self.pipeline.stages.len() > 1
+        {
+            self.pipeline.stages.remove(self.selected_stage);
+            self.selected_stage = self.selected_stage.min(self.pipeline.stages.len() - 1);
+        }
+        if pge.get_key(olc::Key::PageUp).pressed && self.selected_stage > 0
+        {
+            self.pipeline.stages.swap(self.selected_stage, self.selected_stage - 1);
+            self.selected_stage -= 1;
+        }
+        if pge.get_key(olc::Key::PageDown).pressed && self.selected_stage + 1 < self.pipeline.stages.len()
+        {
+            self.pipeline.stages.swap(self.selected_stage, self.selected_stage + 1);
+            self.selected_stage += 1;
+        }
+        self.slider.current_val = filter::index_of(self.pipeline.stages[self.selected_stage].filter) as u32;
+    }
 
-                    let mut r = pa.r as u32 * fraction.1;
-                    let mut g = pa.g as u32 * fraction.1;
-                    let mut b = pa.b as u32 * fraction.1;
+    /// Position and size of the widget of parameter `index` of the selected stage.
+    fn parameter_widget(index: usize) -> (i32, i32, i32, i32)
+    {
+        (5, 45 + 12 * index as i32, 60, 8)
+    }
 
-                    r -= pa.r as u32 * fraction.0;
-                    g -= pa.g as u32 * fraction.0;
-                    b -= pa.b as u32 * fraction.0;
+    /// Sets the parameter of the selected stage whose widget is being dragged with the mouse.
+    fn edit_parameters(&mut self, pge: &olc::PixelGameEngine)
+    {
+        if !pge.get_mouse(0).held
+        {
+            return;
+        }
+        let (mouse_x, mouse_y) = (pge.get_mouse_x(), pge.get_mouse_y());
+        let stage = &mut self.pipeline.stages[self.selected_stage];
+        for (i, parameter) in stage.filter.parameters().iter().enumerate()
+        {
+            let (x, y, w, h) = Self::parameter_widget(i);
+            // a little leeway on both ends, so the extremes are easy to hit
+            if (x - 4..=x + w + 4).contains(&mouse_x) && (y..=y + h).contains(&mouse_y)
+            {
+                stage.set_value(i, parameter.from_fraction((mouse_x - x) as f32 / w as f32));
+            }
+        }
+    }
 
-                    r += pb.r as u32 * fraction.0;
-                    g += pb.g as u32 * fraction.0;
-                    b += pb.b as u32 * fraction.0;
+    fn draw_parameters(&self, pge: &mut olc::PixelGameEngine)
+    {
+        let stage = &self.pipeline.stages[self.selected_stage];
+        for (i, (parameter, &value)) in stage.filter.parameters().iter().zip(&stage.values).enumerate()
+        {
+            let (x, y, w, h) = Self::parameter_widget(i);
+            pge.fill_rect(x, y, w as u32, h as u32, olc::Pixel::rgb(70, 150, 140));
+            pge.fill_rect(x + (parameter.fraction(value) * (w - 2) as f32) as i32, y, 2, h as u32, olc::Pixel::rgb(200, 235, 225));
+            pge.draw_string(x + w + 5, y, &format!("{}: {}", parameter.name, parameter.format(value)), olc::WHITE);
+        }
+    }
 
-                    r /= fraction.1;
-                    g /= fraction.1;
-                    b /= fraction.1;
+    /// Writes the pipeline to a new preset file in the output directory, which [L] then loads.
+    fn save_preset(&mut self)
+    {
+        let pathstring = self.output_dir.join(String::from("preset_") + &format!("{:x}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros()) + ".preset");
+        match preset::save(&self.pipeline, &pathstring)
+        {
+            Ok(()) =>
+            {
+                println!("Saved preset {}", pathstring.display());
+                self.preset_path = Some(pathstring);
+            }
+            Err(e) => eprintln!("Could not save preset {}: {}", pathstring.display(), e),
+        }
+    }
 
-                    self.frame.pixels[i] = olc::Pixel::rgb(r as u8, g as u8, b as u8);
-                }
-            ,
+    /// Replaces the pipeline with the preset file, so that edits made in a text editor can be tried out.
+    fn load_preset(&mut self)
+    {
+        let path = match &self.preset_path
+        {
+            Some(path) => path,
+            None =>
+            {
+                println!("No preset to load. Start with --preset <FILE> or save one with [P].");
+                return;
+            }
+        };
+        match preset::load(path)
+        {
+            Ok(pipeline) =>
+            {
+                println!("Loaded preset {}", path.display());
+                self.pipeline = pipeline;
+                self.selected_stage = 0;
+                self.slider.current_val = filter::index_of(self.pipeline.stages[0].filter) as u32;
+            }
+            Err(e) => eprintln!("Could not load preset {}: {}", path.display(), e),
+        }
+    }
 
-            InputMode::Denoising
-            => 
-                //denoising based on pixel difference between frames
-                for (i, pixel) in frame.pixels().enumerate()
-                {
-                    let p = temporal_denoising(self.frame.pixels[i], olc::Pixel::rgb(pixel.data[0], pixel.data[1], pixel.data[2]));
-                    self.frame.pixels[i] = p;
-                }
-            ,
+    /// Replaces the palette with the next file of the palette library, which also turns off the automatic palette.
+    fn next_library_palette(&mut self)
+    {
+        match self.palette_library.load_next()
+        {
+            Ok((path, colours)) =>
+            {
+                println!("Loaded palette {} with {} colours", path.display(), colours.len());
+                self.pipeline.palette = colours;
+                self.pipeline.auto_palette = None;
+            }
+            Err(e) => eprintln!("Could not load a palette from {}: {}", self.palette_library.directory.display(), e),
+        }
+    }
+
+    /// Writes the current palette to a new GIMP palette file in the output directory.
+    fn save_palette(&self)
+    {
+        let pathstring = self.output_dir.join(String::from("palette_") + &format!("{:x}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros()) + ".gpl");
+        match palette::save(&self.pipeline.palette, &pathstring)
+        {
+            Ok(()) => println!("Saved palette {}", pathstring.display()),
+            Err(e) => eprintln!("Could not save palette {}: {}", pathstring.display(), e),
+        }
+    }
+
+    /// Switches from the fixed palette to extracting it with each `QuantisationMethod` in turn, then back.
+    /// Going back to the fixed palette keeps the colours last extracted.
+    fn cycle_auto_palette(&mut self)
+    {
+        let next = match &self.pipeline.auto_palette
+        {
+            None => Some(QuantisationMethod::ALL[0]),
+            Some(auto_palette) => QuantisationMethod::ALL.get(auto_palette.method.index() + 1).copied(),
+        };
+        self.pipeline.auto_palette = next.map(|method|
+        {
+            let (colours, adaptation) = match &self.pipeline.auto_palette
+            {
+                Some(auto_palette) => (auto_palette.colours, auto_palette.adaptation),
+                None => (16, AutoPalette::DEFAULT_ADAPTATION),
+            };
+            AutoPalette::new(method, colours, adaptation)
+        });
+    }
+
+    fn toggle_recording(&mut self)
+    {
+        if let Some(mut recorder) = self.recorder.take()
+        {
+            if let Err(e) = recorder.flush()
+            {
+                eprintln!("Could not finish the recording: {}", e);
+            }
+            return;
+        }
+        let pathstring = self.output_dir.join(String::from("recording_") + &format!("{:x}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros()) + ".y4m");
+        let header = y4m::Y4mHeader
+        {
+            width: self.target.width,
+            height: self.target.height,
+            frame_rate: self.frame_rate,
+            chroma: y4m::Chroma::C444,
+            full_range: true,
+        };
+        let recorder = std::fs::File::create(&pathstring)
+            .and_then(|file| y4m::Y4mWriter::new(std::io::BufWriter::new(file), header));
+        match recorder
+        {
+            Ok(recorder) => self.recorder = Some(recorder),
+            Err(e) => eprintln!("Could not start recording to {}: {}", pathstring.display(), e),
         }
     }
 }
 
 impl olc::PGEApplication for Window
 {
-    const APP_NAME: &'static str = "[Webcam Image Processor] | Press [S] to save image. |";
+    const APP_NAME: &'static str = "[Webcam Image Processor] | Press [S] to save image, [R] to record video. |";
     fn on_user_create(&mut self, _pge: &mut olc::PixelGameEngine) -> bool
     {
         true
@@ -239,52 +486,47 @@ impl olc::PGEApplication for Window
             std::thread::sleep(std::time::Duration::from_millis(80));
             return true;
         }
-        if self.frame_counter % 1 == 0
-        {
-            self.pre_process_input();
-        }
+        let new_frame = self.pre_process_input();
         self.frame_counter += 1;
 
         let past_input = std::time::Instant::now();
 
-        for processor in &self.processors
+        if new_frame
+        {
+            self.pipeline.update_palette(&self.frame);
+        }
+        self.pipeline.run(&self.frame, &mut self.target, &mut self.buffers);
+
+        if new_frame
         {
-            use Processor::*;
-            let rgb = olc::Pixel::rgb;
-            //process frame
-            match processor
+            if let Some(recorder) = &mut self.recorder
             {
-                Normal => self.target.pixels.copy_from_slice(&self.frame.pixels),
-                Sobel => self.frame.sobel_edge_detection_3x3(&mut self.target),
-                SobelColour => self.frame.sobel_edge_detection_3x3_colour(&mut self.target),
-                Threshold => self.frame.threshold(&mut self.target, (pge.get_mouse_x()*255/ pge.screen_width() as i32) as u8),
-                ThresholdColour => self.frame.threshold_colour(&mut self.target, (pge.get_mouse_x()*255/ pge.screen_width() as i32) as u8),
-                RandomBiasDithering => self.frame.random_bias_dithering(&mut self.target, pge.get_mouse_x() as usize * 8 / pge.screen_width() + 1),//random_bias_dithering(&mut self.target, pge.get_mouse_x() as usize * 8 / pge.screen_width() + 1),
-                PatternedDithering => self.frame.patterned_dithering(&mut self.target, pge.get_mouse_x() as usize * 8 / pge.screen_width() + 1),
-                FloydSteinbergDithering => self.frame.floyd_steinberg_dithering(&mut self.target, pge.get_mouse_x() as usize * 8 / pge.screen_width() + 1),
-                FloydSteinbergDitheringCustomPalette => self.frame.floyd_steinberg_with_custom_colour_palette(&mut self.target, &[rgb(0,60,60),rgb(140,120,50),rgb(255,225,0),rgb(60,60,80),rgb(60,60,140),rgb(80,0,0),rgb(120,60,50),rgb(50,150,120),rgb(120,100,200)]),
-                GaussianBlur => self.frame.gaussian_blur_3x3(&mut self.target),
-                BoxBlur => self.frame.box_blur(&mut self.target, ((((pge.get_mouse_x() as usize * 255 * 49 / pge.screen_width().pow(2) )/2)*2 + 1)).min((pge.screen_width()/2)*2 - 1).max(3)),
-                Emboss => self.frame.emboss(&mut self.target),
-                Outline => self.frame.outline(&mut self.target),
-                GreyScale => self.frame.greyscale(&mut self.target),
-                ChromaticAberration => self.frame.chromatic_aberration(&mut self.target, (pge.get_mouse_x() as usize * 255/ pge.screen_width())/20),
-                Sharpen => self.frame.sharpen(&mut self.target),
-                SharpenColour => self.frame.sharpen_colour(&mut self.target),
-                CrossBlur => self.frame.cross_blur(&mut self.target),
-            };
+                if let Err(e) = recorder.write_frame(&self.target)
+                {
+                    eprintln!("Recording stopped: {}", e);
+                    self.recorder = None;
+                }
+            }
         }
 
         
         if pge.get_mouse(0).held
         {
             let value = self.slider.get_value(pge.get_mouse_x(), pge.get_mouse_y());
-            self.processors[0] = unsafe{std::mem::transmute::<u8, Processor>(value as u8)};
+            self.pipeline.stages[self.selected_stage].set_filter(filter::FILTERS[value as usize]);
         }
 
+        self.edit_chain(pge);
+        self.edit_parameters(pge);
+
         if pge.get_key(olc::Key::S).pressed
         {
-            save_image_as_png(&self.target);
+            save_image_as_png(&self.target, &self.output_dir);
+        }
+
+        if pge.get_key(olc::Key::R).pressed
+        {
+            self.toggle_recording();
         }
 
         if pge.get_key(olc::Key::H).pressed
@@ -292,36 +534,81 @@ impl olc::PGEApplication for Window
             self.hide_ui ^= true;
         }
 
+        if pge.get_key(olc::Key::P).pressed
+        {
+            self.save_preset();
+        }
+
+        if pge.get_key(olc::Key::L).pressed
+        {
+            self.load_preset();
+        }
+
+        if pge.get_key(olc::Key::B).pressed
+        {
+            self.pipeline.border = BorderMode::ALL[(self.pipeline.border.index() + 1) % BorderMode::ALL.len()];
+        }
+
+        if pge.get_key(olc::Key::C).pressed
+        {
+            self.cycle_auto_palette();
+        }
+
+        if pge.get_key(olc::Key::N).pressed
+        {
+            self.next_library_palette();
+        }
+
+        if pge.get_key(olc::Key::G).pressed
+        {
+            self.save_palette();
+        }
+
+        if pge.get_key(olc::Key::K).pressed
+        {
+            if let Some(auto_palette) = &mut self.pipeline.auto_palette
+            {
+                auto_palette.adaptation = if auto_palette.locked() {AutoPalette::DEFAULT_ADAPTATION} else {0.0};
+            }
+        }
+
         if pge.get_key(olc::Key::Left).pressed
         {   
-            let lower = self.processors[0] as i32 - 1;
-            let val = if lower < 0 {Processor::CrossBlur as u8} else {lower as u8};
-            self.processors[0] = unsafe{std::mem::transmute::<u8, Processor>(val)};
+            let count = filter::FILTERS.len();
+            let val = (filter::index_of(self.pipeline.stages[self.selected_stage].filter) + count - 1) % count;
+            self.pipeline.stages[self.selected_stage].set_filter(filter::FILTERS[val]);
             self.slider.current_val = val as u32;
         }
         if pge.get_key(olc::Key::Right).pressed
         {
-            let val = ( (self.processors[0] as i32 + 1) % (Processor::CrossBlur as i32 + 1) ) as u8;
-            self.processors[0] = unsafe{std::mem::transmute::<u8, Processor>(val)};
+            let val = (filter::index_of(self.pipeline.stages[self.selected_stage].filter) + 1) % filter::FILTERS.len();
+            self.pipeline.stages[self.selected_stage].set_filter(filter::FILTERS[val]);
             self.slider.current_val = val as u32;
         }
         if pge.get_key(olc::Key::Down).pressed
         {
-            let lower = self.input_mode as i32 - 1;
-            let val = if lower < 0 {InputMode::Denoising as u8} else {lower as u8};
-            self.input_mode = unsafe{std::mem::transmute::<u8, InputMode>(val)};
+            let count = InputMode::ALL.len();
+            self.pipeline.input_mode = InputMode::ALL[(self.pipeline.input_mode.index() + count - 1) % count];
         }
         if pge.get_key(olc::Key::Up).pressed
         {
-            let val = ( (self.input_mode as i32 + 1) % (InputMode::Denoising as i32 + 1) ) as u8;
-            self.input_mode = unsafe{std::mem::transmute::<u8, InputMode>(val)};
+            self.pipeline.input_mode = InputMode::ALL[(self.pipeline.input_mode.index() + 1) % InputMode::ALL.len()];
         }
 
+        let screen_size = (pge.screen_width(), pge.screen_height());
+        let shown = if (self.target.width, self.target.height) == screen_size
+        {
+            std::borrow::Cow::Borrowed(&self.target)
+        }
+        else
+        {
+            std::borrow::Cow::Owned(self.target.fit_linear(screen_size.0, screen_size.1, self.fit_mode, ResampleFilter::Bilinear))
+        };
         for y in 0..pge.screen_height()
         {
             for x in 0..pge.screen_width()
             {
-                pge.draw(x as i32, y as i32, self.target[(x,y)]);
+                pge.draw(x as i32, y as i32, shown[(x,y)].into());
             }
         }
         let end = std::time::Instant::now();
@@ -329,11 +616,17 @@ impl olc::PGEApplication for Window
         {
             pge.fill_rect(self.slider.x + 2, self.slider.y, self.slider.w as u32, self.slider.h as u32, olc::Pixel::rgb(70, 150, 140));
             pge.fill_rect(self.slider.get_slider_x(), self.slider.y, 2, self.slider.h as u32, olc::Pixel::rgb(200, 235, 225));
-            pge.draw_string(5, pge.screen_height() as i32 - 25, &"Processor:".to_string(), olc::WHITE);
-            
-            pge.draw_string(5, pge.screen_height() as i32 - 10, &format!("{:?}", self.processors[0]), olc::WHITE);
+            // the chain is listed bottom-up above the lower left corner, the selected stage in yellow
+            let chainy = pge.screen_height() as i32 - 10 * self.pipeline.stages.len() as i32;
+            pge.draw_string(5, chainy - 15, &"Processors:".to_string(), olc::WHITE);
+            for (i, stage) in self.pipeline.stages.iter().enumerate()
+            {
+                let colour = if i == self.selected_stage {olc::YELLOW} else {olc::WHITE};
+                pge.draw_string(5, chainy + 10 * i as i32, &format!("{}. {}", i + 1, stage.filter.name()), colour);
+            }
+            self.draw_parameters(pge);
             pge.draw_string(pge.screen_width() as i32 - 80, pge.screen_height() as i32 - 25, &"InputMode:".to_string(), olc::WHITE);
-            pge.draw_string(pge.screen_width() as i32 - 80, pge.screen_height() as i32 - 10, &format!("{:?}", self.input_mode), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 80, pge.screen_height() as i32 - 10, &format!("{:?}", self.pipeline.input_mode), olc::WHITE);
             
             pge.draw_string(pge.screen_width() as i32 - 145, 3, &"[<] Processors [>]".to_string(), olc::WHITE);
             
@@ -345,36 +638,64 @@ impl olc::PGEApplication for Window
             let keysy = 50;
             pge.draw_string(pge.screen_width() as i32 - 120, keysy, &"[H] hide UI".to_string(), olc::WHITE);
             pge.draw_string(pge.screen_width() as i32 - 120, keysy+10, &"[S] save image".to_string(), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+20, &"[R] record video".to_string(), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+30, &"[Tab] next stage".to_string(), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+40, &"[A] add stage".to_string(), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+50, &"[X] remove stage".to_string(), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+60, &"[PgUp/Dn] move".to_string(), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+70, &"[P] save preset".to_string(), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+80, &"[L] load preset".to_string(), olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+90, &format!("[B] {}", self.pipeline.border.name()), olc::WHITE);
+            let palette_name = match &self.pipeline.auto_palette
+            {
+                Some(auto_palette) => format!("[C] {:?}", auto_palette.method),
+                None => "[C] fixed palette".to_string(),
+            };
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+100, &palette_name, olc::WHITE);
+            if let Some(auto_palette) = &self.pipeline.auto_palette
+            {
+                let lock = if auto_palette.locked() {"[K] unlock"} else {"[K] lock"};
+                pge.draw_string(pge.screen_width() as i32 - 120, keysy+110, &lock.to_string(), olc::WHITE);
+            }
+            let library_name = match &self.palette_library.current
+            {
+                Some(path) => format!("[N] {}", path.file_stem().unwrap_or_default().to_string_lossy()),
+                None => "[N] next palette".to_string(),
+            };
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+120, &library_name, olc::WHITE);
+            pge.draw_string(pge.screen_width() as i32 - 120, keysy+130, &"[G] save palette".to_string(), olc::WHITE);
+            // the palette the palette based processors use, as a row of swatches
+            for (i, &colour) in self.pipeline.palette.iter().enumerate()
+            {
+                let (x, y) = (pge.screen_width() as i32 - 120 + 7 * (i as i32 % 16), keysy + 140 + 7 * (i as i32 / 16));
+                pge.fill_rect(x, y, 6, 6, colour.into());
+            }
+            if self.recorder.is_some()
+            {
+                let rows = (self.pipeline.palette.len() as i32 + 15) / 16;
+                pge.draw_string(pge.screen_width() as i32 - 120, keysy + 145 + 7 * rows, &"REC".to_string(), olc::RED);
+            }
 
             let input_duration = past_input - start;
             let rendering_duration = ((end - past_input) + self.frame_time * 99)/100;
             self.frame_time = rendering_duration;
             pge.draw_string(0, 0, &("input duration: ".to_string() + &input_duration.as_secs_f32().to_string()), olc::WHITE);
             pge.draw_string(0, 10, &("rendering duration: ".to_string() + &rendering_duration.as_secs_f32().to_string()), olc::WHITE);
+            if let Some((timestamp, sequence)) = self.last_frame
+            {
+                let captured_at = (timestamp - self.capture.started()).as_secs_f32();
+                let age = timestamp.elapsed().as_secs_f32();
+                pge.draw_string(0, 20, &format!("frame {} captured at: {:.3} (age {:.3})", sequence, captured_at, age), olc::WHITE);
+            }
+            pge.draw_string(0, 30, &format!("dropped frames: {} of {}", self.capture.dropped_frames(), self.capture.captured_frames()), olc::WHITE);
         }
 
         true
     }
 }
 
-fn save_image_as_png(image: &Image)
+fn save_image_as_png(image: &Image, output_dir: &std::path::Path)
 {
     let pathstring = String::from("image_") + &format!("{:x}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_micros()) + ".png";
-    let file = std::fs::File::create(std::path::Path::new(&pathstring)).unwrap();
-    let ref mut w = std::io::BufWriter::new(file);
-    let mut encoder = png::Encoder::new(w, image.width as u32, image.height as u32); 
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455)); // 1.0 / 2.2, scaled by 100000
-    encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));     // 1.0 / 2.2, unscaled, but rounded
-    let source_chromaticities = png::SourceChromaticities::new
-    (   // Using unscaled instantiation here
-        (0.31270, 0.32900),
-        (0.64000, 0.33000),
-        (0.30000, 0.60000),
-        (0.15000, 0.06000)
-    );
-    encoder.set_source_chromaticities(source_chromaticities);
-    let mut writer = encoder.write_header().unwrap();
-    writer.write_image_data(&image.pixels.iter().map(|p| [p.r, p.g, p.b, p.a]).flatten().collect::<Vec<u8>>()).unwrap();
-}
\ No newline at end of file
+    png_io::save_png(image, &output_dir.join(pathstring)).unwrap();
+}
@@ -0,0 +1,342 @@
+use crate::pixel::Rgba8;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The palette used when none is given: dark teals, ochres and purples.
+pub const DEFAULT: [Rgba8; 9] =
+[
+    Rgba8{r: 0, g: 60, b: 60, a: 255},
+    Rgba8{r: 140, g: 120, b: 50, a: 255},
+    Rgba8{r: 255, g: 225, b: 0, a: 255},
+    Rgba8{r: 60, g: 60, b: 80, a: 255},
+    Rgba8{r: 60, g: 60, b: 140, a: 255},
+    Rgba8{r: 80, g: 0, b: 0, a: 255},
+    Rgba8{r: 120, g: 60, b: 50, a: 255},
+    Rgba8{r: 50, g: 150, b: 120, a: 255},
+    Rgba8{r: 120, g: 100, b: 200, a: 255},
+];
+
+/// Parses a colour written as `rrggbb`, with or without a leading `#`.
+pub fn parse_hex(text: &str) -> Option<Rgba8>
+{
+    let digits = text.trim().trim_start_matches('#');
+    if digits.len() != 6 || !digits.is_ascii()
+    {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).ok();
+    Some(Rgba8::rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Formats a colour as `#rrggbb`.
+pub fn to_hex(colour: Rgba8) -> String
+{
+    format!("#{:02x}{:02x}{:02x}", colour.r, colour.g, colour.b)
+}
+
+/// The palette file formats that `load` and `save` understand.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PaletteFormat
+{
+    /// GIMP and Inkscape `.gpl`: a `GIMP Palette` header, then `r g b name` lines.
+    Gimp,
+    /// Paint.NET `.txt`: `aarrggbb` lines, with `;` comments.
+    PaintNet,
+    /// `.hex`, as on Lospec: one `rrggbb` colour per line.
+    Hex,
+    /// JASC (Paint Shop Pro) `.pal`: `JASC-PAL`, `0100`, the number of colours, then `r g b` lines.
+    Jasc,
+}
+
+impl PaletteFormat
+{
+    pub const ALL: [PaletteFormat; 4] = [PaletteFormat::Gimp, PaletteFormat::PaintNet, PaletteFormat::Hex, PaletteFormat::Jasc];
+
+    /// The file extension, without the dot.
+    pub fn extension(&self) -> &'static str
+    {
+        match self
+        {
+            PaletteFormat::Gimp => "gpl",
+            PaletteFormat::PaintNet => "txt",
+            PaletteFormat::Hex => "hex",
+            PaletteFormat::Jasc => "pal",
+        }
+    }
+
+    /// The format a file has by its extension, ignoring case.
+    pub fn from_path(path: &Path) -> Option<PaletteFormat>
+    {
+        let extension = path.extension()?.to_str()?;
+        PaletteFormat::ALL.iter().copied().find(|f| f.extension().eq_ignore_ascii_case(extension))
+    }
+}
+
+/// Reads the colours of a palette file. `format` is where the text came from; the headers of GIMP and JASC
+/// palettes are recognised whatever the file was called. Paint.NET files may also leave out the alpha, and hex lists
+/// may put several colours on a line, separated by spaces or commas, and start them with `#`.
+pub fn parse(text: &str, format: PaletteFormat) -> io::Result<Vec<Rgba8>>
+{
+    let first_line = text.lines().next().unwrap_or("").trim();
+    let format = if first_line.eq_ignore_ascii_case("GIMP Palette")
+    {
+        PaletteFormat::Gimp
+    }
+    else if first_line.eq_ignore_ascii_case("JASC-PAL")
+    {
+        PaletteFormat::Jasc
+    }
+    else
+    {
+        format
+    };
+    let error = |line: usize, message: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line + 1, message));
+    // the red, green and blue at the start of a GIMP or JASC colour line
+    let parse_rgb = |i: usize, line: &str|
+    {
+        let channels = line.split_whitespace().take(3).map(|c| c.parse::<u8>().ok()).collect::<Option<Vec<_>>>();
+        match channels.as_deref()
+        {
+            Some(&[r, g, b]) => Ok(Rgba8::rgb(r, g, b)),
+            _ => Err(error(i, format!("expected red, green and blue from 0 to 255, got {}", line))),
+        }
+    };
+    let mut lines = text.lines().map(str::trim).enumerate();
+    let mut colours = Vec::new();
+    match format
+    {
+        PaletteFormat::Gimp =>
+        {
+            lines.next();
+            for (i, line) in lines
+            {
+                let is_header = ["Name:", "Columns:"].iter().any(|key| line.starts_with(key));
+                if line.is_empty() || line.starts_with('#') || is_header
+                {
+                    continue;
+                }
+                colours.push(parse_rgb(i, line)?);
+            }
+        }
+        PaletteFormat::Jasc =>
+        {
+            lines.next();
+            let (i, version) = lines.next().unwrap_or((1, ""));
+            if version != "0100"
+            {
+                return Err(error(i, format!("expected the JASC-PAL version 0100, got {}", version)));
+            }
+            let (i, count) = lines.next().unwrap_or((2, ""));
+            let count = count.parse::<usize>().map_err(|_| error(i, format!("expected the number of colours, got {}", count)))?;
+            for (i, line) in lines.filter(|(_, line)| !line.is_empty()).take(count)
+            {
+                colours.push(parse_rgb(i, line)?);
+            }
+            if colours.len() < count
+            {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected {} colours, found {}", count, colours.len())));
+            }
+        }
+        PaletteFormat::PaintNet =>
+        {
+            for (i, line) in lines.filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+            {
+                // the alpha comes first, and the palette based processors don't use it
+                let digits = if line.len() == 8 {line.get(2..).unwrap_or(line)} else {line};
+                colours.push(parse_hex(digits).ok_or_else(|| error(i, format!("expected a colour like ffff8000, got {}", line)))?);
+            }
+        }
+        PaletteFormat::Hex =>
+        {
+            for (i, line) in lines
+            {
+                for word in line.split([' ', '\t', ',']).filter(|word| !word.is_empty())
+                {
+                    colours.push(parse_hex(word).ok_or_else(|| error(i, format!("expected a colour like ff8000, got {}", word)))?);
+                }
+            }
+        }
+    }
+    if colours.is_empty()
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the palette has no colours"));
+    }
+    Ok(colours)
+}
+
+/// Writes `colours` in `format`. `name` goes into the header of formats that have one.
+pub fn to_text(colours: &[Rgba8], format: PaletteFormat, name: &str) -> String
+{
+    let mut text = String::new();
+    match format
+    {
+        PaletteFormat::Gimp =>
+        {
+            text += &format!("GIMP Palette\nName: {}\nColumns: 8\n#\n", name);
+            for &c in colours
+            {
+                text += &format!("{:3} {:3} {:3}\t{}\n", c.r, c.g, c.b, to_hex(c));
+            }
+        }
+        PaletteFormat::PaintNet =>
+        {
+            text += &format!("; Paint.NET Palette File\n; {}\n; {} colours\n", name, colours.len());
+            for &c in colours
+            {
+                text += &format!("FF{:02X}{:02X}{:02X}\n", c.r, c.g, c.b);
+            }
+        }
+        PaletteFormat::Hex =>
+        {
+            for &c in colours
+            {
+                text += &format!("{:02x}{:02x}{:02x}\n", c.r, c.g, c.b);
+            }
+        }
+        PaletteFormat::Jasc =>
+        {
+            text += &format!("JASC-PAL\n0100\n{}\n", colours.len());
+            for &c in colours
+            {
+                text += &format!("{} {} {}\n", c.r, c.g, c.b);
+            }
+        }
+    }
+    text
+}
+
+/// Reads a palette file in any of the `PaletteFormat`s. Files with other extensions are read as hex lists.
+pub fn load(path: &Path) -> io::Result<Vec<Rgba8>>
+{
+    parse(&std::fs::read_to_string(path)?, PaletteFormat::from_path(path).unwrap_or(PaletteFormat::Hex))
+}
+
+/// Writes a palette file in the format its extension says, named after the file.
+pub fn save(colours: &[Rgba8], path: &Path) -> io::Result<()>
+{
+    let format = PaletteFormat::from_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "expected a .gpl, .txt, .hex or .pal file"))?;
+    let name = path.file_stem().map(|name| name.to_string_lossy()).unwrap_or_default();
+    std::fs::write(path, to_text(colours, format, &name))
+}
+
+/// A directory of palette files to go through one after another.
+///
+/// The directory is listed again every time, so files added while the program runs show up without a restart.
+pub struct PaletteLibrary
+{
+    pub directory: PathBuf,
+    /// The file `load_next` loaded last.
+    pub current: Option<PathBuf>,
+}
+
+impl PaletteLibrary
+{
+    pub fn new(directory: PathBuf) -> Self
+    {
+        Self{directory, current: None}
+    }
+
+    /// The palette files in the directory, sorted by name.
+    pub fn files(&self) -> io::Result<Vec<PathBuf>>
+    {
+        let mut files = std::fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && PaletteFormat::from_path(path).is_some())
+            .collect::<Vec<_>>();
+        files.sort();
+        Ok(files)
+    }
+
+    /// Loads the file after the current one, starting over after the last. Files that can't be read are skipped.
+    /// Returns the path and the colours, or an error if no file in the directory could be read.
+    pub fn load_next(&mut self) -> io::Result<(PathBuf, Vec<Rgba8>)>
+    {
+        let files = self.files()?;
+        let start = match &self.current
+        {
+            Some(current) => files.iter().position(|file| file > current).unwrap_or(0),
+            None => 0,
+        };
+        for path in files[start..].iter().chain(&files[..start])
+        {
+            match load(path)
+            {
+                Ok(colours) =>
+                {
+                    self.current = Some(path.clone());
+                    return Ok((path.clone(), colours));
+                }
+                Err(e) => println!("Skipping palette {}: {}", path.display(), e),
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("no palette files in {}", self.directory.display())))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_support::temporary_directory;
+
+    #[test]
+    fn saved_files_load_back()
+    {
+        let directory = temporary_directory("palette_files");
+        let colours = [Rgba8::rgb(0, 0, 0), Rgba8::rgb(255, 255, 255), Rgba8::rgb(1, 128, 254), Rgba8::rgb(171, 205, 239)];
+        for format in PaletteFormat::ALL
+        {
+            let path = directory.join(format!("round trip.{}", format.extension()));
+            save(&colours, &path).unwrap();
+            assert_eq!(load(&path).unwrap(), colours, "{:?}", format);
+        }
+        assert_eq!(save(&colours, &directory.join("palette.png")).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn headers_win_over_the_extension()
+    {
+        let colours = vec![Rgba8::rgb(10, 20, 30), Rgba8::rgb(40, 50, 60)];
+        assert_eq!(parse(&to_text(&colours, PaletteFormat::Gimp, "x"), PaletteFormat::Hex).unwrap(), colours);
+        assert_eq!(parse(&to_text(&colours, PaletteFormat::Jasc, "x"), PaletteFormat::PaintNet).unwrap(), colours);
+        assert_eq!(parse("JASC-PAL\r\n0100\r\n2\r\n10 20 30\r\n40 50 60\r\n", PaletteFormat::Hex).unwrap(), colours);
+    }
+
+    #[test]
+    fn loose_variants_are_read()
+    {
+        let colours = vec![Rgba8::rgb(255, 128, 0), Rgba8::rgb(0, 0, 255)];
+        assert_eq!(parse("; comment\nff8000\nFF0000FF\n", PaletteFormat::PaintNet).unwrap(), colours);
+        assert_eq!(parse("#ff8000, #0000ff\n\n", PaletteFormat::Hex).unwrap(), colours);
+        assert_eq!(parse("GIMP Palette\nName: x\n#\n255 128 0 Orange\n  0   0 255\tBlue\n", PaletteFormat::Hex).unwrap(), colours);
+    }
+
+    #[test]
+    fn errors_say_where()
+    {
+        let message = |text: &str, format: PaletteFormat| parse(text, format).unwrap_err().to_string();
+        assert_eq!(message("ff8000\nnope\n", PaletteFormat::Hex), "line 2: expected a colour like ff8000, got nope");
+        assert_eq!(message("GIMP Palette\n255 0\n", PaletteFormat::Gimp), "line 2: expected red, green and blue from 0 to 255, got 255 0");
+        assert_eq!(message("JASC-PAL\n0200\n1\n0 0 0\n", PaletteFormat::Jasc), "line 2: expected the JASC-PAL version 0100, got 0200");
+        assert_eq!(message("JASC-PAL\n0100\n3\n0 0 0\n", PaletteFormat::Jasc), "expected 3 colours, found 1");
+        assert_eq!(message("; only a comment\n", PaletteFormat::PaintNet), "the palette has no colours");
+    }
+
+    #[test]
+    fn library_goes_round_and_skips_broken_files()
+    {
+        let directory = temporary_directory("palette_library");
+        save(&[Rgba8::rgb(1, 1, 1)], &directory.join("a.hex")).unwrap();
+        std::fs::write(directory.join("b.gpl"), "GIMP Palette\nnot a colour\n").unwrap();
+        save(&[Rgba8::rgb(3, 3, 3)], &directory.join("c.pal")).unwrap();
+        std::fs::write(directory.join("notes.md"), "not a palette").unwrap();
+
+        let mut library = PaletteLibrary::new(directory.clone());
+        assert_eq!(library.files().unwrap().len(), 3);
+        let loaded = (0..3).map(|_| library.load_next().unwrap().1).collect::<Vec<_>>();
+        assert_eq!(loaded, vec![vec![Rgba8::rgb(1, 1, 1)], vec![Rgba8::rgb(3, 3, 3)], vec![Rgba8::rgb(1, 1, 1)]]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+use olc_pge as olc;
+
+/// A pixel type that `Image` can store.
+///
+/// Conversions between pixel types go through normalised RGBA, where every channel runs from 0 to 1.
+/// 8 bit channels are rounded and clamped, `RgbF32` keeps values outside that range.
+pub trait Pixel: Copy + Default + PartialEq + std::fmt::Debug + Send + Sync + 'static
+{
+    fn to_rgba(self) -> [f32; 4];
+
+    fn from_rgba(rgba: [f32; 4]) -> Self;
+
+    /// A grey pixel with the brightness `value`, opaque.
+    fn grey(value: u8) -> Self
+    {
+        let v = value as f32 / 255.0;
+        Self::from_rgba([v, v, v, 1.0])
+    }
+
+    fn convert<Q: Pixel>(self) -> Q
+    {
+        Q::from_rgba(self.to_rgba())
+    }
+}
+
+fn to_u8(value: f32) -> u8
+{
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+fn to_f32(value: u8) -> f32
+{
+    value as f32 / 255.0
+}
+
+/// Rec. 601 luma, the same weights as `Illuminator::brightness`.
+fn luma(r: f32, g: f32, b: f32) -> f32
+{
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// One 8 bit brightness channel, for greyscale results like edge detection or thresholds.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Grey8(pub u8);
+
+/// 8 bit red, green and blue.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Rgb8
+{
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// 8 bit red, green, blue and alpha. The default pixel type of `Image`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Rgba8
+{
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+/// Red, green and blue as floats, nominally between 0 and 1, for maths that shouldn't be rounded to 8 bits between steps.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct RgbF32
+{
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+impl Rgb8
+{
+    pub const fn new(r: u8, g: u8, b: u8) -> Self
+    {
+        Self{r, g, b}
+    }
+}
+
+impl Rgba8
+{
+    pub const BLACK: Rgba8 = Rgba8::rgb(0, 0, 0);
+    pub const WHITE: Rgba8 = Rgba8::rgb(255, 255, 255);
+    pub const GREY: Rgba8 = Rgba8::rgb(192, 192, 192);
+    pub const MAGENTA: Rgba8 = Rgba8::rgb(255, 0, 255);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self
+    {
+        Self{r, g, b, a: 255}
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self
+    {
+        Self{r, g, b, a}
+    }
+}
+
+impl RgbF32
+{
+    pub const fn new(r: f32, g: f32, b: f32) -> Self
+    {
+        Self{r, g, b}
+    }
+}
+
+impl Default for Rgba8
+{
+    fn default() -> Self
+    {
+        Rgba8::BLACK
+    }
+}
+
+impl Pixel for Grey8
+{
+    fn to_rgba(self) -> [f32; 4]
+    {
+        let v = to_f32(self.0);
+        [v, v, v, 1.0]
+    }
+
+    fn from_rgba([r, g, b, _]: [f32; 4]) -> Self
+    {
+        Grey8(to_u8(luma(r, g, b)))
+    }
+
+    fn grey(value: u8) -> Self
+    {
+        Grey8(value)
+    }
+}
+
+impl Pixel for Rgb8
+{
+    fn to_rgba(self) -> [f32; 4]
+    {
+        [to_f32(self.r), to_f32(self.g), to_f32(self.b), 1.0]
+    }
+
+    fn from_rgba([r, g, b, _]: [f32; 4]) -> Self
+    {
+        Rgb8::new(to_u8(r), to_u8(g), to_u8(b))
+    }
+
+    fn grey(value: u8) -> Self
+    {
+        Rgb8::new(value, value, value)
+    }
+}
+
+impl Pixel for Rgba8
+{
+    fn to_rgba(self) -> [f32; 4]
+    {
+        [to_f32(self.r), to_f32(self.g), to_f32(self.b), to_f32(self.a)]
+    }
+
+    fn from_rgba([r, g, b, a]: [f32; 4]) -> Self
+    {
+        Rgba8::rgba(to_u8(r), to_u8(g), to_u8(b), to_u8(a))
+    }
+
+    fn grey(value: u8) -> Self
+    {
+        Rgba8::rgb(value, value, value)
+    }
+}
+
+impl Pixel for RgbF32
+{
+    fn to_rgba(self) -> [f32; 4]
+    {
+        [self.r, self.g, self.b, 1.0]
+    }
+
+    fn from_rgba([r, g, b, _]: [f32; 4]) -> Self
+    {
+        RgbF32::new(r, g, b)
+    }
+}
+
+/// Only used to put images on screen.
+impl From<Rgba8> for olc::Pixel
+{
+    fn from(p: Rgba8) -> Self
+    {
+        olc::Pixel::rgba(p.r, p.g, p.b, p.a)
+    }
+}
+
+/// Decodes an sRGB encoded 8 bit channel to linear light between 0 and 1.
+pub fn srgb_to_linear(value: u8) -> f32
+{
+    static TABLE: std::sync::OnceLock<[f32; 256]> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|i| decode_srgb(i as f32 / 255.0)))[value as usize]
+}
+
+/// Encodes a linear-light value to an 8 bit sRGB channel, rounding to the nearest encoded value.
+pub fn linear_to_srgb(value: f32) -> u8
+{
+    // the linear values halfway between neighbouring encoded values, so no powf is needed per pixel
+    static THRESHOLDS: std::sync::OnceLock<[f32; 255]> = std::sync::OnceLock::new();
+    let thresholds = THRESHOLDS.get_or_init(|| std::array::from_fn(|i| decode_srgb((i as f32 + 0.5) / 255.0)));
+    thresholds.partition_point(|&t| t <= value) as u8
+}
+
+/// The sRGB transfer function, from encoded to linear, both between 0 and 1.
+pub fn decode_srgb(v: f32) -> f32
+{
+    if v <= 0.04045 {v / 12.92} else {((v + 0.055) / 1.055).powf(2.4)}
+}
+
+impl RgbF32
+{
+    /// Decodes an sRGB pixel to linear light. Alpha is dropped.
+    pub fn from_srgb(p: Rgba8) -> Self
+    {
+        RgbF32::new(srgb_to_linear(p.r), srgb_to_linear(p.g), srgb_to_linear(p.b))
+    }
+
+    /// Encodes a linear-light pixel to opaque sRGB.
+    pub fn to_srgb(self) -> Rgba8
+    {
+        Rgba8::rgb(linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn rgba8_round_trips_every_value()
+    {
+        for v in 0..=255u8
+        {
+            let pixel = Rgba8::rgba(v, 255 - v, v / 2, v);
+            assert_eq!(Rgba8::from_rgba(pixel.to_rgba()), pixel);
+        }
+    }
+
+    #[test]
+    fn grey8_round_trips_every_value()
+    {
+        for v in 0..=255u8
+        {
+            assert_eq!(Grey8::from_rgba(Grey8(v).to_rgba()), Grey8(v));
+            assert_eq!(Grey8(v).convert::<Rgba8>(), Rgba8::rgb(v, v, v));
+            assert_eq!(Rgba8::rgb(v, v, v).convert::<Grey8>(), Grey8(v));
+        }
+    }
+
+    #[test]
+    fn rgb8_round_trips_every_value()
+    {
+        for v in 0..=255u8
+        {
+            let pixel = Rgb8::new(v, 255 - v, v / 2);
+            assert_eq!(Rgb8::from_rgba(pixel.to_rgba()), pixel);
+            assert_eq!(pixel.convert::<Rgba8>(), Rgba8::rgb(v, 255 - v, v / 2));
+            assert_eq!(Rgba8::rgba(v, 255 - v, v / 2, 7).convert::<Rgb8>(), pixel);
+        }
+        assert_eq!(Rgb8::grey(9), Rgb8::new(9, 9, 9));
+        assert_eq!(Rgb8::new(255, 0, 0).convert::<Grey8>(), Rgba8::rgb(255, 0, 0).convert::<Grey8>());
+    }
+
+    #[test]
+    fn grey8_from_colour_is_the_luma()
+    {
+        assert_eq!(Rgba8::rgb(255, 0, 0).convert::<Grey8>(), Grey8(76));
+        assert_eq!(Rgba8::rgb(0, 255, 0).convert::<Grey8>(), Grey8(150));
+        assert_eq!(Rgba8::rgb(0, 0, 255).convert::<Grey8>(), Grey8(29));
+        // alpha doesn't change the brightness, and converting to grey makes the pixel opaque
+        assert_eq!(Rgba8::rgba(10, 20, 30, 0).convert::<Grey8>().convert::<Rgba8>().a, 255);
+    }
+
+    #[test]
+    fn rgbf32_keeps_values_outside_0_to_1()
+    {
+        let pixel = RgbF32::new(-0.25, 1.5, 0.5);
+        assert_eq!(RgbF32::from_rgba(pixel.to_rgba()), pixel);
+        assert_eq!(pixel.to_rgba(), [-0.25, 1.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn eight_bit_channels_round_and_clamp()
+    {
+        assert_eq!(Rgba8::from_rgba([-0.25, 1.5, 0.5, 2.0]), Rgba8::rgba(0, 255, 128, 255));
+        assert_eq!(RgbF32::new(-1.0, 0.2, 7.0).convert::<Rgba8>(), Rgba8::rgb(0, 51, 255));
+        assert_eq!(RgbF32::new(2.0, 2.0, 2.0).convert::<Grey8>(), Grey8(255));
+        assert_eq!(RgbF32::new(-2.0, -2.0, -2.0).convert::<Grey8>(), Grey8(0));
+    }
+
+    #[test]
+    fn srgb_round_trips_through_linear()
+    {
+        for v in 0..=255u8
+        {
+            assert_eq!(linear_to_srgb(srgb_to_linear(v)), v);
+            assert_eq!(RgbF32::from_srgb(Rgba8::rgb(v, v, v)).to_srgb(), Rgba8::rgb(v, v, v));
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use crate::pixel::Rgba8;
+use crate::image::Image;
+
+/// Layouts of raw frame buffers as delivered by common webcams and capture tools.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PixelFormat
+{
+    /// Packed 4:2:2, bytes `Y0 U Y1 V` for every pair of pixels.
+    Yuyv,
+    /// Packed 4:2:2, bytes `U Y0 V Y1` for every pair of pixels.
+    Uyvy,
+    /// Planar 4:2:0, a full size Y plane followed by one plane of interleaved `U V` pairs.
+    Nv12,
+    /// Planar 4:2:0, a full size Y plane followed by a U plane and a V plane.
+    I420,
+    /// Packed `R G B`.
+    Rgb24,
+    /// Packed `B G R`.
+    Bgr24,
+}
+
+impl PixelFormat
+{
+    pub const ALL: [PixelFormat; 6] = [PixelFormat::Yuyv, PixelFormat::Uyvy, PixelFormat::Nv12, PixelFormat::I420, PixelFormat::Rgb24, PixelFormat::Bgr24];
+
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            PixelFormat::Yuyv => "yuyv",
+            PixelFormat::Uyvy => "uyvy",
+            PixelFormat::Nv12 => "nv12",
+            PixelFormat::I420 => "i420",
+            PixelFormat::Rgb24 => "rgb24",
+            PixelFormat::Bgr24 => "bgr24",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PixelFormat>
+    {
+        PixelFormat::ALL.iter().copied().find(|f| f.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Number of bytes in one `width` by `height` frame.
+    pub fn frame_size(&self, width: usize, height: usize) -> usize
+    {
+        match self
+        {
+            PixelFormat::Yuyv | PixelFormat::Uyvy => width.div_ceil(2) * 4 * height,
+            PixelFormat::Nv12 | PixelFormat::I420 => width * height + 2 * width.div_ceil(2) * height.div_ceil(2),
+            PixelFormat::Rgb24 | PixelFormat::Bgr24 => width * height * 3,
+        }
+    }
+}
+
+/// The RGB to YCbCr matrix a YUV buffer was encoded with.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum YuvMatrix
+{
+    /// Standard definition video and most webcams.
+    Bt601,
+    /// HD video.
+    Bt709,
+}
+
+impl YuvMatrix
+{
+    pub fn from_name(name: &str) -> Option<YuvMatrix>
+    {
+        [YuvMatrix::Bt601, YuvMatrix::Bt709].into_iter().find(|m| format!("{:?}", m).eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// The luma weights `(kr, kb)` of the red and blue channels.
+    fn coefficients(&self) -> (f32, f32)
+    {
+        match self
+        {
+            YuvMatrix::Bt601 => (0.299, 0.114),
+            YuvMatrix::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum YuvRange
+{
+    /// Y, U and V all use 0-255.
+    Full,
+    /// "Studio swing": Y uses 16-235 and U and V use 16-240.
+    Limited,
+}
+
+impl YuvRange
+{
+    pub fn from_name(name: &str) -> Option<YuvRange>
+    {
+        [YuvRange::Full, YuvRange::Limited].into_iter().find(|r| format!("{:?}", r).eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// How to interpret the YUV samples of a buffer.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct YuvConversion
+{
+    pub matrix: YuvMatrix,
+    pub range: YuvRange,
+}
+
+impl Default for YuvConversion
+{
+    fn default() -> Self
+    {
+        Self{matrix: YuvMatrix::Bt601, range: YuvRange::Limited}
+    }
+}
+
+impl YuvConversion
+{
+    pub fn to_rgb(&self, y: u8, u: u8, v: u8) -> Rgba8
+    {
+        let (kr, kb) = self.matrix.coefficients();
+        let kg = 1.0 - kr - kb;
+        let (y, u, v) = match self.range
+        {
+            YuvRange::Full => (y as f32, u as f32 - 128.0, v as f32 - 128.0),
+            YuvRange::Limited => ((y as f32 - 16.0) * 255.0 / 219.0, (u as f32 - 128.0) * 255.0 / 224.0, (v as f32 - 128.0) * 255.0 / 224.0),
+        };
+        let r = y + 2.0 * (1.0 - kr) * v;
+        let b = y + 2.0 * (1.0 - kb) * u;
+        let g = (y - kr * r - kb * b) / kg;
+        Rgba8::rgb(to_u8(r), to_u8(g), to_u8(b))
+    }
+
+    pub fn from_rgb(&self, p: Rgba8) -> (u8, u8, u8)
+    {
+        let (kr, kb) = self.matrix.coefficients();
+        let kg = 1.0 - kr - kb;
+        let (r, g, b) = (p.r as f32, p.g as f32, p.b as f32);
+        let y = kr * r + kg * g + kb * b;
+        let u = (b - y) / (2.0 * (1.0 - kb));
+        let v = (r - y) / (2.0 * (1.0 - kr));
+        match self.range
+        {
+            YuvRange::Full => (to_u8(y), to_u8(u + 128.0), to_u8(v + 128.0)),
+            YuvRange::Limited => (to_u8(16.0 + y * 219.0 / 255.0), to_u8(128.0 + u * 224.0 / 255.0), to_u8(128.0 + v * 224.0 / 255.0)),
+        }
+    }
+}
+
+fn to_u8(value: f32) -> u8
+{
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+/// Converts one raw `width` by `height` frame in `format` into an `Image`.
+/// 
+/// `conversion` is only used by the YUV formats. Fails if `data` is smaller than one frame; extra bytes are ignored.
+pub fn convert_to_image(data: &[u8], width: usize, height: usize, format: PixelFormat, conversion: YuvConversion) -> std::io::Result<Image>
+{
+    let frame_size = format.frame_size(width, height);
+    if data.len() < frame_size
+    {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("a {}x{} {} frame needs {} bytes, got {}", width, height, format.name(), frame_size, data.len())));
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    match format
+    {
+        PixelFormat::Rgb24 => pixels.extend(data[..frame_size].chunks_exact(3).map(|p| Rgba8::rgb(p[0], p[1], p[2]))),
+        PixelFormat::Bgr24 => pixels.extend(data[..frame_size].chunks_exact(3).map(|p| Rgba8::rgb(p[2], p[1], p[0]))),
+        PixelFormat::Yuyv | PixelFormat::Uyvy =>
+        {
+            // byte offsets of Y0, U, Y1 and V inside each 4 byte group
+            let (y0, u, y1, v) = if format == PixelFormat::Yuyv {(0, 1, 2, 3)} else {(1, 0, 3, 2)};
+            let row_size = width.div_ceil(2) * 4;
+            for row in data[..frame_size].chunks_exact(row_size)
+            {
+                for x in 0..width
+                {
+                    let group = &row[x / 2 * 4..x / 2 * 4 + 4];
+                    let luma = if x % 2 == 0 {group[y0]} else {group[y1]};
+                    pixels.push(conversion.to_rgb(luma, group[u], group[v]));
+                }
+            }
+        }
+        PixelFormat::Nv12 | PixelFormat::I420 =>
+        {
+            let chroma_width = width.div_ceil(2);
+            let chroma_plane_size = chroma_width * height.div_ceil(2);
+            let (luma, chroma) = data.split_at(width * height);
+            for y in 0..height
+            {
+                for x in 0..width
+                {
+                    let i = (y / 2) * chroma_width + x / 2;
+                    let (u, v) = if format == PixelFormat::Nv12
+                    {
+                        (chroma[2 * i], chroma[2 * i + 1])
+                    }
+                    else
+                    {
+                        (chroma[i], chroma[chroma_plane_size + i])
+                    };
+                    pixels.push(conversion.to_rgb(luma[y * width + x], u, v));
+                }
+            }
+        }
+    }
+    Ok(Image{width, height, pixels})
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const BT601_LIMITED: YuvConversion = YuvConversion{matrix: YuvMatrix::Bt601, range: YuvRange::Limited};
+    const BT601_FULL: YuvConversion = YuvConversion{matrix: YuvMatrix::Bt601, range: YuvRange::Full};
+    const BT709_LIMITED: YuvConversion = YuvConversion{matrix: YuvMatrix::Bt709, range: YuvRange::Limited};
+
+    fn assert_close(actual: Rgba8, expected: (u8, u8, u8))
+    {
+        let close = |a: u8, b: u8| (a as i32 - b as i32).abs() <= 1;
+        assert!(close(actual.r, expected.0) && close(actual.g, expected.1) && close(actual.b, expected.2), "{:?} is not close to {:?}", actual, expected);
+    }
+
+    #[test]
+    fn bt601_limited_reference_colours()
+    {
+        assert_close(BT601_LIMITED.to_rgb(16, 128, 128), (0, 0, 0));
+        assert_close(BT601_LIMITED.to_rgb(235, 128, 128), (255, 255, 255));
+        assert_close(BT601_LIMITED.to_rgb(81, 90, 240), (255, 0, 0));
+        assert_close(BT601_LIMITED.to_rgb(145, 54, 34), (0, 255, 0));
+        assert_close(BT601_LIMITED.to_rgb(41, 240, 110), (0, 0, 255));
+    }
+
+    #[test]
+    fn bt601_full_reference_colours()
+    {
+        assert_close(BT601_FULL.to_rgb(0, 128, 128), (0, 0, 0));
+        assert_close(BT601_FULL.to_rgb(255, 128, 128), (255, 255, 255));
+        assert_close(BT601_FULL.to_rgb(76, 85, 255), (255, 0, 0));
+        assert_close(BT601_FULL.to_rgb(150, 44, 21), (0, 255, 0));
+        assert_close(BT601_FULL.to_rgb(29, 255, 107), (0, 0, 255));
+    }
+
+    #[test]
+    fn bt709_limited_reference_colours()
+    {
+        assert_close(BT709_LIMITED.to_rgb(63, 102, 240), (255, 0, 0));
+        assert_close(BT709_LIMITED.to_rgb(173, 42, 26), (0, 255, 0));
+        assert_close(BT709_LIMITED.to_rgb(32, 240, 118), (0, 0, 255));
+    }
+
+    #[test]
+    fn rgb_round_trip()
+    {
+        for conversion in [BT601_LIMITED, BT601_FULL, BT709_LIMITED]
+        {
+            for &colour in &[Rgba8::rgb(12, 200, 99), Rgba8::rgb(250, 128, 3), Rgba8::GREY]
+            {
+                let (y, u, v) = conversion.from_rgb(colour);
+                assert_close(conversion.to_rgb(y, u, v), (colour.r, colour.g, colour.b));
+            }
+        }
+    }
+
+    #[test]
+    fn packed_rgb_formats()
+    {
+        let data = [1, 2, 3, 4, 5, 6];
+        let rgb = convert_to_image(&data, 2, 1, PixelFormat::Rgb24, YuvConversion::default()).unwrap();
+        let bgr = convert_to_image(&data, 2, 1, PixelFormat::Bgr24, YuvConversion::default()).unwrap();
+        assert_eq!(rgb.pixels, vec![Rgba8::rgb(1, 2, 3), Rgba8::rgb(4, 5, 6)]);
+        assert_eq!(bgr.pixels, vec![Rgba8::rgb(3, 2, 1), Rgba8::rgb(6, 5, 4)]);
+    }
+
+    #[test]
+    fn packed_yuv_formats()
+    {
+        // a white and a black pixel sharing neutral chroma
+        let yuyv = convert_to_image(&[235, 128, 16, 128], 2, 1, PixelFormat::Yuyv, BT601_LIMITED).unwrap();
+        let uyvy = convert_to_image(&[128, 235, 128, 16], 2, 1, PixelFormat::Uyvy, BT601_LIMITED).unwrap();
+        assert_eq!(yuyv.pixels, vec![Rgba8::WHITE, Rgba8::BLACK]);
+        assert_eq!(uyvy.pixels, vec![Rgba8::WHITE, Rgba8::BLACK]);
+    }
+
+    #[test]
+    fn planar_yuv_formats()
+    {
+        // 2x2 red frame: four Y samples followed by one chroma sample
+        let nv12 = convert_to_image(&[81, 81, 81, 81, 90, 240], 2, 2, PixelFormat::Nv12, BT601_LIMITED).unwrap();
+        let i420 = convert_to_image(&[81, 81, 81, 81, 90, 240], 2, 2, PixelFormat::I420, BT601_LIMITED).unwrap();
+        for pixel in nv12.pixels.into_iter().chain(i420.pixels)
+        {
+            assert_close(pixel, (255, 0, 0));
+        }
+    }
+
+    #[test]
+    fn odd_sizes_round_chroma_up()
+    {
+        assert_eq!(PixelFormat::I420.frame_size(3, 3), 9 + 2 * 4);
+        assert_eq!(PixelFormat::Yuyv.frame_size(3, 2), 16);
+        let image = convert_to_image(&[16; 17], 3, 3, PixelFormat::I420, BT601_FULL).unwrap();
+        assert_eq!(image.pixels.len(), 9);
+    }
+
+    #[test]
+    fn short_buffers_are_rejected()
+    {
+        assert!(convert_to_image(&[0; 5], 2, 2, PixelFormat::Nv12, YuvConversion::default()).is_err());
+    }
+}
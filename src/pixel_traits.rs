@@ -1,5 +1,4 @@
-use olc_pge as olc;
-use lerp::Lerp;
+use crate::pixel::{Grey8, Rgb8, Rgba8, RgbF32};
 
 pub trait Illuminator
 {
@@ -7,7 +6,7 @@ pub trait Illuminator
     fn brightness(&self) -> Self::Output;
 }
 
-impl Illuminator for olc::Pixel
+impl Illuminator for Rgba8
 {
     type Output = u8; 
     fn brightness(&self) -> Self::Output
@@ -20,13 +19,40 @@ impl Illuminator for olc::Pixel
     }
 }
 
+impl Illuminator for Grey8
+{
+    type Output = u8;
+    fn brightness(&self) -> Self::Output
+    {
+        self.0
+    }
+}
+
+impl Illuminator for Rgb8
+{
+    type Output = u8;
+    fn brightness(&self) -> Self::Output
+    {
+        Rgba8::rgb(self.r, self.g, self.b).brightness()
+    }
+}
+
+impl Illuminator for RgbF32
+{
+    type Output = f32;
+    fn brightness(&self) -> Self::Output
+    {
+        0.299 * self.r + 0.587 * self.g + 0.114 * self.b
+    }
+}
+
 pub trait MagnitudeSquared
 {
     type Output;
     fn mag2(&self) -> Self::Output;
 }
 
-impl MagnitudeSquared for olc::Pixel
+impl MagnitudeSquared for Rgba8
 {
     type Output = u32;
     fn mag2(&self) -> Self::Output
@@ -34,7 +60,35 @@ impl MagnitudeSquared for olc::Pixel
         let r = self.r as u32;
         let g = self.g as u32;
         let b = self.b as u32;
-        return r*r+g*g+b*b;
+        r*r+g*g+b*b
+    }
+}
+
+impl MagnitudeSquared for Grey8
+{
+    type Output = u32;
+    fn mag2(&self) -> Self::Output
+    {
+        let v = self.0 as u32;
+        v*v
+    }
+}
+
+impl MagnitudeSquared for Rgb8
+{
+    type Output = u32;
+    fn mag2(&self) -> Self::Output
+    {
+        Rgba8::rgb(self.r, self.g, self.b).mag2()
+    }
+}
+
+impl MagnitudeSquared for RgbF32
+{
+    type Output = f32;
+    fn mag2(&self) -> Self::Output
+    {
+        self.r*self.r+self.g*self.g+self.b*self.b
     }
 }
 
@@ -44,16 +98,47 @@ pub trait DistanceSquared
     fn distance_squared(&self, other: Self) -> Self::Output;
 }
 
-impl DistanceSquared for olc::Pixel
+impl DistanceSquared for Rgba8
 {
     type Output = u32;
-    fn distance_squared(&self, other: olc::Pixel) -> Self::Output
+    fn distance_squared(&self, other: Rgba8) -> Self::Output
     {
         let r = self.r as i32 - other.r as i32;
         let g = self.g as i32 - other.g as i32;
         let b = self.b as i32 - other.b as i32;
 
-        return (r*r+g*g+b*b) as u32;
+        (r*r+g*g+b*b) as u32
+    }
+}
+
+impl DistanceSquared for Grey8
+{
+    type Output = u32;
+    fn distance_squared(&self, other: Grey8) -> Self::Output
+    {
+        let v = self.0 as i32 - other.0 as i32;
+        (v*v) as u32
+    }
+}
+
+impl DistanceSquared for Rgb8
+{
+    type Output = u32;
+    fn distance_squared(&self, other: Rgb8) -> Self::Output
+    {
+        Rgba8::rgb(self.r, self.g, self.b).distance_squared(Rgba8::rgb(other.r, other.g, other.b))
+    }
+}
+
+impl DistanceSquared for RgbF32
+{
+    type Output = f32;
+    fn distance_squared(&self, other: RgbF32) -> Self::Output
+    {
+        let r = self.r - other.r;
+        let g = self.g - other.g;
+        let b = self.b - other.b;
+        r*r+g*g+b*b
     }
 }
 
@@ -74,7 +159,7 @@ pub trait PixelArithmetic
     fn clamping_fraction_mul(&self, fraction: (u8, u8)) -> Self;
 }
 
-impl PixelArithmetic for olc::Pixel
+impl PixelArithmetic for Rgba8
 {
     fn clamping_add(&self, other: Self) -> Self
     {
@@ -87,7 +172,7 @@ impl PixelArithmetic for olc::Pixel
         r = r.min(255);
         g = g.min(255);
         b = b.min(255);
-        return olc::Pixel::rgb(r as u8, g as u8, b as u8);
+        Rgba8::rgb(r as u8, g as u8, b as u8)
     }
 
     fn clamping_mul(&self, factor: u8) -> Self
@@ -101,7 +186,7 @@ impl PixelArithmetic for olc::Pixel
         r = r.min(255);
         g = g.min(255);
         b = b.min(255);
-        return olc::Pixel::rgb(r as u8, g as u8, b as u8);
+        Rgba8::rgb(r as u8, g as u8, b as u8)
     }
 
     fn clamping_sub(&self, other: Self) -> Self
@@ -115,7 +200,7 @@ impl PixelArithmetic for olc::Pixel
         r = r.max(0);
         g = g.max(0);
         b = b.max(0);
-        return olc::Pixel::rgb(r as u8, g as u8, b as u8);
+        Rgba8::rgb(r as u8, g as u8, b as u8)
     }
 
     fn sub(&self, other: Self) -> Self
@@ -124,7 +209,7 @@ impl PixelArithmetic for olc::Pixel
         p.r -= other.r;
         p.g -= other.g;
         p.b -= other.b;
-        return p;
+        p
     }
 
     fn div(&self, divisor: u8) -> Self
@@ -134,7 +219,7 @@ impl PixelArithmetic for olc::Pixel
         p.r /= divisor;
         p.g /= divisor;
         p.b /= divisor;
-        return p;
+        p
     }
 
     fn normalised_mul(&self, other: Self) -> Self
@@ -148,7 +233,7 @@ impl PixelArithmetic for olc::Pixel
         r /= 255;
         g /= 255;
         b /= 255;
-        return olc::Pixel::rgb(r as u8, g as u8, b as u8);
+        Rgba8::rgb(r as u8, g as u8, b as u8)
     }
 
     fn clamping_fraction_mul(&self, fraction: (u8, u8)) -> Self
@@ -166,11 +251,145 @@ impl PixelArithmetic for olc::Pixel
         r = r.min(255);
         g = g.min(255);
         b = b.min(255);
-        return olc::Pixel::rgb(r as u8, g as u8, b as u8);
+        Rgba8::rgb(r as u8, g as u8, b as u8)
+    }
+}
+
+/// The 8 bit colour types share the maths of `Rgba8`, so the results are identical whichever one is used.
+impl PixelArithmetic for Rgb8
+{
+    fn clamping_add(&self, other: Self) -> Self
+    {
+        from_rgba8(to_rgba8(*self).clamping_add(to_rgba8(other)))
+    }
+
+    fn clamping_mul(&self, factor: u8) -> Self
+    {
+        from_rgba8(to_rgba8(*self).clamping_mul(factor))
+    }
+
+    fn clamping_sub(&self, other: Self) -> Self
+    {
+        from_rgba8(to_rgba8(*self).clamping_sub(to_rgba8(other)))
+    }
+
+    fn sub(&self, other: Self) -> Self
+    {
+        from_rgba8(to_rgba8(*self).sub(to_rgba8(other)))
+    }
+
+    fn div(&self, divisor: u8) -> Self
+    {
+        from_rgba8(to_rgba8(*self).div(divisor))
+    }
+
+    fn normalised_mul(&self, other: Self) -> Self
+    {
+        from_rgba8(to_rgba8(*self).normalised_mul(to_rgba8(other)))
+    }
+
+    fn clamping_fraction_mul(&self, fraction: (u8, u8)) -> Self
+    {
+        from_rgba8(to_rgba8(*self).clamping_fraction_mul(fraction))
+    }
+}
+
+fn to_rgba8(p: Rgb8) -> Rgba8
+{
+    Rgba8::rgb(p.r, p.g, p.b)
+}
+
+fn from_rgba8(p: Rgba8) -> Rgb8
+{
+    Rgb8::new(p.r, p.g, p.b)
+}
+
+impl PixelArithmetic for Grey8
+{
+    fn clamping_add(&self, other: Self) -> Self
+    {
+        Grey8(self.0.saturating_add(other.0))
+    }
+
+    fn clamping_mul(&self, factor: u8) -> Self
+    {
+        Grey8(self.0.saturating_mul(factor))
+    }
+
+    fn clamping_sub(&self, other: Self) -> Self
+    {
+        Grey8(self.0.saturating_sub(other.0))
+    }
+
+    fn sub(&self, other: Self) -> Self
+    {
+        Grey8(self.0 - other.0)
+    }
+
+    fn div(&self, divisor: u8) -> Self
+    {
+        debug_assert_ne!(divisor, 0);
+        Grey8(self.0 / divisor)
+    }
+
+    fn normalised_mul(&self, other: Self) -> Self
+    {
+        Grey8((self.0 as u16 * other.0 as u16 / 255) as u8)
+    }
+
+    fn clamping_fraction_mul(&self, fraction: (u8, u8)) -> Self
+    {
+        debug_assert_ne!(fraction.1, 0);
+        Grey8((self.0 as u16 * fraction.0 as u16 / fraction.1 as u16).min(255) as u8)
+    }
+}
+
+/// Channels run from 0 to 1. The clamping operations clamp to that range like the 8 bit types do,
+/// the others are plain floating point maths, so `sub` can go negative.
+impl PixelArithmetic for RgbF32
+{
+    fn clamping_add(&self, other: Self) -> Self
+    {
+        RgbF32::new((self.r + other.r).min(1.0), (self.g + other.g).min(1.0), (self.b + other.b).min(1.0))
+    }
+
+    fn clamping_mul(&self, factor: u8) -> Self
+    {
+        let factor = factor as f32;
+        RgbF32::new((self.r * factor).min(1.0), (self.g * factor).min(1.0), (self.b * factor).min(1.0))
+    }
+
+    fn clamping_sub(&self, other: Self) -> Self
+    {
+        RgbF32::new((self.r - other.r).max(0.0), (self.g - other.g).max(0.0), (self.b - other.b).max(0.0))
+    }
+
+    fn sub(&self, other: Self) -> Self
+    {
+        RgbF32::new(self.r - other.r, self.g - other.g, self.b - other.b)
+    }
+
+    fn div(&self, divisor: u8) -> Self
+    {
+        debug_assert_ne!(divisor, 0);
+        let divisor = divisor as f32;
+        RgbF32::new(self.r / divisor, self.g / divisor, self.b / divisor)
+    }
+
+    fn normalised_mul(&self, other: Self) -> Self
+    {
+        RgbF32::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+
+    fn clamping_fraction_mul(&self, fraction: (u8, u8)) -> Self
+    {
+        debug_assert_ne!(fraction.1, 0);
+        let factor = fraction.0 as f32 / fraction.1 as f32;
+        RgbF32::new((self.r * factor).min(1.0), (self.g * factor).min(1.0), (self.b * factor).min(1.0))
     }
 }
 
-pub fn temporal_denoising(current_pixel: olc::Pixel, next_pixel: olc::Pixel) -> olc::Pixel
+pub fn temporal_denoising(current_pixel: Rgba8, next_pixel: Rgba8) -> Rgba8
 {
     let dist2 = current_pixel.distance_squared(next_pixel);
     
@@ -187,5 +406,5 @@ pub fn temporal_denoising(current_pixel: olc::Pixel, next_pixel: olc::Pixel) ->
     r /= fraction.1;
     g /= fraction.1;
     b /= fraction.1;
-    return olc::Pixel::rgb(r as u8, g as u8, b as u8);
+    Rgba8::rgb(r as u8, g as u8, b as u8)
 }
\ No newline at end of file
@@ -0,0 +1,50 @@
+use crate::pixel::Rgba8;
+use crate::image::Image;
+use std::path::Path;
+
+/// Decodes a PNG file into an `Image`.
+/// 
+/// Palette, greyscale and 16 bit images are expanded to 8 bit colour. The alpha channel is kept as is.
+pub fn load_png(path: &Path) -> std::io::Result<Image>
+{
+    let file = std::fs::File::open(path)?;
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let bytes = &buffer[..info.buffer_size()];
+
+    let pixels = match info.color_type
+    {
+        png::ColorType::Grayscale => bytes.iter().map(|&v| Rgba8::rgb(v, v, v)).collect(),
+        png::ColorType::GrayscaleAlpha => bytes.chunks_exact(2).map(|p| Rgba8::rgba(p[0], p[0], p[0], p[1])).collect(),
+        png::ColorType::Rgb => bytes.chunks_exact(3).map(|p| Rgba8::rgb(p[0], p[1], p[2])).collect(),
+        png::ColorType::Rgba => bytes.chunks_exact(4).map(|p| Rgba8::rgba(p[0], p[1], p[2], p[3])).collect(),
+        png::ColorType::Indexed => unreachable!("indexed PNGs are expanded to RGB by the decoder"),
+    };
+    Ok(Image{width: info.width as usize, height: info.height as usize, pixels})
+}
+
+/// Encodes `image` as an 8 bit RGBA PNG file.
+pub fn save_png(image: &Image, path: &Path) -> std::io::Result<()>
+{
+    let file = std::fs::File::create(path)?;
+    let w = &mut std::io::BufWriter::new(file);
+    let mut encoder = png::Encoder::new(w, image.width as u32, image.height as u32); 
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455)); // 1.0 / 2.2, scaled by 100000
+    encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));     // 1.0 / 2.2, unscaled, but rounded
+    let source_chromaticities = png::SourceChromaticities::new
+    (   // Using unscaled instantiation here
+        (0.31270, 0.32900),
+        (0.64000, 0.33000),
+        (0.30000, 0.60000),
+        (0.15000, 0.06000)
+    );
+    encoder.set_source_chromaticities(source_chromaticities);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&image.pixels.iter().flat_map(|p| [p.r, p.g, p.b, p.a]).collect::<Vec<u8>>())?;
+    Ok(())
+}
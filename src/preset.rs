@@ -0,0 +1,203 @@
+use crate::filter;
+use crate::image::BorderMode;
+use crate::palette;
+use crate::processor::{InputMode, Pipeline, Stage};
+use crate::quantise::AutoPalette;
+use std::io;
+use std::path::Path;
+
+/// Reads a preset: a text file with one `key = value` setting per line.
+/// 
+/// ```text
+/// # lines starting with # are comments
+/// input_mode = TimeBlend
+/// palette = #003c3c #8c7832 #ffe100
+/// stage = GaussianBlur
+/// stage = Threshold level=100
+/// ```
+/// 
+/// Every `stage` line adds a processor to the end of the chain, followed by any of its parameters as `name=value`.
+/// Parameters that are left out keep their defaults, and values outside a parameter's range are clamped.
+/// `input_mode` and `palette` are optional and default to `Normal` and the built-in palette.
+/// `border` sets what kernels read past the edge of the image: `clamp` (the default), `mirror`, `wrap`, `crop` or `constant #rrggbb`.
+/// `auto_palette = kmeans 16 0.05` extracts the palette from the frames with `medianCut`, `kmeans` or `octree`,
+/// optionally followed by the number of colours and how fast it adapts to new frames, or `locked`, see `AutoPalette::from_name`.
+/// `linear_light = false` runs the blurs on sRGB values instead of linear light, as older versions did.
+/// Names are not case sensitive.
+pub fn parse(text: &str) -> io::Result<Pipeline>
+{
+    let mut pipeline = Pipeline{stages: Vec::new(), ..Pipeline::default()};
+    for (i, line) in text.lines().enumerate()
+    {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#')
+        {
+            continue;
+        }
+        let error = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", i + 1, message));
+        let (key, value) = line.split_once('=').ok_or_else(|| error(format!("expected key = value, got {}", line)))?;
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str()
+        {
+            "input_mode" => pipeline.input_mode = InputMode::from_name(value).ok_or_else(|| error(format!("unknown input mode {}", value)))?,
+            "palette" =>
+            {
+                pipeline.palette = value.split_whitespace()
+                    .map(|colour| palette::parse_hex(colour).ok_or_else(|| error(format!("expected a colour like #ff8000, got {}", colour))))
+                    .collect::<io::Result<Vec<_>>>()?;
+                if pipeline.palette.is_empty()
+                {
+                    return Err(error("the palette needs at least one colour".to_string()));
+                }
+            }
+            "auto_palette" => pipeline.auto_palette = Some(AutoPalette::from_name(value).ok_or_else(|| error(format!("expected a method, colours and adaptation like kmeans 16 0.05, got {}", value)))?),
+            "linear_light" => pipeline.linear_light = value.parse().map_err(|_| error(format!("linear_light expects true or false, got {}", value)))?,
+            "border" => pipeline.border = BorderMode::from_name(value).ok_or_else(|| error(format!("unknown border mode {}, expected clamp, mirror, wrap, crop or constant #rrggbb", value)))?,
+            "stage" => pipeline.stages.push(parse_stage(value).map_err(error)?),
+            other => return Err(error(format!("unknown setting {}", other))),
+        }
+    }
+    if pipeline.stages.is_empty()
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "a preset needs at least one stage"));
+    }
+    Ok(pipeline)
+}
+
+/// Parses the value of a `stage` line, e.g. `BoxBlur size=15`.
+fn parse_stage(text: &str) -> Result<Stage, String>
+{
+    let mut words = text.split_whitespace();
+    let name = words.next().ok_or_else(|| "stage needs a processor name".to_string())?;
+    let mut stage = Stage::new(filter::find(name).ok_or_else(|| format!("unknown processor {}", name))?);
+    for word in words
+    {
+        let (parameter, value) = word.split_once('=').ok_or_else(|| format!("expected parameter=value, got {}", word))?;
+        let (index, value) = match stage.filter.parameters().iter().position(|p| p.name.eq_ignore_ascii_case(parameter))
+        {
+            Some(index) => (index, stage.filter.parameters()[index].parse(value).ok_or_else(|| format!("{} expects a number, got {}", parameter, value))?),
+            None => value.parse().ok().and_then(|value| filter::former_parameter(name, parameter, value))
+                .ok_or_else(|| format!("{} has no parameter {}", stage.filter.name(), parameter))?,
+        };
+        stage.set_value(index, value);
+    }
+    Ok(stage)
+}
+
+/// Writes `pipeline` in the format read by `parse`.
+pub fn to_text(pipeline: &Pipeline) -> String
+{
+    let mut text = String::from("# Webcam Image Processor preset\n");
+    text += &format!("input_mode = {:?}\n", pipeline.input_mode);
+    text += &format!("palette = {}\n", pipeline.palette.iter().map(|&c| palette::to_hex(c)).collect::<Vec<_>>().join(" "));
+    if let Some(auto_palette) = &pipeline.auto_palette
+    {
+        text += &format!("auto_palette = {}\n", auto_palette.name());
+    }
+    text += &format!("border = {}\n", pipeline.border.name());
+    text += &format!("linear_light = {}\n", pipeline.linear_light);
+    for stage in &pipeline.stages
+    {
+        text += &format!("stage = {}", stage.filter.name());
+        for (parameter, value) in stage.filter.parameters().iter().zip(&stage.values)
+        {
+            text += &format!(" {}={}", parameter.name, parameter.to_text(*value));
+        }
+        text += "\n";
+    }
+    text
+}
+
+pub fn load(path: &Path) -> io::Result<Pipeline>
+{
+    parse(&std::fs::read_to_string(path)?)
+}
+
+pub fn save(pipeline: &Pipeline, path: &Path) -> io::Result<()>
+{
+    std::fs::write(path, to_text(pipeline))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::filter::{ParameterType, FILTERS};
+    use crate::pixel::Rgba8;
+
+    fn error(text: &str) -> String
+    {
+        parse(text).err().map(|e| e.to_string()).unwrap_or_default()
+    }
+
+    #[test]
+    fn text_round_trips_every_filter_and_setting()
+    {
+        let stages = FILTERS.iter().map(|&filter|
+        {
+            let mut stage = Stage::new(filter);
+            for (index, parameter) in filter.parameters().iter().enumerate()
+            {
+                // the last option of choices, and an awkward value between the steps of numbers
+                let value = match parameter.kind
+                {
+                    ParameterType::Choice(_) => parameter.max,
+                    _ => parameter.from_fraction(0.37),
+                };
+                let value = if value == parameter.default {parameter.from_fraction(0.81)} else {value};
+                assert!(value != parameter.default, "{} {}", filter.name(), parameter.name);
+                stage.set_value(index, value);
+            }
+            stage
+        }).collect();
+        let pipeline = Pipeline
+        {
+            input_mode: InputMode::TimeBlend,
+            stages,
+            palette: vec![Rgba8::rgb(0x12, 0xab, 0x34), Rgba8::BLACK, Rgba8::rgb(255, 255, 255)],
+            auto_palette: AutoPalette::from_name("octree 8 0.25"),
+            linear_light: false,
+            border: BorderMode::Constant(Rgba8::rgb(0xfe, 0x80, 0x01)),
+        };
+
+        let text = to_text(&pipeline);
+        assert!(text.contains("border = constant #fe8001\n"), "{}", text);
+        let read = parse(&text).unwrap();
+        assert_eq!(read.input_mode, pipeline.input_mode);
+        assert_eq!(read.palette, pipeline.palette);
+        assert_eq!(read.auto_palette.map(|a| a.name()), pipeline.auto_palette.map(|a| a.name()));
+        assert_eq!(read.linear_light, pipeline.linear_light);
+        assert_eq!(read.border, pipeline.border);
+        assert_eq!(read.stages.len(), FILTERS.len());
+        for (read, stage) in read.stages.iter().zip(&pipeline.stages)
+        {
+            assert_eq!(read.filter.name(), stage.filter.name());
+            assert_eq!(read.values, stage.values, "{}", stage.filter.name());
+        }
+        assert_eq!(to_text(&parse(&text).unwrap()), text);
+    }
+
+    #[test]
+    fn parameters_can_be_left_out_and_are_clamped()
+    {
+        let pipeline = parse("# a comment\n\n  STAGE = boxblur size=1000\nstage = OrderedDithering map=Bayer2\n").unwrap();
+        assert_eq!(pipeline.stages[0].values, vec![49.0]);
+        assert_eq!(pipeline.stages[1].values[0], pipeline.stages[1].filter.parameters()[0].default);
+        assert_eq!(pipeline.stages[1].values[1], 0.0);
+        assert_eq!((pipeline.input_mode, pipeline.border, pipeline.linear_light), (InputMode::Normal, BorderMode::Clamp, true));
+        assert_eq!(pipeline.palette, palette::DEFAULT.to_vec());
+    }
+
+    #[test]
+    fn errors_name_the_line()
+    {
+        assert_eq!(error("stage = Sobel\ncolour = red\n"), "line 2: unknown setting colour");
+        assert_eq!(error("# first\nstage =\n"), "line 2: stage needs a processor name");
+        assert_eq!(error("stage = Blurry\n"), "line 1: unknown processor Blurry");
+        assert_eq!(error("stage = GaussianBlurSigma radius=2\n"), "line 1: GaussianBlurSigma has no parameter radius");
+        assert_eq!(error("stage = BoxBlur size\n"), "line 1: expected parameter=value, got size");
+        assert_eq!(error("stage = BoxBlur size=big\n"), "line 1: size expects a number, got big");
+        assert_eq!(error("input_mode = Normal\n"), "a preset needs at least one stage");
+        assert_eq!(error("stage = Sobel\nborder = sideways\n"), "line 2: unknown border mode sideways, expected clamp, mirror, wrap, crop or constant #rrggbb");
+    }
+}
@@ -0,0 +1,405 @@
+use crate::pixel::{Grey8, Rgba8, RgbF32};
+use crate::image::{BorderMode, Image};
+use crate::filter::{Filter, FilterContext, FloydSteinbergDitheringCustomPalette};
+use crate::palette;
+use crate::quantise::AutoPalette;
+use crate::pixel_traits::*;
+
+/// One step of a processor chain: a filter together with the values of its parameters.
+#[derive(Clone)]
+pub struct Stage
+{
+    pub filter: &'static dyn Filter,
+    /// One value per entry of `filter.parameters()`.
+    pub values: Vec<f32>,
+}
+
+impl Stage
+{
+    /// Creates a stage with the default parameter values of `filter`.
+    pub fn new(filter: &'static dyn Filter) -> Self
+    {
+        Self{filter, values: filter.parameters().iter().map(|p| p.default).collect()}
+    }
+
+    /// Switches to another filter. Its parameters start at their defaults.
+    pub fn set_filter(&mut self, filter: &'static dyn Filter)
+    {
+        if filter.name() != self.filter.name()
+        {
+            *self = Stage::new(filter);
+        }
+    }
+
+    /// Sets parameter `index` to `value`, constrained to the parameter's range.
+    pub fn set_value(&mut self, index: usize, value: f32)
+    {
+        self.values[index] = self.filter.parameters()[index].constrain(value);
+    }
+}
+
+/// Everything that decides how frames are processed, and what a preset file stores.
+#[derive(Clone)]
+pub struct Pipeline
+{
+    pub input_mode: InputMode,
+    /// The processor chain, run front to back.
+    pub stages: Vec<Stage>,
+    /// The colours the palette based filters reduce the image to.
+    pub palette: Vec<Rgba8>,
+    /// Extracts `palette` from the frames instead of keeping it fixed.
+    pub auto_palette: Option<AutoPalette>,
+    /// Whether filters that support it run in linear light. Turning it off runs everything on sRGB encoded values.
+    pub linear_light: bool,
+    /// What the kernel based filters read past the edge of the image.
+    pub border: BorderMode,
+}
+
+impl Default for Pipeline
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            input_mode: InputMode::Normal,
+            stages: vec![Stage::new(&FloydSteinbergDitheringCustomPalette)],
+            palette: palette::DEFAULT.to_vec(),
+            auto_palette: None,
+            linear_light: true,
+            border: BorderMode::Clamp,
+        }
+    }
+}
+
+impl Pipeline
+{
+    /// What the filter of `stage` gets besides the images.
+    fn context<'a>(&'a self, stage: &'a Stage) -> FilterContext<'a>
+    {
+        FilterContext{values: &stage.values, palette: &self.palette, border: self.border}
+    }
+
+    /// Updates `palette` from the colours of the input `frame` if there is an `auto_palette`. Call it once for every new frame.
+    pub fn update_palette(&mut self, frame: &Image)
+    {
+        if let Some(auto_palette) = &mut self.auto_palette
+        {
+            self.palette = auto_palette.update(frame);
+        }
+    }
+
+    /// Runs the stages one after another, each one reading the output of the previous one, and writes the result to `target`.
+    ///
+    /// Stages whose filter works in linear light get the image decoded to `RgbF32`. Consecutive linear stages pass floats
+    /// to each other, so the image is only rounded to 8 bits when a stage needs sRGB or at the end.
+    /// Stages whose filter only writes a brightness write to a `Grey8` image, which is expanded to colour when the next stage
+    /// reads it or at the end.
+    /// An empty chain copies `source` to `target`.
+    pub fn run(&self, source: &Image, target: &mut Image, buffers: &mut Buffers)
+    {
+        buffers.fit_to(target.width, target.height);
+        // where the output of the last stage is
+        enum Latest
+        {
+            Source,
+            Target,
+            Linear,
+            Grey,
+        }
+        let mut latest = Latest::Source;
+        for stage in &self.stages
+        {
+            if self.linear_light && stage.filter.works_in_linear_light()
+            {
+                match latest
+                {
+                    Latest::Source => source.to_linear_into(&mut buffers.linear_temp),
+                    Latest::Target => target.to_linear_into(&mut buffers.linear_temp),
+                    Latest::Linear => std::mem::swap(&mut buffers.linear, &mut buffers.linear_temp),
+                    Latest::Grey =>
+                    {
+                        buffers.grey.convert_into(&mut buffers.temp);
+                        buffers.temp.to_linear_into(&mut buffers.linear_temp);
+                    }
+                }
+                stage.filter.apply_linear(&buffers.linear_temp, &mut buffers.linear, &self.context(stage));
+                latest = Latest::Linear;
+                continue;
+            }
+            let input = match latest
+            {
+                Latest::Source => source,
+                Latest::Target =>
+                {
+                    std::mem::swap(target, &mut buffers.temp);
+                    &buffers.temp
+                }
+                Latest::Linear =>
+                {
+                    buffers.linear.to_srgb_into(&mut buffers.temp);
+                    &buffers.temp
+                }
+                Latest::Grey =>
+                {
+                    buffers.grey.convert_into(&mut buffers.temp);
+                    &buffers.temp
+                }
+            };
+            if stage.filter.writes_grey()
+            {
+                stage.filter.apply_grey(input, &mut buffers.grey, &self.context(stage));
+                latest = Latest::Grey;
+            }
+            else
+            {
+                stage.filter.apply(input, target, &self.context(stage));
+                latest = Latest::Target;
+            }
+        }
+        match latest
+        {
+            Latest::Source => target.pixels.copy_from_slice(&source.pixels),
+            Latest::Linear => buffers.linear.to_srgb_into(target),
+            Latest::Grey => buffers.grey.convert_into(target),
+            Latest::Target => (),
+        }
+    }
+}
+
+/// Scratch images for `Pipeline::run`, kept between frames so that they aren't allocated for every frame.
+pub struct Buffers
+{
+    temp: Image,
+    linear: Image<RgbF32>,
+    linear_temp: Image<RgbF32>,
+    grey: Image<Grey8>,
+}
+
+impl Buffers
+{
+    pub fn new(width: usize, height: usize) -> Self
+    {
+        Self
+        {
+            temp: Image::new(width, height, Rgba8::BLACK),
+            linear: Image::new(width, height, RgbF32::default()),
+            linear_temp: Image::new(width, height, RgbF32::default()),
+            grey: Image::new(width, height, Grey8::default()),
+        }
+    }
+
+    /// Reallocates the buffers if the image size changed.
+    fn fit_to(&mut self, width: usize, height: usize)
+    {
+        if (self.temp.width, self.temp.height) != (width, height)
+        {
+            *self = Buffers::new(width, height);
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum InputMode
+{
+    /// Uses each new frame as it is.
+    Normal, 
+    /// Keeps 8/10 of the previous input and mixes in 2/10 of the new frame.
+    TimeBlend,
+    /// Mixes in between 1/10 and all of each new pixel, more the further it is from the previous one.
+    Denoising,
+}
+
+impl InputMode
+{
+    pub const ALL: [InputMode; 3] = [InputMode::Normal, InputMode::TimeBlend, InputMode::Denoising];
+
+    pub fn from_name(name: &str) -> Option<InputMode>
+    {
+        InputMode::ALL.iter().copied().find(|m| format!("{:?}", m).eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// The position of the mode in `ALL`.
+    pub fn index(&self) -> usize
+    {
+        InputMode::ALL.iter().position(|m| m == self).unwrap_or(0)
+    }
+
+    /// Combines the previous input `frame` with the `next` one, writing the result to `frame`.
+    pub fn blend(&self, frame: &mut Image, next: &Image)
+    {
+        match self
+        {
+            InputMode::Normal
+            =>  {
+                    frame.pixels.copy_from_slice(&next.pixels);
+                }
+            ,
+
+            InputMode::TimeBlend
+            => 
+                // fraction.0 is proportional to the influence of the next frame
+                for (i, &pixel) in next.pixels.iter().enumerate()
+                {
+                    let fraction = (2, 10);
+                    let pa = frame.pixels[i];
+                    let pb = pixel;
+
+                    let mut r = pa.r as u32 * fraction.1;
+                    let mut g = pa.g as u32 * fraction.1;
+                    let mut b = pa.b as u32 * fraction.1;
+
+                    r -= pa.r as u32 * fraction.0;
+                    g -= pa.g as u32 * fraction.0;
+                    b -= pa.b as u32 * fraction.0;
+
+                    r += pb.r as u32 * fraction.0;
+                    g += pb.g as u32 * fraction.0;
+                    b += pb.b as u32 * fraction.0;
+
+                    r /= fraction.1;
+                    g /= fraction.1;
+                    b /= fraction.1;
+
+                    frame.pixels[i] = Rgba8::rgb(r as u8, g as u8, b as u8);
+                }
+            ,
+
+            InputMode::Denoising
+            => 
+                //denoising based on pixel difference between frames
+                for (i, &pixel) in next.pixels.iter().enumerate()
+                {
+                    let p = temporal_denoising(frame.pixels[i], pixel);
+                    frame.pixels[i] = p;
+                }
+            ,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::filter::{BoxBlur, ChromaticAberration, GaussianBlur, GreyScale, SharpenColour, Sobel, Threshold};
+
+    fn gradient(width: usize, height: usize) -> Image
+    {
+        let mut image = Image::new(width, height, Rgba8::BLACK);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                image[(x, y)] = Rgba8::rgb((x * 23 + y * 5) as u8, (x * x * 7 + y * 31) as u8, (200 - x * 9 - y * 13) as u8);
+            }
+        }
+        image
+    }
+
+    /// Applies the stages one by one on new images, keeping floats only between consecutive linear stages.
+    fn one_by_one(pipeline: &Pipeline, source: &Image) -> Image
+    {
+        let mut colour = source.clone();
+        let mut linear: Option<Image<RgbF32>> = None;
+        for stage in &pipeline.stages
+        {
+            if pipeline.linear_light && stage.filter.works_in_linear_light()
+            {
+                let input = linear.take().unwrap_or_else(|| colour.to_linear());
+                let mut output = input.clone();
+                stage.filter.apply_linear(&input, &mut output, &pipeline.context(stage));
+                linear = Some(output);
+            }
+            else
+            {
+                if let Some(input) = linear.take()
+                {
+                    input.to_srgb_into(&mut colour);
+                }
+                let mut output = colour.clone();
+                stage.filter.apply(&colour, &mut output, &pipeline.context(stage));
+                colour = output;
+            }
+        }
+        if let Some(input) = linear
+        {
+            input.to_srgb_into(&mut colour);
+        }
+        colour
+    }
+
+    #[test]
+    fn every_stage_reads_the_output_of_the_one_before()
+    {
+        // colour, grey and linear stages, with offsets and thresholds that make the order matter
+        let mut shift = Stage::new(&ChromaticAberration);
+        shift.set_value(0, 2.0);
+        let mut threshold = Stage::new(&Threshold);
+        threshold.set_value(0, 90.0);
+        let choices = [shift, threshold, Stage::new(&Sobel), Stage::new(&GreyScale), Stage::new(&SharpenColour), Stage::new(&GaussianBlur), Stage::new(&BoxBlur)];
+
+        let source = gradient(11, 7);
+        let mut buffers = Buffers::new(1, 1);
+        let mut target = Image::new(11, 7, Rgba8::BLACK);
+        for linear_light in [false, true]
+        {
+            for length in 0..=4
+            {
+                for combination in 0..choices.len().pow(length)
+                {
+                    let stages = (0..length).map(|i| choices[combination / choices.len().pow(i) % choices.len()].clone()).collect();
+                    let pipeline = Pipeline{stages, linear_light, ..Pipeline::default()};
+                    pipeline.run(&source, &mut target, &mut buffers);
+                    let names: Vec<_> = pipeline.stages.iter().map(|s| s.filter.name()).collect();
+                    assert!(target.pixels == one_by_one(&pipeline, &source).pixels, "{:?}, linear light {}", names, linear_light);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_chains_copy_the_source()
+    {
+        let source = gradient(5, 4);
+        let mut target = Image::new(5, 4, Rgba8::BLACK);
+        Pipeline{stages: Vec::new(), ..Pipeline::default()}.run(&source, &mut target, &mut Buffers::new(5, 4));
+        assert!(target.pixels == source.pixels);
+    }
+
+    #[test]
+    fn time_blend_keeps_eight_tenths_of_the_previous_input()
+    {
+        let mut frame = Image::new(2, 1, Rgba8::rgb(100, 255, 0));
+        let next = Image::new(2, 1, Rgba8::rgb(200, 0, 255));
+        InputMode::TimeBlend.blend(&mut frame, &next);
+        assert_eq!(frame.pixels, vec![Rgba8::rgb(120, 204, 51); 2]);
+
+        InputMode::Normal.blend(&mut frame, &next);
+        assert_eq!(frame.pixels, next.pixels);
+    }
+
+    #[test]
+    fn denoising_mixes_in_more_of_pixels_that_changed_more()
+    {
+        let grey = Rgba8::rgb(100, 100, 100);
+        let cases =
+        [
+            // unchanged pixels stay
+            (grey, grey, grey),
+            // a distance of 12 takes 4/10 of the new pixel
+            (grey, Rgba8::rgb(100, 100, 112), Rgba8::rgb(100, 100, 104)),
+            // a distance of 4 takes 2/10
+            (grey, Rgba8::rgb(104, 100, 100), Rgba8::rgb(100, 100, 100)),
+            // from a distance of 36 on, and past the cut off, the new pixel replaces the old one
+            (grey, Rgba8::rgb(140, 100, 100), Rgba8::rgb(140, 100, 100)),
+            (Rgba8::BLACK, Rgba8::rgb(160, 160, 160), Rgba8::rgb(160, 160, 160)),
+            (Rgba8::BLACK, Rgba8::rgb(255, 255, 255), Rgba8::rgb(255, 255, 255)),
+        ];
+        for (previous, next, expected) in cases
+        {
+            let mut frame = Image::new(1, 1, previous);
+            InputMode::Denoising.blend(&mut frame, &Image::new(1, 1, next));
+            assert_eq!(frame.pixels[0], expected, "{:?} to {:?}", previous, next);
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use crate::image::Image;
+use crate::pixel::Rgba8;
+use std::collections::HashMap;
+
+/// How `AutoPalette` picks the colours of a palette.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum QuantisationMethod
+{
+    /// Splits the colours into boxes at the median of their widest channel. Fast, and gives rare colours a fair share.
+    MedianCut,
+    /// Refines the median cut palette by moving every colour to the mean of the pixels nearest to it.
+    KMeans,
+    /// Merges the least used branches of an octree of the colours until few enough leaves are left.
+    Octree,
+}
+
+impl QuantisationMethod
+{
+    pub const ALL: [QuantisationMethod; 3] = [QuantisationMethod::MedianCut, QuantisationMethod::KMeans, QuantisationMethod::Octree];
+
+    pub fn from_name(name: &str) -> Option<QuantisationMethod>
+    {
+        QuantisationMethod::ALL.iter().copied().find(|m| format!("{:?}", m).eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// The position of the method in `ALL`.
+    pub fn index(&self) -> usize
+    {
+        QuantisationMethod::ALL.iter().position(|m| m == self).unwrap_or(0)
+    }
+}
+
+/// The distinct colours of an image, with 5 bits per channel, and how many pixels have each one.
+/// Every entry keeps the mean of the full precision colours that fell into it.
+struct Histogram
+{
+    colours: Vec<[f32; 3]>,
+    counts: Vec<u32>,
+}
+
+impl Histogram
+{
+    fn new(image: &Image) -> Self
+    {
+        let mut bins = vec![([0u64; 3], 0u32); 1 << 15];
+        for p in &image.pixels
+        {
+            let bin = &mut bins[((p.r as usize >> 3) << 10) | ((p.g as usize >> 3) << 5) | (p.b as usize >> 3)];
+            bin.0[0] += p.r as u64;
+            bin.0[1] += p.g as u64;
+            bin.0[2] += p.b as u64;
+            bin.1 += 1;
+        }
+        let (colours, counts) = bins.into_iter().filter(|(_, count)| *count > 0)
+            .map(|(sum, count)| (sum.map(|channel| channel as f32 / count as f32), count))
+            .unzip();
+        Self{colours, counts}
+    }
+
+    /// The mean colour of the entries `indices`, weighted by their counts.
+    fn mean(&self, indices: &[usize]) -> [f32; 3]
+    {
+        let mut sum = [0.0f64; 3];
+        let mut total = 0.0f64;
+        for &i in indices
+        {
+            for (s, &c) in sum.iter_mut().zip(&self.colours[i])
+            {
+                *s += c as f64 * self.counts[i] as f64;
+            }
+            total += self.counts[i] as f64;
+        }
+        sum.map(|s| (s / total.max(1.0)) as f32)
+    }
+}
+
+/// Picks a palette of at most `colours` colours. Histograms with fewer distinct colours get fewer.
+/// With k-means, `previous` is where the colours start if it has the right number of them.
+fn extract(histogram: &Histogram, colours: usize, method: QuantisationMethod, previous: Option<&[[f32; 3]]>) -> Vec<[f32; 3]>
+{
+    match method
+    {
+        QuantisationMethod::MedianCut => median_cut(histogram, colours),
+        QuantisationMethod::KMeans =>
+        {
+            // starting from the last palette keeps the colours in place from frame to frame
+            let start = match previous
+            {
+                Some(previous) if previous.len() == colours => previous.to_vec(),
+                _ => median_cut(histogram, colours),
+            };
+            k_means(histogram, start, 8)
+        }
+        QuantisationMethod::Octree => octree(histogram, colours),
+    }
+}
+
+fn to_rgba8(colours: &[[f32; 3]]) -> Vec<Rgba8>
+{
+    colours.iter().map(|c| {let [r, g, b] = c.map(|c| c.round().clamp(0.0, 255.0) as u8); Rgba8::rgb(r, g, b)}).collect()
+}
+
+/// Heckbert's [median cut]: starting with one box around all colours, keeps splitting the box with the widest range
+/// in any channel at the median pixel along that channel, then takes the mean colour of every box.
+///
+/// [median cut]: https://en.wikipedia.org/wiki/Median_cut
+fn median_cut(histogram: &Histogram, colours: usize) -> Vec<[f32; 3]>
+{
+    let mut boxes = vec![(0..histogram.colours.len()).collect::<Vec<_>>()];
+    boxes.retain(|entries| !entries.is_empty());
+    // the widest channel of a box and how wide it is
+    let widest = |entries: &[usize]|
+    {
+        (0..3).map(|channel|
+        {
+            let values = entries.iter().map(|&i| histogram.colours[i][channel]);
+            let (min, max) = values.fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(v), max.max(v)));
+            (channel, max - min)
+        }).max_by(|a, b| a.1.total_cmp(&b.1)).unwrap_or((0, 0.0))
+    };
+    while boxes.len() < colours
+    {
+        let candidate = boxes.iter().enumerate().filter(|(_, entries)| entries.len() > 1)
+            .map(|(i, entries)| (i, widest(entries))).max_by(|a, b| a.1.1.total_cmp(&b.1.1));
+        let (index, (channel, _)) = match candidate
+        {
+            Some(candidate) => candidate,
+            None => break,
+        };
+        let mut entries = boxes.swap_remove(index);
+        entries.sort_by(|&a, &b| histogram.colours[a][channel].total_cmp(&histogram.colours[b][channel]));
+        // split where half of the pixels are on either side, leaving at least one entry in each box
+        let total = entries.iter().map(|&i| histogram.counts[i] as u64).sum::<u64>();
+        let mut seen = 0;
+        let mut split = entries.len() - 1;
+        for (position, &i) in entries.iter().enumerate()
+        {
+            seen += histogram.counts[i] as u64;
+            if seen * 2 >= total
+            {
+                split = (position + 1).clamp(1, entries.len() - 1);
+                break;
+            }
+        }
+        let upper = entries.split_off(split);
+        boxes.push(entries);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|entries| histogram.mean(entries)).collect()
+}
+
+/// [k-means clustering] from `centres`: every colour goes to its nearest centre, and every centre moves to the mean
+/// of its colours, weighted by their counts, up to `iterations` times or until nothing moves. Centres without colours stay.
+///
+/// [k-means clustering]: https://en.wikipedia.org/wiki/K-means_clustering
+fn k_means(histogram: &Histogram, mut centres: Vec<[f32; 3]>, iterations: usize) -> Vec<[f32; 3]>
+{
+    let mut assignment = vec![usize::MAX; histogram.colours.len()];
+    for _ in 0..iterations
+    {
+        let mut changed = false;
+        for (colour, assigned) in histogram.colours.iter().zip(&mut assignment)
+        {
+            let nearest = nearest(&centres, colour);
+            changed |= nearest != *assigned;
+            *assigned = nearest;
+        }
+        if !changed
+        {
+            break;
+        }
+        let mut members = vec![vec![]; centres.len()];
+        for (i, &assigned) in assignment.iter().enumerate()
+        {
+            members[assigned].push(i);
+        }
+        for (centre, members) in centres.iter_mut().zip(&members)
+        {
+            if !members.is_empty()
+            {
+                *centre = histogram.mean(members);
+            }
+        }
+    }
+    centres
+}
+
+/// Octree quantisation: every colour is a leaf at the depth of the histogram's 5 bits, and the node whose
+/// child leaves hold the fewest pixels on the deepest level is merged into one leaf until at most `colours` are left. Once every node of a level is merged the next level up follows.
+///
+/// Merging a node can take away up to seven leaves at once, so the palette can end up a few colours short.
+fn octree(histogram: &Histogram, colours: usize) -> Vec<[f32; 3]>
+{
+    const DEPTH: u32 = 5;
+    // leaves by depth and by their colour at that depth, with the summed colour and pixel count
+    let mut leaves: HashMap<(u32, [u8; 3]), ([f64; 3], u64)> = HashMap::new();
+    for (colour, &count) in histogram.colours.iter().zip(&histogram.counts)
+    {
+        let key = colour.map(|c| (c as u8) >> (8 - DEPTH));
+        let leaf = leaves.entry((DEPTH, key)).or_insert(([0.0; 3], 0));
+        for (sum, &c) in leaf.0.iter_mut().zip(colour)
+        {
+            *sum += c as f64 * count as f64;
+        }
+        leaf.1 += count as u64;
+    }
+    for depth in (1..=DEPTH).rev()
+    {
+        if leaves.len() <= colours.max(1)
+        {
+            break;
+        }
+        // the leaves on this level by their parent, with the pixels under every parent
+        let mut parents: HashMap<[u8; 3], (Vec<[u8; 3]>, u64)> = HashMap::new();
+        for (&(_, key), &(_, count)) in leaves.iter().filter(|((d, _), _)| *d == depth)
+        {
+            let parent = parents.entry(key.map(|c| c >> 1)).or_insert((vec![], 0));
+            parent.0.push(key);
+            parent.1 += count;
+        }
+        // ties go to the smallest key, so the result doesn't depend on the order of the map
+        let mut parents = parents.into_iter().collect::<Vec<_>>();
+        parents.sort_by_key(|(key, (_, count))| (*count, *key));
+        for (parent, (children, _)) in parents
+        {
+            if leaves.len() <= colours.max(1)
+            {
+                break;
+            }
+            let mut merged = ([0.0; 3], 0);
+            for child in children
+            {
+                let (sum, count) = leaves.remove(&(depth, child)).unwrap();
+                for (m, s) in merged.0.iter_mut().zip(sum)
+                {
+                    *m += s;
+                }
+                merged.1 += count;
+            }
+            leaves.insert((depth - 1, parent), merged);
+        }
+    }
+    let mut result = leaves.into_iter().collect::<Vec<_>>();
+    result.sort_by_key(|&(key, _)| key);
+    result.into_iter().map(|(_, (sum, count))| sum.map(|s| (s / count.max(1) as f64) as f32)).collect()
+}
+
+/// The index of the colour in `palette` nearest to `colour`.
+fn nearest(palette: &[[f32; 3]], colour: &[f32; 3]) -> usize
+{
+    let distance = |p: &[f32; 3]| p.iter().zip(colour).map(|(a, b)| (a - b) * (a - b)).sum::<f32>();
+    palette.iter().enumerate().min_by(|a, b| distance(a.1).total_cmp(&distance(b.1))).map(|(i, _)| i).unwrap_or(0)
+}
+
+/// A palette that is extracted from the frames as they come in, see `Pipeline::update_palette`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct AutoPalette
+{
+    pub method: QuantisationMethod,
+    /// How many colours to extract.
+    pub colours: usize,
+    /// How far the palette moves towards the colours of every new frame, from 0 to 1.
+    /// 0 locks the palette once it has been extracted, 1 extracts it anew for every frame.
+    pub adaptation: f32,
+    /// The palette so far in full precision, so that small steps towards the new colours aren't lost to rounding.
+    current: Vec<[f32; 3]>,
+}
+
+impl AutoPalette
+{
+    /// The rate at which the palette follows the scene when it isn't locked: it covers most of the way in about a second.
+    pub const DEFAULT_ADAPTATION: f32 = 0.05;
+
+    pub fn new(method: QuantisationMethod, colours: usize, adaptation: f32) -> Self
+    {
+        Self{method, colours: colours.max(1), adaptation: adaptation.clamp(0.0, 1.0), current: vec![]}
+    }
+
+    /// Reads a setting like `kmeans 16 0.05`: the method, then optionally the number of colours, 16 if left out,
+    /// and the adaptation rate or `locked`, `DEFAULT_ADAPTATION` if left out.
+    pub fn from_name(text: &str) -> Option<AutoPalette>
+    {
+        let mut words = text.split_whitespace();
+        let method = QuantisationMethod::from_name(words.next()?)?;
+        let colours = match words.next()
+        {
+            Some(colours) => colours.parse().ok().filter(|&colours| colours > 0)?,
+            None => 16,
+        };
+        let adaptation = match words.next()
+        {
+            Some(word) if word.eq_ignore_ascii_case("locked") => 0.0,
+            Some(rate) => rate.parse().ok().filter(|rate| (0.0..=1.0).contains(rate))?,
+            None => Self::DEFAULT_ADAPTATION,
+        };
+        if words.next().is_some()
+        {
+            return None;
+        }
+        Some(AutoPalette::new(method, colours, adaptation))
+    }
+
+    /// The setting in the form `from_name` reads.
+    pub fn name(&self) -> String
+    {
+        let adaptation = if self.locked() {"locked".to_string()} else {self.adaptation.to_string()};
+        format!("{:?} {} {}", self.method, self.colours, adaptation)
+    }
+
+    pub fn locked(&self) -> bool
+    {
+        self.adaptation <= 0.0
+    }
+
+    /// Makes the next `update` extract the palette anew, even when it's locked.
+    pub fn reset(&mut self)
+    {
+        self.current.clear();
+    }
+
+    /// Moves the palette towards the colours of `frame` and returns it.
+    ///
+    /// Every old colour moves towards the new colour it's paired with. Pairs are made nearest first,
+    /// so that colours don't swap places and the dithering doesn't flicker. If the new palette has a different
+    /// number of colours it replaces the old one.
+    pub fn update(&mut self, frame: &Image) -> Vec<Rgba8>
+    {
+        if self.current.is_empty()
+        {
+            self.current = extract(&Histogram::new(frame), self.colours, self.method, None);
+        }
+        else if !self.locked()
+        {
+            let new = extract(&Histogram::new(frame), self.colours, self.method, Some(&self.current));
+            if new.len() != self.current.len()
+            {
+                // the scene has more or fewer colours than before, or `colours` changed
+                self.current = new;
+                return to_rgba8(&self.current);
+            }
+            let mut pairs = Vec::with_capacity(self.current.len() * new.len());
+            for (i, old) in self.current.iter().enumerate()
+            {
+                for (j, colour) in new.iter().enumerate()
+                {
+                    pairs.push((old.iter().zip(colour).map(|(a, b)| (a - b) * (a - b)).sum::<f32>(), i, j));
+                }
+            }
+            pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let (mut old_paired, mut new_paired) = (vec![false; self.current.len()], vec![false; new.len()]);
+            for (_, i, j) in pairs
+            {
+                if old_paired[i] || new_paired[j]
+                {
+                    continue;
+                }
+                old_paired[i] = true;
+                new_paired[j] = true;
+                for (old, &colour) in self.current[i].iter_mut().zip(&new[j])
+                {
+                    *old += (colour - *old) * self.adaptation;
+                }
+            }
+        }
+        to_rgba8(&self.current)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Every pixel a different mix of colours, the same on every run.
+    fn colourful_image(width: usize, height: usize) -> Image
+    {
+        let mut image = Image::new(width, height, Rgba8::BLACK);
+        for (i, pixel) in image.pixels.iter_mut().enumerate()
+        {
+            let [r, g, b, _] = (i as u32).wrapping_mul(2654435761).to_le_bytes();
+            *pixel = Rgba8::rgb(r, g, b);
+        }
+        image
+    }
+
+    fn palette(image: &Image, colours: usize, method: QuantisationMethod) -> Vec<Rgba8>
+    {
+        to_rgba8(&extract(&Histogram::new(image), colours, method, None))
+    }
+
+    #[test]
+    fn palettes_have_at_most_the_colours_asked_for()
+    {
+        let image = colourful_image(64, 48);
+        for method in QuantisationMethod::ALL
+        {
+            for colours in [1, 2, 5, 16, 64, 256]
+            {
+                let palette = palette(&image, colours, method);
+                assert!(!palette.is_empty() && palette.len() <= colours, "{:?} gave {} colours for {}", method, palette.len(), colours);
+            }
+            // median cut and k-means fill the palette when there are enough distinct colours
+            if method != QuantisationMethod::Octree
+            {
+                assert_eq!(palette(&image, 16, method).len(), 16);
+            }
+        }
+    }
+
+    #[test]
+    fn one_colour_gives_that_colour()
+    {
+        let image = Image::new(8, 8, Rgba8::rgb(200, 30, 77));
+        for method in QuantisationMethod::ALL
+        {
+            for colours in [1, 16]
+            {
+                assert_eq!(palette(&image, colours, method), vec![Rgba8::rgb(200, 30, 77)], "{:?} with {} colours", method, colours);
+            }
+        }
+    }
+
+    #[test]
+    fn one_colour_asked_for_gives_the_mean()
+    {
+        let mut image = Image::new(2, 1, Rgba8::rgb(0, 0, 0));
+        image.pixels[1] = Rgba8::rgb(200, 100, 50);
+        for method in QuantisationMethod::ALL
+        {
+            assert_eq!(palette(&image, 1, method), vec![Rgba8::rgb(100, 50, 25)], "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn distinct_colours_are_found_exactly()
+    {
+        let colours = [Rgba8::rgb(255, 0, 0), Rgba8::rgb(0, 128, 255), Rgba8::rgb(20, 20, 20), Rgba8::rgb(250, 250, 200)];
+        let mut image = Image::new(16, 16, colours[0]);
+        for (i, pixel) in image.pixels.iter_mut().enumerate()
+        {
+            *pixel = colours[i % colours.len()];
+        }
+        for method in QuantisationMethod::ALL
+        {
+            let mut palette = palette(&image, 8, method);
+            palette.sort_by_key(|p| (p.r, p.g, p.b));
+            let mut expected = colours.to_vec();
+            expected.sort_by_key(|p| (p.r, p.g, p.b));
+            assert_eq!(palette, expected, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn settings_read_back_what_they_write()
+    {
+        for text in ["kmeans 16 0.05", "octree 3 locked", "MedianCut 256 1"]
+        {
+            let auto_palette = AutoPalette::from_name(text).unwrap();
+            assert_eq!(AutoPalette::from_name(&auto_palette.name()), Some(auto_palette));
+        }
+        for text in ["", "kmeans 0", "kmeans 16 2", "popularity 16", "kmeans 16 0.05 extra"]
+        {
+            assert_eq!(AutoPalette::from_name(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn locked_palettes_stay_and_open_ones_follow()
+    {
+        let (red, blue) = (Image::new(4, 4, Rgba8::rgb(255, 0, 0)), Image::new(4, 4, Rgba8::rgb(0, 0, 255)));
+        let mut locked = AutoPalette::new(QuantisationMethod::MedianCut, 4, 0.0);
+        assert_eq!(locked.update(&red), vec![Rgba8::rgb(255, 0, 0)]);
+        assert_eq!(locked.update(&blue), vec![Rgba8::rgb(255, 0, 0)]);
+        let mut following = AutoPalette::new(QuantisationMethod::MedianCut, 4, 0.5);
+        following.update(&red);
+        assert_eq!(following.update(&blue), vec![Rgba8::rgb(128, 0, 128)]);
+    }
+}
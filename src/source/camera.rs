@@ -0,0 +1,45 @@
+use crate::image::Image;
+use crate::pixel_format::{self, PixelFormat, YuvConversion};
+use super::FrameSource;
+
+/// Reads frames from a webcam through `camera_capture`.
+pub struct CameraSource
+{
+    cam_iter: camera_capture::ImageIterator,
+}
+
+impl CameraSource
+{
+    /// Opens camera number `index` and starts capturing at the requested `fps` and resolution.
+    /// 
+    /// If the camera doesn't support that resolution, the supported one closest to it is used instead.
+    pub fn new(index: u32, fps: f64, width: u32, height: u32) -> Result<Self, camera_capture::Error>
+    {
+        let builder = match camera_capture::create(index)?.resolution(width, height)
+        {
+            Err(camera_capture::Error::InvalidResolution(supported)) if !supported.is_empty() =>
+            {
+                let closest = supported.iter()
+                    .min_by_key(|&&(w, h)| (w as i64 - width as i64).abs() + (h as i64 - height as i64).abs())
+                    .copied()
+                    .unwrap();
+                println!("Camera {} doesn't support {}x{}, using {}x{} instead.", index, width, height, closest.0, closest.1);
+                camera_capture::create(index)?.resolution(closest.0, closest.1)?
+            }
+            result => result?,
+        };
+        let cam_iter = builder.fps(fps)?.start()?;
+        Ok(Self{cam_iter})
+    }
+}
+
+impl FrameSource for CameraSource
+{
+    fn next_frame(&mut self) -> Option<Image>
+    {
+        // camera_capture always asks the driver for packed RGB
+        let frame = self.cam_iter.next()?;
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        pixel_format::convert_to_image(&frame, width, height, PixelFormat::Rgb24, YuvConversion::default()).ok()
+    }
+}
@@ -0,0 +1,88 @@
+use crate::image::{Image, FitMode, ResampleFilter};
+
+pub mod camera;
+pub mod still;
+pub mod pattern;
+pub mod y4m;
+pub mod raw;
+pub mod threaded;
+
+pub use camera::CameraSource;
+pub use pattern::{TestPattern, TestPatternSource};
+pub use y4m::Y4mSource;
+pub use raw::RawFileSource;
+pub use still::ImageSequenceSource;
+pub use threaded::{ThreadedCapture, CapturedFrame};
+
+/// Anything that can feed frames into the processing pipeline.
+/// 
+/// The webcam is only one implementation; files, generators and test doubles work just as well,
+/// which means nothing downstream of a `FrameSource` needs a real camera to run.
+pub trait FrameSource
+{
+    /// Returns the next frame, or `None` if no new frame is available.
+    /// 
+    /// Returning `None` is not an error: the caller keeps showing the previous frame and asks again later.
+    fn next_frame(&mut self) -> Option<Image>;
+
+    /// The frame rate the source has decided on itself, as numerator and denominator, like the header of a video file.
+    /// `None` means it delivers frames at the rate it was asked for.
+    fn frame_rate(&self) -> Option<(u32, u32)>
+    {
+        None
+    }
+}
+
+/// Yields its image once. Since the pipeline keeps the last frame, that is enough to show it indefinitely.
+pub struct StaticSource
+{
+    image: Option<Image>,
+}
+
+impl StaticSource
+{
+    pub fn new(image: Image) -> Self
+    {
+        Self{image: Some(image)}
+    }
+}
+
+impl FrameSource for StaticSource
+{
+    fn next_frame(&mut self) -> Option<Image>
+    {
+        self.image.take()
+    }
+}
+
+/// Scales the frames of another source to a fixed size, so that the capture resolution can differ from the processing resolution.
+pub struct FittedSource
+{
+    inner: Box<dyn FrameSource>,
+    width: usize,
+    height: usize,
+    fit_mode: FitMode,
+    filter: ResampleFilter,
+}
+
+impl FittedSource
+{
+    pub fn new(inner: Box<dyn FrameSource>, width: usize, height: usize, fit_mode: FitMode, filter: ResampleFilter) -> Self
+    {
+        Self{inner, width, height, fit_mode, filter}
+    }
+}
+
+impl FrameSource for FittedSource
+{
+    fn next_frame(&mut self) -> Option<Image>
+    {
+        let frame = self.inner.next_frame()?;
+        Some(frame.fit_linear(self.width, self.height, self.fit_mode, self.filter))
+    }
+
+    fn frame_rate(&self) -> Option<(u32, u32)>
+    {
+        self.inner.frame_rate()
+    }
+}
@@ -0,0 +1,316 @@
+use crate::pixel::Rgba8;
+use crate::image::Image;
+use super::FrameSource;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TestPattern
+{
+    ColourBars,
+    HorizontalGradient,
+    VerticalGradient,
+    ZonePlate,
+    Checkerboard,
+    MovingEdge,
+    Noise,
+}
+
+impl TestPattern
+{
+    pub const ALL: [TestPattern; 7] =
+    [
+        TestPattern::ColourBars,
+        TestPattern::HorizontalGradient,
+        TestPattern::VerticalGradient,
+        TestPattern::ZonePlate,
+        TestPattern::Checkerboard,
+        TestPattern::MovingEdge,
+        TestPattern::Noise,
+    ];
+
+    /// The short name used to pick a pattern at startup, e.g. `bars` or `zoneplate`.
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            TestPattern::ColourBars => "bars",
+            TestPattern::HorizontalGradient => "hgradient",
+            TestPattern::VerticalGradient => "vgradient",
+            TestPattern::ZonePlate => "zoneplate",
+            TestPattern::Checkerboard => "checkerboard",
+            TestPattern::MovingEdge => "edge",
+            TestPattern::Noise => "noise",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TestPattern>
+    {
+        TestPattern::ALL.iter().copied().find(|p| p.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Whether the pattern changes from frame to frame.
+    pub fn is_animated(&self) -> bool
+    {
+        matches!(self, TestPattern::MovingEdge | TestPattern::Noise)
+    }
+
+    /// Draws frame number `frame` of the pattern. `seed` only affects `Noise`.
+    pub fn render(&self, width: usize, height: usize, frame: u64, seed: u64) -> Image
+    {
+        let mut image = Image{width, height, pixels: vec![Rgba8::BLACK; width * height]};
+        match self
+        {
+            TestPattern::ColourBars => draw_colour_bars(&mut image),
+            TestPattern::HorizontalGradient =>
+            {
+                for y in 0..height
+                {
+                    for x in 0..width
+                    {
+                        let v = (x * 255 / (width - 1).max(1)) as u8;
+                        image[(x, y)] = Rgba8::rgb(v, v, v);
+                    }
+                }
+            }
+            TestPattern::VerticalGradient =>
+            {
+                for y in 0..height
+                {
+                    let v = (y * 255 / (height - 1).max(1)) as u8;
+                    for x in 0..width
+                    {
+                        image[(x, y)] = Rgba8::rgb(v, v, v);
+                    }
+                }
+            }
+            TestPattern::ZonePlate =>
+            {
+                // the local frequency grows linearly with the radius and reaches the Nyquist limit at the corners
+                let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+                let max_radius = (cx * cx + cy * cy).sqrt();
+                let k = std::f32::consts::PI / (2.0 * max_radius);
+                for y in 0..height
+                {
+                    for x in 0..width
+                    {
+                        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                        let v = (127.5 + 127.5 * (k * (dx * dx + dy * dy)).cos()) as u8;
+                        image[(x, y)] = Rgba8::rgb(v, v, v);
+                    }
+                }
+            }
+            TestPattern::Checkerboard =>
+            {
+                let cell = (width.max(height) / 16).max(1);
+                for y in 0..height
+                {
+                    for x in 0..width
+                    {
+                        image[(x, y)] = if (x / cell) % 2 == (y / cell) % 2 {Rgba8::WHITE} else {Rgba8::BLACK};
+                    }
+                }
+            }
+            TestPattern::MovingEdge =>
+            {
+                // a slightly slanted edge sweeping from left to right, which exercises sub-pixel edge positions
+                let sweep = width + height / 4;
+                let position = (frame as usize * 2) % sweep.max(1);
+                for y in 0..height
+                {
+                    for x in 0..width
+                    {
+                        image[(x, y)] = if x + y / 4 < position {Rgba8::WHITE} else {Rgba8::BLACK};
+                    }
+                }
+            }
+            TestPattern::Noise =>
+            {
+                let rng = fastrand::Rng::with_seed(seed ^ frame.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                for pixel in image.pixels.iter_mut()
+                {
+                    *pixel = Rgba8::rgb(rng.u8(..), rng.u8(..), rng.u8(..));
+                }
+            }
+        }
+        image
+    }
+}
+
+/// SMPTE ECR 1-1978 colour bars: 75% bars on top, the reversed blue bars in the middle and the -I/white/+Q/PLUGE row at the bottom.
+fn draw_colour_bars(image: &mut Image)
+{
+    let rgb = Rgba8::rgb;
+    let black = rgb(19, 19, 19);
+    let top = [rgb(191, 191, 191), rgb(191, 191, 0), rgb(0, 191, 191), rgb(0, 191, 0), rgb(191, 0, 191), rgb(191, 0, 0), rgb(0, 0, 191)];
+    let middle = [rgb(0, 0, 191), black, rgb(191, 0, 191), black, rgb(0, 191, 191), black, rgb(191, 191, 191)];
+    // widths of the bottom row in 1/84ths of the image: four blocks of 15, three PLUGE bars of 4 and the final black block of 12
+    let bottom = [(15, rgb(0, 33, 76)), (15, rgb(255, 255, 255)), (15, rgb(50, 0, 106)), (15, black), (4, rgb(9, 9, 9)), (4, black), (4, rgb(29, 29, 29)), (12, black)];
+
+    let (width, height) = (image.width, image.height);
+    for y in 0..height
+    {
+        for x in 0..width
+        {
+            image[(x, y)] = if y < height * 2 / 3
+            {
+                top[x * 7 / width]
+            }
+            else if y < height * 3 / 4
+            {
+                middle[x * 7 / width]
+            }
+            else
+            {
+                let mut position = x * 84 / width;
+                let mut colour = black;
+                for &(block_width, block_colour) in &bottom
+                {
+                    if position < block_width
+                    {
+                        colour = block_colour;
+                        break;
+                    }
+                    position -= block_width;
+                }
+                colour
+            };
+        }
+    }
+}
+
+/// Generates test patterns instead of reading from a device or file.
+/// 
+/// Still patterns are produced once, animated ones at 30 frames per second unless changed with `with_fps`.
+pub struct TestPatternSource
+{
+    pattern: TestPattern,
+    width: usize,
+    height: usize,
+    seed: u64,
+    frame: u64,
+    frame_duration: std::time::Duration,
+    next_frame_due: std::time::Instant,
+}
+
+impl TestPatternSource
+{
+    pub fn new(pattern: TestPattern, width: usize, height: usize) -> Self
+    {
+        Self
+        {
+            pattern,
+            width,
+            height,
+            seed: 0,
+            frame: 0,
+            frame_duration: std::time::Duration::from_secs_f64(1.0 / 30.0),
+            next_frame_due: std::time::Instant::now(),
+        }
+    }
+
+    pub fn with_fps(mut self, fps: f64) -> Self
+    {
+        self.frame_duration = std::time::Duration::from_secs_f64(1.0 / fps);
+        self
+    }
+
+    /// Sets the seed of the `Noise` pattern, so that the same seed always produces the same sequence of frames.
+    pub fn with_seed(mut self, seed: u64) -> Self
+    {
+        self.seed = seed;
+        self
+    }
+}
+
+impl FrameSource for TestPatternSource
+{
+    fn next_frame(&mut self) -> Option<Image>
+    {
+        if !self.pattern.is_animated() && self.frame > 0
+        {
+            return None;
+        }
+        let now = std::time::Instant::now();
+        if now < self.next_frame_due
+        {
+            return None;
+        }
+        self.next_frame_due = (self.next_frame_due + self.frame_duration).max(now);
+
+        let image = self.pattern.render(self.width, self.height, self.frame, self.seed);
+        self.frame += 1;
+        Some(image)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Takes `count` frames from a source that delivers them as fast as it is asked.
+    fn frames(pattern: TestPattern, seed: u64, count: usize) -> Vec<Image>
+    {
+        let mut source = TestPatternSource::new(pattern, 16, 9).with_fps(1e9).with_seed(seed);
+        let mut frames = Vec::new();
+        while frames.len() < count
+        {
+            frames.extend(source.next_frame());
+        }
+        frames
+    }
+
+    #[test]
+    fn every_pattern_is_found_by_its_name()
+    {
+        for pattern in TestPattern::ALL
+        {
+            assert_eq!(TestPattern::from_name(pattern.name()), Some(pattern));
+            assert_eq!(TestPattern::from_name(&format!(" {} ", pattern.name().to_uppercase())), Some(pattern));
+            assert_eq!(TestPattern::ALL.iter().filter(|p| p.name() == pattern.name()).count(), 1);
+        }
+        assert_eq!(TestPattern::from_name("stripes"), None);
+    }
+
+    #[test]
+    fn frames_have_the_requested_size()
+    {
+        for pattern in TestPattern::ALL
+        {
+            for (width, height) in [(1, 1), (1, 5), (5, 1), (2, 3), (17, 5), (64, 48)]
+            {
+                for frame in [0, 1, 1000]
+                {
+                    let image = pattern.render(width, height, frame, 3);
+                    assert_eq!((image.width, image.height, image.pixels.len()), (width, height, width * height), "{:?}", pattern);
+                }
+            }
+            let mut source = TestPatternSource::new(pattern, 40, 30);
+            let image = source.next_frame().unwrap();
+            assert_eq!((image.width, image.height), (40, 30), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn still_patterns_are_produced_once()
+    {
+        for pattern in TestPattern::ALL
+        {
+            let mut source = TestPatternSource::new(pattern, 8, 8).with_fps(1e9);
+            assert!(source.next_frame().is_some());
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            assert_eq!(source.next_frame().is_some(), pattern.is_animated(), "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn noise_is_the_same_for_the_same_seed()
+    {
+        let pixels = |frames: Vec<Image>| frames.into_iter().map(|frame| frame.pixels).collect::<Vec<_>>();
+        let first = pixels(frames(TestPattern::Noise, 7, 3));
+        assert!(first == pixels(frames(TestPattern::Noise, 7, 3)));
+        assert!(first != pixels(frames(TestPattern::Noise, 8, 3)));
+        // the frames of one sequence differ from each other
+        assert!(first[0] != first[1] && first[1] != first[2]);
+        assert!(TestPattern::Noise.render(16, 9, 1, 7).pixels == first[1]);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::image::Image;
+use crate::pixel_format::{self, PixelFormat, YuvConversion};
+use super::FrameSource;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Plays a file of headerless raw frames, e.g. a dump from `v4l2-ctl --stream-to`, as a looping video.
+pub struct RawFileSource
+{
+    path: PathBuf,
+    file: std::io::BufReader<std::fs::File>,
+    format: PixelFormat,
+    conversion: YuvConversion,
+    frame_width: usize,
+    frame_height: usize,
+    buffer: Vec<u8>,
+    frame_duration: std::time::Duration,
+    next_frame_due: std::time::Instant,
+}
+
+impl RawFileSource
+{
+    /// `frame_width` and `frame_height` are the size of the frames in the file.
+    pub fn open(path: &Path, format: PixelFormat, conversion: YuvConversion, (frame_width, frame_height): (usize, usize), fps: f64) -> std::io::Result<Self>
+    {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(Self
+        {
+            path: path.to_path_buf(),
+            file,
+            format,
+            conversion,
+            frame_width,
+            frame_height,
+            buffer: vec![0; format.frame_size(frame_width, frame_height)],
+            frame_duration: std::time::Duration::from_secs_f64(1.0 / fps),
+            next_frame_due: std::time::Instant::now(),
+        })
+    }
+
+    /// Reads one frame into the buffer, starting over at the end of the file.
+    fn read_frame(&mut self) -> std::io::Result<()>
+    {
+        match self.file.read_exact(&mut self.buffer)
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                self.file = std::io::BufReader::new(std::fs::File::open(&self.path)?);
+                self.file.read_exact(&mut self.buffer)
+            }
+            result => result,
+        }
+    }
+}
+
+impl FrameSource for RawFileSource
+{
+    fn next_frame(&mut self) -> Option<Image>
+    {
+        let now = std::time::Instant::now();
+        if now < self.next_frame_due
+        {
+            return None;
+        }
+        self.next_frame_due = (self.next_frame_due + self.frame_duration).max(now);
+
+        let frame = self.read_frame()
+            .and_then(|_| pixel_format::convert_to_image(&self.buffer, self.frame_width, self.frame_height, self.format, self.conversion));
+        match frame
+        {
+            Ok(frame) => Some(frame),
+            Err(e) =>
+            {
+                eprintln!("Could not read {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+}
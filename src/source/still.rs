@@ -0,0 +1,129 @@
+use crate::image::Image;
+use crate::png_io::load_png;
+use super::FrameSource;
+use std::path::{Path, PathBuf};
+
+/// Plays a directory of numbered PNG files as a looping video.
+/// 
+/// Files are ordered by the number in their name, so `frame_2.png` comes before `frame_10.png`.
+/// Every frame is decoded when it is due.
+pub struct ImageSequenceSource
+{
+    paths: Vec<PathBuf>,
+    frame_duration: std::time::Duration,
+    start: std::time::Instant,
+    last_index: Option<usize>,
+}
+
+impl ImageSequenceSource
+{
+    pub fn open(directory: &Path, fps: f64) -> std::io::Result<Self>
+    {
+        let paths = list_numbered_pngs(directory)?;
+        Ok(Self
+        {
+            paths,
+            frame_duration: std::time::Duration::from_secs_f64(1.0 / fps),
+            start: std::time::Instant::now(),
+            last_index: None,
+        })
+    }
+}
+
+impl FrameSource for ImageSequenceSource
+{
+    fn next_frame(&mut self) -> Option<Image>
+    {
+        let elapsed_frames = self.start.elapsed().as_nanos() / self.frame_duration.as_nanos().max(1);
+        let index = (elapsed_frames % self.paths.len() as u128) as usize;
+        if self.last_index == Some(index)
+        {
+            return None;
+        }
+        self.last_index = Some(index);
+        match load_png(&self.paths[index])
+        {
+            Ok(image) => Some(image),
+            Err(e) =>
+            {
+                eprintln!("Could not read {}: {}", self.paths[index].display(), e);
+                None
+            }
+        }
+    }
+}
+
+/// Lists the PNG files in `directory`, ordered by the number in their name. Fails if there are none.
+/// 
+/// Files that batch mode wrote, the ones ending in `batch::PROCESSED_SUFFIX`, are left out, so that a directory it was run on
+/// still plays and processes as the frames it had before.
+pub fn list_numbered_pngs(directory: &Path) -> std::io::Result<Vec<PathBuf>>
+{
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(directory)?
+    {
+        let path = entry?.path();
+        let is_png = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("png"));
+        let is_processed = path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| stem.ends_with(crate::batch::PROCESSED_SUFFIX));
+        if is_png && !is_processed && path.is_file()
+        {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty()
+    {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("no PNG files in {}", directory.display())));
+    }
+    paths.sort_by_cached_key(|p| (frame_number(p), p.clone()));
+    Ok(paths)
+}
+
+/// The last run of digits in the file name, e.g. `7` for `take3_0007.png`.
+fn frame_number(path: &Path) -> Option<u64>
+{
+    let stem = path.file_stem()?.to_str()?;
+    let end = stem.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = stem[..end].rfind(|c: char| !c.is_ascii_digit()).map_or(0, |i| i + 1);
+    stem[start..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn names(paths: &[PathBuf]) -> Vec<String>
+    {
+        paths.iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn frames_are_ordered_by_their_number()
+    {
+        let directory = crate::test_support::temporary_directory("numbered_pngs");
+        assert!(list_numbered_pngs(&directory).is_err());
+        let files = ["frame_10.png", "frame_2.png", "take3_0007.PNG", "poster.png", "frame_1.png", "cover.png", "notes.txt", "frame_2_processed.png"];
+        for name in files
+        {
+            std::fs::write(directory.join(name), b"").unwrap();
+        }
+        std::fs::create_dir(directory.join("frame_0.png")).unwrap();
+
+        let paths = list_numbered_pngs(&directory).unwrap();
+        // names without digits come first, in alphabetical order
+        assert_eq!(names(&paths), ["cover.png", "poster.png", "frame_1.png", "frame_2.png", "take3_0007.PNG", "frame_10.png"]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn frame_numbers_are_the_last_run_of_digits()
+    {
+        let number = |name: &str| frame_number(Path::new(name));
+        assert_eq!(number("frame_2.png"), Some(2));
+        assert_eq!(number("take3_0007.png"), Some(7));
+        assert_eq!(number("12.png"), Some(12));
+        assert_eq!(number("v2_frame.png"), Some(2));
+        assert_eq!(number("cover.png"), None);
+        assert_eq!(number("99999999999999999999999.png"), None);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::image::Image;
+use super::FrameSource;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A frame together with the time it was captured.
+pub struct CapturedFrame
+{
+    pub image: Image,
+    pub timestamp: std::time::Instant,
+    /// Counts every frame the source produced, including the ones that were dropped.
+    pub sequence: u64,
+}
+
+struct Shared
+{
+    queue: Mutex<VecDeque<CapturedFrame>>,
+    capacity: usize,
+    stop: AtomicBool,
+    captured: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Runs a `FrameSource` on a background thread, so that a slow camera doesn't stall rendering and input.
+/// 
+/// Frames are kept in a small queue. When it is full the oldest frame is dropped, so the consumer always gets recent frames.
+pub struct ThreadedCapture
+{
+    shared: Arc<Shared>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    started: std::time::Instant,
+    frame_rate: Option<(u32, u32)>,
+}
+
+impl ThreadedCapture
+{
+    /// Starts the capture thread. The source is created on that thread by `open_source`,
+    /// because camera handles generally can't be moved between threads.
+    /// 
+    /// Waits until the source is open, and returns its error if that fails.
+    pub fn spawn<F>(capacity: usize, open_source: F) -> Result<Self, String> where F: FnOnce() -> Result<Box<dyn FrameSource>, String> + Send + 'static
+    {
+        let shared = Arc::new(Shared
+        {
+            queue: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            stop: AtomicBool::new(false),
+            captured: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        let thread_shared = shared.clone();
+        let (opened, result) = std::sync::mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move ||
+            {
+                match open_source()
+                {
+                    Ok(source) =>
+                    {
+                        let _ = opened.send(Ok(source.frame_rate()));
+                        capture_loop(source, &thread_shared);
+                    }
+                    Err(e) =>
+                    {
+                        let _ = opened.send(Err(e));
+                    }
+                }
+            })
+            .map_err(|e| format!("could not start the capture thread: {}", e))?;
+        match result.recv()
+        {
+            Ok(Ok(frame_rate)) => Ok(Self{shared, thread: Some(thread), started: std::time::Instant::now(), frame_rate}),
+            Ok(Err(e)) =>
+            {
+                let _ = thread.join();
+                Err(e)
+            }
+            Err(_) =>
+            {
+                let _ = thread.join();
+                Err("the capture thread stopped while opening the source".to_string())
+            }
+        }
+    }
+
+    /// Takes the oldest queued frame.
+    pub fn next_captured(&mut self) -> Option<CapturedFrame>
+    {
+        self.shared.queue.lock().unwrap().pop_front()
+    }
+
+    /// Number of frames that were thrown away because the queue was full.
+    pub fn dropped_frames(&self) -> u64
+    {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn captured_frames(&self) -> u64
+    {
+        self.shared.captured.load(Ordering::Relaxed)
+    }
+
+    /// The moment the capture thread was started, which frame timestamps can be related to.
+    pub fn started(&self) -> std::time::Instant
+    {
+        self.started
+    }
+}
+
+fn capture_loop(mut source: Box<dyn FrameSource>, shared: &Shared)
+{
+    while !shared.stop.load(Ordering::Relaxed)
+    {
+        let image = match source.next_frame()
+        {
+            Some(image) => image,
+            None =>
+            {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                continue;
+            }
+        };
+        let frame = CapturedFrame
+        {
+            image,
+            timestamp: std::time::Instant::now(),
+            sequence: shared.captured.fetch_add(1, Ordering::Relaxed),
+        };
+        let mut queue = shared.queue.lock().unwrap();
+        if queue.len() >= shared.capacity
+        {
+            queue.pop_front();
+            shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        queue.push_back(frame);
+    }
+}
+
+impl FrameSource for ThreadedCapture
+{
+    fn next_frame(&mut self) -> Option<Image>
+    {
+        self.next_captured().map(|frame| frame.image)
+    }
+
+    fn frame_rate(&self) -> Option<(u32, u32)>
+    {
+        self.frame_rate
+    }
+}
+
+impl Drop for ThreadedCapture
+{
+    fn drop(&mut self)
+    {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take()
+        {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::pixel::Rgba8;
+    use crate::source::StaticSource;
+
+    #[test]
+    fn frames_arrive_from_the_capture_thread()
+    {
+        let image = Image{width: 2, height: 1, pixels: vec![Rgba8::rgb(1, 2, 3), Rgba8::rgb(4, 5, 6)]};
+        let expected = image.pixels.clone();
+        let mut capture = ThreadedCapture::spawn(2, move || Ok(Box::new(StaticSource::new(image)) as Box<dyn FrameSource>)).unwrap();
+        let frame = loop
+        {
+            if let Some(frame) = capture.next_captured()
+            {
+                break frame;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        assert_eq!((frame.sequence, frame.image.pixels), (0, expected));
+    }
+
+    /// Produces `count` frames as fast as it is asked, each filled with its number, then no more.
+    struct CountingSource
+    {
+        next: u8,
+        count: u8,
+    }
+
+    impl FrameSource for CountingSource
+    {
+        fn next_frame(&mut self) -> Option<Image>
+        {
+            if self.next == self.count
+            {
+                return None;
+            }
+            self.next += 1;
+            Some(Image::new(1, 1, Rgba8::rgb(self.next - 1, 0, 0)))
+        }
+    }
+
+    fn wait_for(capture: &ThreadedCapture, captured: u64)
+    {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while capture.captured_frames() < captured
+        {
+            assert!(std::time::Instant::now() < deadline, "only {} frames were captured", capture.captured_frames());
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn full_queues_drop_the_oldest_frames()
+    {
+        let mut capture = ThreadedCapture::spawn(3, || Ok(Box::new(CountingSource{next: 0, count: 20}) as Box<dyn FrameSource>)).unwrap();
+        // the consumer doesn't take anything until the source has run dry
+        wait_for(&capture, 20);
+        assert_eq!(capture.dropped_frames(), 17);
+        let mut kept = Vec::new();
+        while let Some(frame) = capture.next_captured()
+        {
+            assert_eq!(frame.image.pixels[0].r as u64, frame.sequence);
+            kept.push(frame.sequence);
+        }
+        assert_eq!(kept, vec![17, 18, 19]);
+        assert_eq!((capture.captured_frames(), capture.dropped_frames()), (20, 17));
+    }
+
+    #[test]
+    fn slow_consumers_get_recent_frames_in_order()
+    {
+        let mut capture = ThreadedCapture::spawn(2, || Ok(Box::new(CountingSource{next: 0, count: 200}) as Box<dyn FrameSource>)).unwrap();
+        let mut taken = Vec::new();
+        while taken.last() != Some(&199)
+        {
+            std::thread::sleep(std::time::Duration::from_millis(3));
+            if let Some(frame) = capture.next_captured()
+            {
+                taken.push(frame.sequence);
+            }
+        }
+        // every frame was either taken or counted as dropped, and the newest one always arrives
+        assert!(taken.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", taken);
+        assert!(capture.dropped_frames() > 0);
+        assert_eq!(capture.dropped_frames() + taken.len() as u64, 200);
+        assert_eq!(capture.captured_frames(), 200);
+    }
+
+    #[test]
+    fn open_errors_reach_the_caller()
+    {
+        let result = ThreadedCapture::spawn(2, || Err("no such camera".to_string()));
+        assert_eq!(result.err(), Some("no such camera".to_string()));
+
+        let result = ThreadedCapture::spawn(2, || panic!("driver crashed"));
+        assert_eq!(result.err(), Some("the capture thread stopped while opening the source".to_string()));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::image::Image;
+use crate::y4m::Y4mReader;
+use super::FrameSource;
+use std::path::{Path, PathBuf};
+
+/// Plays a `.y4m` file as a looping video at the frame rate stored in its header.
+pub struct Y4mSource
+{
+    path: PathBuf,
+    reader: Y4mReader<std::io::BufReader<std::fs::File>>,
+    frame_rate: (u32, u32),
+    frame_duration: std::time::Duration,
+    next_frame_due: std::time::Instant,
+}
+
+impl Y4mSource
+{
+    pub fn open(path: &Path) -> std::io::Result<Self>
+    {
+        let reader = Self::open_reader(path)?;
+        let frame_rate = match reader.header().frame_rate
+        {
+            (0, _) | (_, 0) => (25, 1),
+            frame_rate => frame_rate,
+        };
+        Ok(Self
+        {
+            path: path.to_path_buf(),
+            reader,
+            frame_rate,
+            frame_duration: std::time::Duration::from_secs_f64(frame_rate.1 as f64 / frame_rate.0 as f64),
+            next_frame_due: std::time::Instant::now(),
+        })
+    }
+
+    fn open_reader(path: &Path) -> std::io::Result<Y4mReader<std::io::BufReader<std::fs::File>>>
+    {
+        Y4mReader::new(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+impl FrameSource for Y4mSource
+{
+    fn next_frame(&mut self) -> Option<Image>
+    {
+        let now = std::time::Instant::now();
+        if now < self.next_frame_due
+        {
+            return None;
+        }
+        self.next_frame_due = (self.next_frame_due + self.frame_duration).max(now);
+
+        let frame = match self.reader.read_frame()
+        {
+            Ok(Some(frame)) => Ok(Some(frame)),
+            // start over at the end of the file
+            Ok(None) => Self::open_reader(&self.path).and_then(|reader| {self.reader = reader; self.reader.read_frame()}),
+            Err(e) => Err(e),
+        };
+        match frame
+        {
+            Ok(frame) => frame,
+            Err(e) =>
+            {
+                eprintln!("Could not read {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn frame_rate(&self) -> Option<(u32, u32)>
+    {
+        Some(self.frame_rate)
+    }
+}
@@ -0,0 +1,12 @@
+//! Helpers shared by the unit tests.
+
+use std::path::PathBuf;
+
+/// An empty directory for the test `name`, in the system's temporary directory.
+pub fn temporary_directory(name: &str) -> PathBuf
+{
+    let directory = std::env::temp_dir().join(format!("webcam_image_processor_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
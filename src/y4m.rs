@@ -0,0 +1,444 @@
+use crate::image::Image;
+use crate::pixel_format::{YuvConversion, YuvMatrix, YuvRange};
+use std::io::{BufRead, Write};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Chroma
+{
+    C420,
+    C422,
+    C444,
+    Mono,
+}
+
+impl Chroma
+{
+    fn from_tag(tag: &str) -> Option<Chroma>
+    {
+        match tag
+        {
+            "420" | "420jpeg" | "420paldv" | "420mpeg2" => Some(Chroma::C420),
+            "422" => Some(Chroma::C422),
+            "444" => Some(Chroma::C444),
+            "mono" => Some(Chroma::Mono),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> &'static str
+    {
+        match self
+        {
+            Chroma::C420 => "420jpeg",
+            Chroma::C422 => "422",
+            Chroma::C444 => "444",
+            Chroma::Mono => "mono",
+        }
+    }
+
+    /// Horizontal and vertical subsampling factors of the chroma planes.
+    fn subsampling(&self) -> (usize, usize)
+    {
+        match self
+        {
+            Chroma::C420 => (2, 2),
+            Chroma::C422 => (2, 1),
+            Chroma::C444 | Chroma::Mono => (1, 1),
+        }
+    }
+
+    /// Size of one chroma plane, or `(0, 0)` for `Mono`.
+    fn plane_size(&self, width: usize, height: usize) -> (usize, usize)
+    {
+        if *self == Chroma::Mono
+        {
+            return (0, 0);
+        }
+        let (sx, sy) = self.subsampling();
+        (width.div_ceil(sx), height.div_ceil(sy))
+    }
+}
+
+/// The stream parameters from a Y4M file header.
+#[derive(Clone, Debug)]
+pub struct Y4mHeader
+{
+    pub width: usize,
+    pub height: usize,
+    /// Frame rate as `numerator / denominator` frames per second.
+    pub frame_rate: (u32, u32),
+    pub chroma: Chroma,
+    /// `true` if the samples use the full 0-255 range instead of the studio range 16-235.
+    pub full_range: bool,
+}
+
+impl Y4mHeader
+{
+    pub fn fps(&self) -> f64
+    {
+        self.frame_rate.0 as f64 / self.frame_rate.1.max(1) as f64
+    }
+
+    /// Y4M streams don't record their matrix; BT.601 is what `ffmpeg` assumes.
+    fn conversion(&self) -> YuvConversion
+    {
+        YuvConversion{matrix: YuvMatrix::Bt601, range: if self.full_range {YuvRange::Full} else {YuvRange::Limited}}
+    }
+
+    fn frame_size(&self) -> usize
+    {
+        let (cw, ch) = self.chroma.plane_size(self.width, self.height);
+        self.width * self.height + 2 * cw * ch
+    }
+}
+
+/// `fps` as the numerator and denominator of a Y4M header: whole rates over 1,
+/// the NTSC rates like 29.97 over 1001 and anything else to a thousandth of a frame.
+pub fn frame_rate(fps: f64) -> (u32, u32)
+{
+    if (fps - fps.round()).abs() < 1e-6
+    {
+        return (fps.round().max(1.0) as u32, 1);
+    }
+    let ntsc = (fps * 1.001).round();
+    if (ntsc / 1.001 - fps).abs() < 1e-3
+    {
+        return (ntsc as u32 * 1000, 1001);
+    }
+    let thousandths = ((fps * 1000.0).round() as u32).max(1);
+    let (mut a, mut b) = (thousandths, 1000);
+    while b != 0
+    {
+        (a, b) = (b, a % b);
+    }
+    (thousandths / a, 1000 / a)
+}
+
+fn invalid_data(message: String) -> std::io::Error
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Reads uncompressed [YUV4MPEG2] streams, the format `ffmpeg` and most other video tools use to pipe raw frames.
+/// 
+/// [YUV4MPEG2]: https://wiki.multimedia.cx/index.php/YUV4MPEG2
+pub struct Y4mReader<R: BufRead>
+{
+    reader: R,
+    header: Y4mHeader,
+    buffer: Vec<u8>,
+}
+
+impl<R: BufRead> Y4mReader<R>
+{
+    pub fn new(mut reader: R) -> std::io::Result<Self>
+    {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut tokens = line.trim_end().split(' ');
+        if tokens.next() != Some("YUV4MPEG2")
+        {
+            return Err(invalid_data("not a YUV4MPEG2 stream".to_string()));
+        }
+
+        let mut header = Y4mHeader{width: 0, height: 0, frame_rate: (25, 1), chroma: Chroma::C420, full_range: false};
+        for token in tokens.filter(|t| !t.is_empty())
+        {
+            let (tag, value) = token.split_at(token.chars().next().map_or(0, char::len_utf8));
+            let parse_error = || invalid_data(format!("invalid Y4M header parameter {}", token));
+            match tag
+            {
+                "W" => header.width = value.parse().map_err(|_| parse_error())?,
+                "H" => header.height = value.parse().map_err(|_| parse_error())?,
+                "F" =>
+                {
+                    let (num, den) = value.split_once(':').ok_or_else(parse_error)?;
+                    header.frame_rate = (num.parse().map_err(|_| parse_error())?, den.parse().map_err(|_| parse_error())?);
+                }
+                "C" => header.chroma = Chroma::from_tag(value).ok_or_else(|| invalid_data(format!("unsupported Y4M colour space {}", value)))?,
+                "X" if value.eq_ignore_ascii_case("COLORRANGE=FULL") => header.full_range = true,
+                _ => (), // interlacing, aspect ratio and other extensions don't affect decoding
+            }
+        }
+        if header.width == 0 || header.height == 0
+        {
+            return Err(invalid_data("Y4M header is missing the frame size".to_string()));
+        }
+
+        let buffer = vec![0; header.frame_size()];
+        Ok(Self{reader, header, buffer})
+    }
+
+    pub fn header(&self) -> &Y4mHeader
+    {
+        &self.header
+    }
+
+    /// Reads the next frame. Returns `Ok(None)` at the end of the stream.
+    pub fn read_frame(&mut self) -> std::io::Result<Option<Image>>
+    {
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0
+        {
+            return Ok(None);
+        }
+        if !line.starts_with(b"FRAME")
+        {
+            return Err(invalid_data("expected a FRAME marker".to_string()));
+        }
+        self.reader.read_exact(&mut self.buffer)?;
+        Ok(Some(self.decode_frame()))
+    }
+
+    fn decode_frame(&self) -> Image
+    {
+        let Y4mHeader{width, height, chroma, ..} = self.header;
+        let conversion = self.header.conversion();
+        let (sx, sy) = chroma.subsampling();
+        let (cw, ch) = chroma.plane_size(width, height);
+        let (luma, chroma_planes) = self.buffer.split_at(width * height);
+        let (u_plane, v_plane) = chroma_planes.split_at(cw * ch);
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                let luma_value = luma[y * width + x];
+                let (u, v) = if chroma == Chroma::Mono
+                {
+                    (128, 128)
+                }
+                else
+                {
+                    let i = (y / sy) * cw + x / sx;
+                    (u_plane[i], v_plane[i])
+                };
+                pixels.push(conversion.to_rgb(luma_value, u, v));
+            }
+        }
+        Image{width, height, pixels}
+    }
+}
+
+/// Writes `Image`s as an uncompressed YUV4MPEG2 stream, e.g. for `ffmpeg -i recording.y4m recording.mp4`.
+pub struct Y4mWriter<W: Write>
+{
+    writer: W,
+    header: Y4mHeader,
+}
+
+impl<W: Write> Y4mWriter<W>
+{
+    /// Writes the stream header. Every frame passed to `write_frame` must have the size given in `header`.
+    pub fn new(mut writer: W, header: Y4mHeader) -> std::io::Result<Self>
+    {
+        write!(writer, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C{}", header.width, header.height, header.frame_rate.0, header.frame_rate.1, header.chroma.tag())?;
+        if header.full_range
+        {
+            write!(writer, " XCOLORRANGE=FULL")?;
+        }
+        writeln!(writer)?;
+        Ok(Self{writer, header})
+    }
+
+    pub fn write_frame(&mut self, image: &Image) -> std::io::Result<()>
+    {
+        let Y4mHeader{width, height, chroma, ..} = self.header;
+        let conversion = self.header.conversion();
+        if image.width != width || image.height != height
+        {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("frame is {}x{}, but the stream is {}x{}", image.width, image.height, width, height)));
+        }
+        let (sx, sy) = chroma.subsampling();
+        let (cw, ch) = chroma.plane_size(width, height);
+
+        let mut data = Vec::with_capacity(self.header.frame_size());
+        data.extend(image.pixels.iter().map(|&p| conversion.from_rgb(p).0));
+        let mut v_plane = Vec::with_capacity(cw * ch);
+        for cy in 0..ch
+        {
+            for cx in 0..cw
+            {
+                // average the block of pixels that share one chroma sample
+                let (mut u_sum, mut v_sum, mut count) = (0, 0, 0);
+                for y in cy * sy..((cy + 1) * sy).min(height)
+                {
+                    for x in cx * sx..((cx + 1) * sx).min(width)
+                    {
+                        let (_, u, v) = conversion.from_rgb(image[(x, y)]);
+                        u_sum += u as u32;
+                        v_sum += v as u32;
+                        count += 1;
+                    }
+                }
+                data.push(((u_sum + count / 2) / count) as u8);
+                v_plane.push(((v_sum + count / 2) / count) as u8);
+            }
+        }
+        data.extend(v_plane);
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&data)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()>
+    {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::pixel::Rgba8;
+
+    /// An image whose colour only changes between 2x2 blocks, so that every chroma subsampling keeps it.
+    fn blocks(width: usize, height: usize, frame: u8) -> Image
+    {
+        let mut image = Image::new(width, height, Rgba8::BLACK);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                let (bx, by) = ((x / 2) as u8, (y / 2) as u8);
+                image[(x, y)] = Rgba8::rgb(bx.wrapping_mul(53).wrapping_add(frame), by.wrapping_mul(91), (bx ^ by).wrapping_mul(37));
+            }
+        }
+        image
+    }
+
+    fn header(width: usize, height: usize, chroma: Chroma, full_range: bool) -> Y4mHeader
+    {
+        Y4mHeader{width, height, frame_rate: (30000, 1001), chroma, full_range}
+    }
+
+    fn write(header: &Y4mHeader, frames: &[Image]) -> Vec<u8>
+    {
+        let mut writer = Y4mWriter::new(Vec::new(), header.clone()).unwrap();
+        for frame in frames
+        {
+            writer.write_frame(frame).unwrap();
+        }
+        writer.flush().unwrap();
+        writer.writer
+    }
+
+    fn largest_difference(a: &Image, b: &Image) -> u8
+    {
+        a.pixels.iter().zip(&b.pixels).flat_map(|(p, q)| [p.r.abs_diff(q.r), p.g.abs_diff(q.g), p.b.abs_diff(q.b)]).max().unwrap()
+    }
+
+    #[test]
+    fn frames_round_trip()
+    {
+        for chroma in [Chroma::C420, Chroma::C422, Chroma::C444]
+        {
+            for (width, height) in [(16, 8), (7, 5), (1, 1), (9, 2)]
+            {
+                for full_range in [false, true]
+                {
+                    let frames = [blocks(width, height, 0), blocks(width, height, 100)];
+                    let stream = write(&header(width, height, chroma, full_range), &frames);
+                    let mut reader = Y4mReader::new(stream.as_slice()).unwrap();
+                    let read = reader.header();
+                    assert_eq!((read.width, read.height, read.frame_rate, read.chroma, read.full_range), (width, height, (30000, 1001), chroma, full_range));
+                    for frame in &frames
+                    {
+                        let decoded = reader.read_frame().unwrap().unwrap();
+                        assert_eq!((decoded.width, decoded.height), (width, height));
+                        let difference = largest_difference(frame, &decoded);
+                        assert!(difference <= 3, "{:?} {}x{} full range {} is off by {}", chroma, width, height, full_range, difference);
+                    }
+                    assert!(reader.read_frame().unwrap().is_none());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn planes_have_the_subsampled_size()
+    {
+        for (chroma, chroma_size) in [(Chroma::C420, 4 * 3), (Chroma::C422, 4 * 5), (Chroma::C444, 7 * 5), (Chroma::Mono, 0)]
+        {
+            let stream = write(&header(7, 5, chroma, false), &[blocks(7, 5, 0)]);
+            let header_length = stream.iter().position(|&b| b == b'\n').unwrap() + 1;
+            assert_eq!(stream.len(), header_length + "FRAME\n".len() + 7 * 5 + 2 * chroma_size, "{:?}", chroma);
+        }
+    }
+
+    #[test]
+    fn colour_range_tag()
+    {
+        let white = Image::new(3, 3, Rgba8::WHITE);
+        let limited = write(&header(3, 3, Chroma::C444, false), std::slice::from_ref(&white));
+        let full = write(&header(3, 3, Chroma::C444, true), std::slice::from_ref(&white));
+        assert!(String::from_utf8_lossy(&full).lines().next().unwrap().ends_with(" XCOLORRANGE=FULL"));
+        assert!(!String::from_utf8_lossy(&limited).contains("XCOLORRANGE"));
+        // white is the top of the studio range, or the top of the byte
+        assert_eq!(limited[limited.len() - 27], 235);
+        assert_eq!(full[full.len() - 27], 255);
+
+        // the tag is read whatever its case, and decides how the samples are scaled
+        let stream = b"YUV4MPEG2 W1 H1 C444 xcolorrange=full\nFRAME\n\xeb\x80\x80";
+        let mut reader = Y4mReader::new(&stream[..]).unwrap();
+        assert!(!reader.header().full_range, "the tag starts with a capital X");
+        assert_eq!(reader.read_frame().unwrap().unwrap().pixels[0], Rgba8::WHITE);
+        let stream = b"YUV4MPEG2 W1 H1 C444 XCOLORRANGE=full\nFRAME\n\xeb\x80\x80";
+        let mut reader = Y4mReader::new(&stream[..]).unwrap();
+        assert!(reader.header().full_range);
+        assert_eq!(reader.read_frame().unwrap().unwrap().pixels[0], Rgba8::rgb(235, 235, 235));
+    }
+
+    fn error(stream: &[u8]) -> String
+    {
+        let result = Y4mReader::new(stream).and_then(|mut reader| reader.read_frame().map(|_| ()));
+        let error = result.expect_err("the stream should be refused");
+        error.to_string()
+    }
+
+    #[test]
+    fn broken_streams_are_refused()
+    {
+        assert_eq!(error(b"YUV4MPEG W2 H2\n"), "not a YUV4MPEG2 stream");
+        assert_eq!(error(b"YUV4MPEG2 H2 C444\n"), "Y4M header is missing the frame size");
+        assert_eq!(error(b"YUV4MPEG2 W2 C444\n"), "Y4M header is missing the frame size");
+        assert_eq!(error(b"YUV4MPEG2 W0 H2\n"), "Y4M header is missing the frame size");
+        assert_eq!(error(b"YUV4MPEG2 Wtwo H2\n"), "invalid Y4M header parameter Wtwo");
+        assert_eq!(error(b"YUV4MPEG2 W2 H2 F30\n"), "invalid Y4M header parameter F30");
+        assert_eq!(error(b"YUV4MPEG2 W2 H2 C411\n"), "unsupported Y4M colour space 411");
+        assert_eq!(error(b"YUV4MPEG2 W1 H1 C444\nFRAMX\n\0\0\0"), "expected a FRAME marker");
+        assert_eq!(error(b"YUV4MPEG2 W1 H1 C444\n\0\0\0"), "expected a FRAME marker");
+        let truncated = Y4mReader::new(&b"YUV4MPEG2 W2 H2 C444\nFRAME\n\0\0\0"[..]).unwrap().read_frame();
+        assert_eq!(truncated.err().map(|e| e.kind()), Some(std::io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn frames_of_the_wrong_size_are_refused()
+    {
+        let mut writer = Y4mWriter::new(Vec::new(), header(4, 4, Chroma::C420, false)).unwrap();
+        let error = writer.write_frame(&blocks(4, 3, 0)).unwrap_err();
+        assert_eq!(error.to_string(), "frame is 4x3, but the stream is 4x4");
+    }
+
+    #[test]
+    fn frame_rates_become_fractions()
+    {
+        assert_eq!(frame_rate(24.0), (24, 1));
+        assert_eq!(frame_rate(1.0 / (1.0 / 60.0)), (60, 1));
+        assert_eq!(frame_rate(29.97), (30000, 1001));
+        assert_eq!(frame_rate(23.976), (24000, 1001));
+        assert_eq!(frame_rate(12.5), (25, 2));
+        assert_eq!(frame_rate(0.5), (1, 2));
+        assert_eq!(frame_rate(7.123), (7123, 1000));
+    }
+
+    #[test]
+    fn header_tags_may_be_any_character()
+    {
+        let stream = "YUV4MPEG2 W2 H2 \u{e9}t\u{e9} \u{1F3A5} C444\nFRAME\n\0\0\0\0\0\0\0\0\0\0\0\0";
+        let reader = Y4mReader::new(stream.as_bytes()).unwrap();
+        assert_eq!((reader.header().width, reader.header().height, reader.header().chroma), (2, 2, Chroma::C444));
+    }
+}